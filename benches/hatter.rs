@@ -0,0 +1,330 @@
+//! Benchmarks for scanning, parsing, and rendering - with the
+//! tree-walker, optimized, with the VM, and compiled to Rust. Run
+//! them with:
+//!
+//!     cargo bench
+//!     cargo bench -- render   # just the ones with "render" in their name
+//!
+//! There's no harness, just `std::time`: each benchmark is warmed up,
+//! then run for about a second, and the mean time per iteration is
+//! printed. `cargo test --benches` runs each one once, so they don't
+//! rot, and checks that `benches/baseline.txt` has all of them.
+//!
+//! To catch regressions, save a baseline before changing something,
+//! then compare against it:
+//!
+//!     cargo bench --bench hatter -- --save
+//!     cargo bench --bench hatter -- --compare
+//!     cargo bench --bench hatter -- --compare --threshold=5
+//!
+//! `--compare` prints how each benchmark changed and fails if any got
+//! slower by more than the threshold, 10% by default. The checked in
+//! baseline is from one machine, so save your own before comparing.
+
+use {
+    hatter::{compile, optimize, parse, scan, Code, Env, Result, Stmt},
+    std::{
+        fs,
+        hint::black_box,
+        path::Path,
+        process,
+        time::{Duration, Instant},
+    },
+};
+
+const WARMUP: Duration = Duration::from_millis(200);
+const MEASURE: Duration = Duration::from_secs(1);
+const BASELINE: &str = "benches/baseline.txt";
+
+fn main() {
+    let mut bench = Bench {
+        quick: true,
+        filters: vec![],
+        save: false,
+        compare: false,
+        threshold: 10.0,
+        baseline: load_baseline(),
+        results: vec![],
+    };
+    for arg in std::env::args().skip(1) {
+        match arg.as_ref() {
+            "--bench" => bench.quick = false,
+            "--save" => bench.save = true,
+            "--compare" => bench.compare = true,
+            _ if arg.starts_with("--threshold=") => {
+                bench.threshold = arg["--threshold=".len()..]
+                    .parse()
+                    .expect("--threshold should be a percentage, like 10")
+            }
+            _ if arg.starts_with('-') => {}
+            _ => bench.filters.push(arg),
+        }
+    }
+
+    let mut examples = vec![];
+    hat_files(Path::new("examples"), &mut examples);
+    // the ones showing off errors don't render
+    examples.retain(|source| hatter::render(source).is_ok());
+    let tokens = examples
+        .iter()
+        .map(|source| scan(source).unwrap())
+        .collect::<Vec<_>>();
+    let stmts = examples
+        .iter()
+        .map(|source| compile(source).unwrap())
+        .collect::<Vec<_>>();
+    let optimized = stmts.iter().cloned().map(optimize).collect::<Vec<_>>();
+    let codes = stmts.iter().cloned().map(Code::new).collect::<Vec<_>>();
+
+    bench.run("scan/examples", || {
+        examples
+            .iter()
+            .map(|s| scan(s).unwrap())
+            .collect::<Vec<_>>()
+    });
+    bench.run("parse/examples", || {
+        tokens.iter().map(|t| parse(t).unwrap()).collect::<Vec<_>>()
+    });
+    let blocks = many_blocks(2_000);
+    let block_tokens = scan(&blocks).unwrap();
+    bench.run("parse/many-blocks", || parse(&block_tokens).unwrap());
+    bench.run("render/examples", || {
+        examples
+            .iter()
+            .map(|source| Env::new().render(source).unwrap())
+            .collect::<Vec<_>>()
+    });
+    bench.run("render/examples-compiled", || render_all(&stmts));
+    bench.run("render/examples-optimized", || render_all(&optimized));
+    bench.run("render/examples-vm", || {
+        codes
+            .iter()
+            .map(|code| Env::new().render_code(code).unwrap())
+            .collect::<Vec<_>>()
+    });
+
+    // tests/aot/page.hat, compiled to Rust
+    let page: fn(&mut Env) -> Result<String> = include!("../tests/aot/page.hat.rs");
+    let page_stmts = compile(fs::read_to_string("tests/aot/page.hat").unwrap()).unwrap();
+    bench.run("render/page-compiled", || {
+        Env::new().render_stmts(&page_stmts).unwrap()
+    });
+    bench.run("render/page-aot", || page(&mut Env::new()).unwrap());
+
+    let nested = deep_nesting(200);
+    bench.run("render/deep-nesting", || {
+        Env::new().render(&nested).unwrap()
+    });
+    let loops = large_loops(10_000);
+    bench.run("render/large-loops", || Env::new().render(&loops).unwrap());
+    let interpolation = interpolation(1_000);
+    bench.run("render/interpolation", || {
+        Env::new().render(&interpolation).unwrap()
+    });
+
+    bench.finish();
+}
+
+/// Render compiled templates with the tree-walker.
+fn render_all(templates: &[Vec<Stmt>]) -> Vec<String> {
+    templates
+        .iter()
+        .map(|stmts| Env::new().render_stmts(stmts).unwrap())
+        .collect()
+}
+
+/// Runs and times benchmarks.
+struct Bench {
+    quick: bool,                      // just run each one once?
+    filters: Vec<String>,             // only run these
+    save: bool,                       // write the results to `BASELINE`?
+    compare: bool,                    // compare the results to `BASELINE`?
+    threshold: f64,                   // % slower that's a regression
+    baseline: Vec<(String, u128)>,    // name, ns per iter
+    results: Vec<(String, Duration)>, // what we've run
+}
+
+impl Bench {
+    fn run<T, F: FnMut() -> T>(&mut self, name: &str, mut f: F) {
+        if !self.filters.is_empty() && !self.filters.iter().any(|s| name.contains(s.as_str())) {
+            return;
+        }
+        if self.quick {
+            black_box(f());
+            if !self.baseline.iter().any(|(n, _)| n == name) {
+                panic!(
+                    "{} isn't in {}. Run `cargo bench --bench hatter -- --save`.",
+                    name, BASELINE
+                );
+            }
+            println!("{} ... ok", name);
+            return;
+        }
+
+        // guess how many iterations fit in `MEASURE`
+        let start = Instant::now();
+        let mut iters = 0;
+        while start.elapsed() < WARMUP {
+            black_box(f());
+            iters += 1;
+        }
+        let each = start.elapsed() / iters;
+        let iters = (MEASURE.as_nanos() / each.as_nanos().max(1)).max(1) as u32;
+
+        let start = Instant::now();
+        for _ in 0..iters {
+            black_box(f());
+        }
+        let each = start.elapsed() / iters;
+        let change = match self.change(name, each) {
+            Some(pct) if self.compare => format!("  {:+.1}%", pct),
+            None if self.compare => "  (no baseline)".to_string(),
+            _ => String::new(),
+        };
+        println!(
+            "{:28} {:>12} / iter  ({} iters){}",
+            name,
+            human(each),
+            iters,
+            change
+        );
+        self.results.push((name.to_string(), each));
+    }
+
+    /// How much slower `name` is than the baseline, in percent.
+    fn change(&self, name: &str, each: Duration) -> Option<f64> {
+        let (_, base) = self.baseline.iter().find(|(n, _)| n == name)?;
+        Some((each.as_nanos() as f64 / (*base).max(1) as f64 - 1.0) * 100.0)
+    }
+
+    /// Save or compare the results, once everything has run.
+    fn finish(&mut self) {
+        if self.quick {
+            return;
+        }
+        if self.compare {
+            let slower = self
+                .results
+                .iter()
+                .filter_map(|(name, each)| Some((name, self.change(name, *each)?)))
+                .filter(|(_, pct)| *pct > self.threshold)
+                .collect::<Vec<_>>();
+            if !slower.is_empty() {
+                println!(
+                    "\nslower than {} by more than {}%:",
+                    BASELINE, self.threshold
+                );
+                for (name, pct) in slower {
+                    println!("  {:28} {:+.1}%", name, pct);
+                }
+                process::exit(1);
+            }
+        }
+        if self.save {
+            for (name, each) in &self.results {
+                match self.baseline.iter_mut().find(|(n, _)| n == name) {
+                    Some((_, base)) => *base = each.as_nanos(),
+                    None => self.baseline.push((name.clone(), each.as_nanos())),
+                }
+            }
+            let mut out =
+                String::from("# ns per iter. update with `cargo bench --bench hatter -- --save`\n");
+            for (name, ns) in &self.baseline {
+                out.push_str(&format!("{} {}\n", name, ns));
+            }
+            fs::write(BASELINE, out).unwrap();
+            println!("\nsaved {}", BASELINE);
+        }
+    }
+}
+
+/// The saved results in `BASELINE`, if there are any.
+fn load_baseline() -> Vec<(String, u128)> {
+    let Ok(text) = fs::read_to_string(BASELINE) else {
+        return vec![];
+    };
+    text.lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let (name, ns) = line.split_once(' ')?;
+            Some((name.to_string(), ns.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+/// A duration with sensible units.
+fn human(d: Duration) -> String {
+    let ns = d.as_nanos() as f64;
+    if ns < 1e3 {
+        format!("{:.0}ns", ns)
+    } else if ns < 1e6 {
+        format!("{:.2}µs", ns / 1e3)
+    } else if ns < 1e9 {
+        format!("{:.2}ms", ns / 1e6)
+    } else {
+        format!("{:.2}s", ns / 1e9)
+    }
+}
+
+fn hat_files(dir: &Path, files: &mut Vec<String>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            hat_files(&path, files);
+        } else if path.extension().is_some_and(|e| e == "hat") {
+            files.push(fs::read_to_string(&path).unwrap());
+        }
+    }
+}
+
+/// `depth` <div>s, each inside the last.
+fn deep_nesting(depth: usize) -> String {
+    let mut source = String::new();
+    for i in 0..depth {
+        source.push_str(&format!(
+            "{}<div.level-{} data-depth={}>\n",
+            "  ".repeat(i),
+            i,
+            i
+        ));
+    }
+    source.push_str(&"  ".repeat(depth));
+    source.push_str("deep down\n");
+    source
+}
+
+/// `n` short `if` blocks, one after another.
+fn many_blocks(n: usize) -> String {
+    "if true\n  <p> 1\nelse\n  <p> 2\n".repeat(n)
+}
+
+/// A loop doing math `n` times and one printing `n / 10` tags.
+fn large_loops(n: usize) -> String {
+    format!(
+        "total := 0
+for i in 0..{}
+  if i % 2 == 0
+    total += i
+  else
+    total -= 1
+<ul>
+  for i in 0..{}
+    <li.item data-id=i> i
+<p> total
+",
+        n,
+        n / 10
+    )
+}
+
+/// `n` lines, each a string with a bunch of interpolated bits.
+fn interpolation(n: usize) -> String {
+    format!(
+        r#"name := "world"
+items := ["one", "two", "three"]
+for i in 0..{}
+  <p> "Hello, {{name}}! Line {{i}} of {}, {{items[i % 3]}} {{to-uppercase(name)}} {{i * 2}}."
+"#,
+        n, n
+    )
+}
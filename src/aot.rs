@@ -0,0 +1,609 @@
+//! Compile templates to Rust ahead of time, so they're checked when
+//! your crate is built and don't need to be parsed at runtime.
+//!
+//! Call `hatter::aot::build()` from `build.rs` with your templates
+//! directory, `include_templates!()` once at the root of your crate,
+//! then `include_template!()` them:
+//!
+//! ```ignore
+//! // build.rs
+//! fn main() {
+//!     hatter::aot::build("templates").unwrap();
+//! }
+//!
+//! // src/main.rs
+//! hatter::include_templates!();
+//!
+//! let page = hatter::include_template!("page.hat");
+//! let html = page(&mut hatter::Env::new())?;
+//! ```
+//!
+//! Tags with literal names and attributes are written out as string
+//! literals, and loops, `if`s, variables, and calls to native
+//! functions become plain Rust. Everything else - calls to Hatter
+//! functions, special forms like `&&`, custom tags, layouts - is
+//! handed to the tree-walker, so the output is the same as
+//! `Env::render()`'s.
+//!
+//! Like `Template`, a `Compiler` can run templates through
+//! `optimize()` first with `set_optimize(true)`, so constant
+//! expressions are folded and whole static tags become one literal.
+//! Builtin operators like `+` then can't be replaced with
+//! `Env::set()`.
+
+use {
+    crate::{
+        compile, eval::env::aot::static_html, line_and_col, optimize, Result, Span, Stmt, Symbol,
+        Tag, Value,
+    },
+    std::{collections::HashMap, env, fs, path::Path},
+};
+
+/// What compiled templates use at runtime.
+#[doc(hidden)]
+pub mod rt {
+    pub use crate::eval::env::{aot::*, Flow};
+}
+
+/// The first line of generated code.
+const HEADER: &str = "// compiled by hatter. don't edit!\n";
+
+/// Define the templates compiled by `hatter::aot::build()`. Call it
+/// once, at the root of your crate, so every `include_template!()`
+/// shares them.
+#[macro_export]
+macro_rules! include_templates {
+    () => {
+        #[doc(hidden)]
+        #[allow(unused, clippy::all)]
+        mod hatter_templates {
+            include!(concat!(env!("OUT_DIR"), "/hatter/templates.rs"));
+        }
+    };
+}
+
+/// Include a template compiled by `hatter::aot::build()`, as a
+/// `fn(&mut Env) -> Result<String>`. Errors in the template are
+/// compile errors. Needs `include_templates!()`.
+#[macro_export]
+macro_rules! include_template {
+    ($path:literal) => {
+        include!(concat!(env!("OUT_DIR"), "/hatter/", $path, ".rs"))
+    };
+}
+
+/// Compile every `.hat` file in `dir` and its subdirectories for
+/// `include_template!()`, without optimizing them. Meant to be called
+/// from `build.rs`.
+pub fn build<P: AsRef<Path>>(dir: P) -> Result<()> {
+    Compiler::new().build(dir)
+}
+
+/// Compile a template to a Rust expression: a function that renders
+/// it, like `Env::render()`. It isn't optimized.
+pub fn to_rust(source: &str) -> Result<String> {
+    Compiler::new().to_rust(source)
+}
+
+/// Compiles templates to Rust. `build()` and `to_rust()` use one
+/// with the default settings.
+#[derive(Debug, Default, Clone)]
+pub struct Compiler {
+    optimize: bool,
+}
+
+impl Compiler {
+    /// A Compiler with the default settings.
+    pub fn new() -> Compiler {
+        Compiler::default()
+    }
+
+    /// Whether to run templates through `optimize()` before turning
+    /// them into Rust. Off by default, like `Template::set_optimize()`.
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+    }
+
+    /// Compile every `.hat` file in `dir` and its subdirectories for
+    /// `include_template!()`. Each one becomes a function in
+    /// `templates.rs`, for `include_templates!()`, and
+    /// `include_template!()` includes a file naming it.
+    pub fn build<P: AsRef<Path>>(&self, dir: P) -> Result<()> {
+        let root = dir.as_ref();
+        let out = match env::var_os("OUT_DIR") {
+            Some(out) => Path::new(&out).join("hatter"),
+            None => return error!("OUT_DIR isn't set. Is this a build script?"),
+        };
+        println!("cargo:rerun-if-changed={}", root.display());
+
+        let mut paths = vec![];
+        let mut dirs = vec![root.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else if path.extension().filter(|ext| *ext == "hat").is_some() {
+                    paths.push(path);
+                }
+            }
+        }
+        paths.sort();
+
+        let mut templates = String::from(HEADER);
+        for (i, path) in paths.iter().enumerate() {
+            let name = path.strip_prefix(root).unwrap_or(path);
+            let source = fs::read_to_string(path)?;
+            let code = match self.to_rust(&source) {
+                Ok(code) => {
+                    templates.push_str(&format!(
+                        "\n/// {}\npub fn t{}(env: &mut ::hatter::Env) -> ::hatter::Result<::std::string::String> {{\nlet render: fn(&mut ::hatter::Env) -> ::hatter::Result<::std::string::String> = {};\nrender(env)\n}}\n",
+                        name.display(),
+                        i,
+                        code.trim_start_matches(HEADER)
+                    ));
+                    format!("crate::hatter_templates::t{}\n", i)
+                }
+                Err(err) => {
+                    let (line, col) = line_and_col(&source, err.pos);
+                    let msg = format!("{}:{}:{}: {}", path.display(), line, col, err.details);
+                    format!("compile_error!({:?})\n", msg)
+                }
+            };
+            let dest = out.join(format!("{}.rs", name.display()));
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(dest, code)?;
+        }
+        fs::create_dir_all(&out)?;
+        fs::write(out.join("templates.rs"), indent(&templates))?;
+        Ok(())
+    }
+
+    /// Compile a template to a Rust expression: a function that
+    /// renders it, like `Env::render()`.
+    pub fn to_rust(&self, source: &str) -> Result<String> {
+        let stmts = compile(source)?;
+        let stmts = if self.optimize {
+            optimize(stmts)
+        } else {
+            stmts
+        };
+        let mut gen = Gen::default();
+
+        // layouts are rendered by the tree-walker
+        let body = if stmts.iter().any(|s| matches!(s, Stmt::Extends(..))) {
+            for stmt in &stmts {
+                let code = gen.build(stmt);
+                gen.node(code);
+            }
+            "env.render_stmts(p.stmts())".to_string()
+        } else {
+            let head = matches!(stmts.first(), Some(Stmt::Tag(tag))
+            if matches!(&*tag.tag, Stmt::Value(Value::String(name)) if name == "head"));
+            format!(
+                "rt::render(env, {}, |env: &mut Env| -> Flow<()> {{\n{}Ok(())\n}})",
+                head,
+                gen.block(&stmts, true)
+            )
+        };
+        if let Some(val) = gen.unbuildable {
+            return error!("can't compile {:?} to Rust", val);
+        }
+
+        let nodes = gen
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| format!("{} => {},\n", i, node))
+            .collect::<String>();
+        let code = format!(
+            "{}{{
+const SYMS: &[&str] = &{:?};
+static PARTS: ::std::sync::OnceLock<::hatter::aot::rt::Parts> = ::std::sync::OnceLock::new();
+
+#[allow(unused, clippy::all)]
+fn build(p: &::hatter::aot::rt::Parts, i: usize) -> ::hatter::Stmt {{
+use {{
+::hatter::{{Prerendered, Span, Stmt, Tag, Value}},
+::std::sync::Arc,
+}};
+match i {{
+{}_ => Stmt::None,
+}}
+}}
+
+#[allow(unused, clippy::all)]
+fn render(env: &mut ::hatter::Env) -> ::hatter::Result<::std::string::String> {{
+use ::hatter::{{aot::rt::{{self, Flow}}, Env, Span, Value}};
+let p = PARTS.get_or_init(|| rt::Parts::new(SYMS, {}, build));
+{}
+}}
+
+render
+}}
+",
+            HEADER,
+            gen.syms,
+            nodes,
+            gen.nodes.len(),
+            body,
+        );
+        Ok(indent(&code))
+    }
+}
+
+/// Turns `Stmt`s into Rust.
+#[derive(Default)]
+struct Gen {
+    syms: Vec<String>, // names to intern
+    sym_idx: HashMap<String, usize>,
+    nodes: Vec<String>,         // code building `Stmt`s for the tree-walker
+    unbuildable: Option<Value>, // a literal `build()` can't write
+}
+
+impl Gen {
+    /// `Symbol` for a name.
+    fn sym(&mut self, name: &str) -> String {
+        let next = self.syms.len();
+        let idx = *self.sym_idx.entry(name.to_string()).or_insert(next);
+        if idx == next {
+            self.syms.push(name.to_string());
+        }
+        format!("p.syms[{}]", idx)
+    }
+
+    /// `&Stmt` for a `Stmt` handed to the tree-walker, given the
+    /// code that `build()`s it. It's built the first time it's needed,
+    /// not parsed.
+    fn node(&mut self, code: String) -> String {
+        self.nodes.push(code);
+        format!("p.node({})", self.nodes.len() - 1)
+    }
+
+    /// Statements. In a `printed` block, like a tag's body, what each
+    /// one returns is printed.
+    fn block(&mut self, stmts: &[Stmt], printed: bool) -> String {
+        let mut code = String::new();
+        for stmt in stmts {
+            code.push_str(&self.stmt(stmt, printed));
+        }
+        code
+    }
+
+    /// A `Stmt` as a statement.
+    fn stmt(&mut self, stmt: &Stmt, printed: bool) -> String {
+        match stmt {
+            Stmt::Word(name, span) if printed => {
+                format!("rt::print_word(env, &{}, {})?;\n", self.sym(name), span_of(*span))
+            }
+            Stmt::Assign(name, expr, reassign, span) => format!(
+                "rt::assign(env, &{}, {}, {}, |env: &mut Env| -> Flow<Value> {{ {} }})?;\n",
+                self.sym(name),
+                reassign,
+                span_of(*span),
+                flow(self.expr(expr))
+            ),
+            Stmt::If(conds) => {
+                let mut branches = vec![];
+                for (test, body) in conds {
+                    let test = self.expr(test);
+                    branches.push(format!(
+                        "if {}.to_bool() {{\nrt::scoped(env, |env: &mut Env| -> Flow<()> {{\n{}Ok(())\n}})?;\n}}",
+                        test,
+                        self.block(body, false)
+                    ));
+                }
+                branches.join(" else ") + "\n"
+            }
+            Stmt::For(key, val, iter, body) => format!(
+                "{{\nlet iter = {};\nrt::for_each(env, {}, &{}, iter, |env: &mut Env| -> Flow<()> {{\n{}Ok(())\n}})?;\n}}\n",
+                self.expr(iter),
+                key.as_ref()
+                    .map(|k| format!("Some(&{})", self.sym(k)))
+                    .unwrap_or_else(|| "None".into()),
+                self.sym(val),
+                self.block(body, false)
+            ),
+            Stmt::While(test, body) => format!(
+                "rt::while_loop(\nenv,\n|env: &mut Env| -> Flow<Value> {{ {} }},\n|env: &mut Env| -> Flow<()> {{\n{}Ok(())\n}},\n)?;\n",
+                flow(self.expr(test)),
+                self.block(body, false)
+            ),
+            Stmt::Tag(tag) => self.tag(tag),
+            _ if printed => format!("let val = {};\nrt::print(env, val);\n", self.expr(stmt)),
+            _ => format!("{};\n", self.expr(stmt)),
+        }
+    }
+
+    /// A `Stmt` as an expression that evaluates to a `Value`, or
+    /// leaves with `?`.
+    fn expr(&mut self, stmt: &Stmt) -> String {
+        match stmt {
+            Stmt::None => "Value::None".into(),
+            Stmt::Value(val) => self.value(val),
+            Stmt::Word(name, span) => {
+                format!("rt::word(env, &{}, {})?", self.sym(name), span_of(*span))
+            }
+            Stmt::Call(target, args, _)
+                if matches!(**target, Stmt::Word(..))
+                    && !args.iter().any(|a| matches!(a, Stmt::Args(..))) =>
+            {
+                let node = self.build(stmt);
+                let node = self.node(node);
+                let target = self.expr(target);
+                let args = args.iter().map(|arg| self.expr(arg)).collect::<Vec<_>>();
+                format!(
+                    "{{\nlet f = {};\nrt::call(env, f, {}, |env: &mut Env| -> Flow<Vec<Value>> {{\nOk(vec![{}])\n}})?\n}}",
+                    target,
+                    node,
+                    args.join(", ")
+                )
+            }
+            _ => {
+                let node = self.build(stmt);
+                format!("rt::run(env, {})?", self.node(node))
+            }
+        }
+    }
+
+    /// A literal `Value`. The parser and `optimize()` only make
+    /// simple ones, so anything else is noted as unbuildable.
+    fn value(&mut self, val: &Value) -> String {
+        match val {
+            Value::None => "Value::None".into(),
+            Value::Bool(b) => format!("Value::Bool({})", b),
+            Value::Number(n) if n.is_nan() => "Value::Number(f64::NAN)".into(),
+            Value::Number(n) if n.is_infinite() && *n > 0.0 => {
+                "Value::Number(f64::INFINITY)".into()
+            }
+            Value::Number(n) if n.is_infinite() => "Value::Number(f64::NEG_INFINITY)".into(),
+            Value::Number(n) => format!("Value::Number({:?})", n),
+            Value::String(s) => format!("Value::String({}.clone())", self.sym(s)),
+            Value::Html(s) => format!("Value::Html({}.clone())", self.sym(s)),
+            _ => {
+                self.unbuildable.get_or_insert_with(|| val.clone());
+                "Value::None".into()
+            }
+        }
+    }
+
+    /// Rust that builds `stmt`.
+    fn build(&mut self, stmt: &Stmt) -> String {
+        match stmt {
+            Stmt::None => "Stmt::None".into(),
+            Stmt::Value(val) => format!("Stmt::Value({})", self.value(val)),
+            Stmt::Word(name, span) => {
+                format!("Stmt::Word({}.clone(), {})", self.sym(name), span_of(*span))
+            }
+            Stmt::List(items) => format!("Stmt::List({})", self.build_block(items)),
+            Stmt::Map(pairs) => format!("Stmt::Map({})", self.build_pairs(pairs)),
+            Stmt::Args(pairs) => format!("Stmt::Args({})", self.build_pairs(pairs)),
+            Stmt::Call(target, args, span) => format!(
+                "Stmt::Call(\nBox::new({}),\n{},\n{},\n)",
+                self.build(target),
+                self.build_block(args),
+                span_of(*span)
+            ),
+            Stmt::Return(expr, span) => {
+                format!(
+                    "Stmt::Return(Box::new({}), {})",
+                    self.build(expr),
+                    span_of(*span)
+                )
+            }
+            Stmt::If(conds) => {
+                let conds = conds
+                    .iter()
+                    .map(|(test, body)| {
+                        format!("({}, {}),\n", self.build(test), self.build_block(body))
+                    })
+                    .collect::<String>();
+                format!("Stmt::If(vec![\n{}])", conds)
+            }
+            Stmt::For(key, val, iter, body) => format!(
+                "Stmt::For(\n{},\n{}.clone(),\nBox::new({}),\n{},\n)",
+                key.as_ref()
+                    .map(|k| format!("Some({}.clone())", self.sym(k)))
+                    .unwrap_or_else(|| "None".into()),
+                self.sym(val),
+                self.build(iter),
+                self.build_block(body)
+            ),
+            Stmt::While(test, body) => format!(
+                "Stmt::While(Box::new({}), {})",
+                self.build(test),
+                self.build_block(body)
+            ),
+            Stmt::Assign(name, val, reassign, span) => format!(
+                "Stmt::Assign(\n{}.clone(),\nBox::new({}),\n{},\n{},\n)",
+                self.sym(name),
+                self.build(val),
+                reassign,
+                span_of(*span)
+            ),
+            Stmt::Tag(tag) => self.build_tag(tag),
+            Stmt::Fn(params, body) => format!(
+                "Stmt::Fn(vec![{}], {})",
+                self.build_syms(params),
+                self.build_block(body)
+            ),
+            Stmt::Block(name, body) => format!(
+                "Stmt::Block({}.clone(), {})",
+                self.sym(name),
+                self.build_block(body)
+            ),
+            Stmt::Extends(expr) => format!("Stmt::Extends(Box::new({}))", self.build(expr)),
+        }
+    }
+
+    /// Rust that builds a `Vec<Stmt>`.
+    fn build_block(&mut self, stmts: &[Stmt]) -> String {
+        if stmts.is_empty() {
+            return "vec![]".into();
+        }
+        let stmts = stmts
+            .iter()
+            .map(|stmt| format!("{},\n", self.build(stmt)))
+            .collect::<String>();
+        format!("vec![\n{}]", stmts)
+    }
+
+    /// Rust that builds a map's or keyword args' pairs.
+    fn build_pairs(&mut self, pairs: &[(Symbol, Stmt)]) -> String {
+        let pairs = pairs
+            .iter()
+            .map(|(name, stmt)| format!("({}.clone(), {}),\n", self.sym(name), self.build(stmt)))
+            .collect::<String>();
+        format!("vec![\n{}]", pairs)
+    }
+
+    /// Rust for some `Symbol`s, separated by commas.
+    fn build_syms(&mut self, names: &[Symbol]) -> String {
+        names
+            .iter()
+            .map(|name| format!("{}.clone()", self.sym(name)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Rust that builds a `Stmt::Tag`, pre-rendered HTML and all.
+    fn build_tag(&mut self, tag: &Tag) -> String {
+        let mut code = format!(
+            "Stmt::Tag({{\nlet mut tag = Tag::new({});\n",
+            self.build(&tag.tag)
+        );
+        if tag.id.is_some() {
+            code.push_str(&format!("tag.id = Box::new({});\n", self.build(&tag.id)));
+        }
+        if !tag.classes.is_empty() {
+            code.push_str(&format!(
+                "tag.classes = {};\n",
+                self.build_block(&tag.classes)
+            ));
+        }
+        if !tag.attrs.is_empty() {
+            let attrs = tag
+                .attrs
+                .iter()
+                .map(|(name, val)| format!("({}, {}),\n", self.build(name), self.build(val)))
+                .collect::<String>();
+            code.push_str(&format!("tag.attrs = vec![\n{}];\n", attrs));
+        }
+        if !tag.body.is_empty() {
+            code.push_str(&format!("tag.body = {};\n", self.build_block(&tag.body)));
+        }
+        if tag.closed {
+            code.push_str("tag.closed = true;\n");
+        }
+        code.push_str(&format!("tag.span = {};\n", span_of(tag.span)));
+        if let Some(html) = &tag.html {
+            code.push_str(&format!(
+                "tag.html = Some(Arc::new(Prerendered {{\nhtml: {:?}.into(),\nunset: vec![{}],\n}}));\n",
+                html.html,
+                self.build_syms(&html.unset)
+            ));
+        }
+        code.push_str("tag\n})");
+        code
+    }
+
+    /// Print a tag.
+    fn tag(&mut self, tag: &Tag) -> String {
+        let custom = match &*tag.tag {
+            Stmt::Value(Value::String(name)) => Some(self.sym(&format!("<{}>", name))),
+            _ => None,
+        };
+        if let Some(html) = &tag.html {
+            let unset = html
+                .unset
+                .iter()
+                .map(|name| format!("&{}", self.sym(name)))
+                .collect::<Vec<_>>();
+            let node = self.build_tag(tag);
+            let node = self.node(node);
+            return format!(
+                "rt::html(env, &[{}], {:?}, {})?;\n",
+                unset.join(", "),
+                html.html,
+                node
+            );
+        }
+        if let (Some(custom), Some((open, close))) = (&custom, static_html(tag)) {
+            let node = self.build_tag(tag);
+            let node = self.node(node);
+            return format!(
+                "rt::static_tag(env, &{}, {}, {:?}, {:?}, |env: &mut Env| -> Flow<()> {{\n{}Ok(())\n}})?;\n",
+                custom,
+                node,
+                open,
+                close,
+                self.block(&tag.body, true)
+            );
+        }
+
+        let closure =
+            |code: String| format!("&|env: &mut Env| -> Flow<Value> {{ {} }}", flow(code));
+        let name = self.expr(&tag.tag);
+        let id = if tag.id.is_none() {
+            "None".to_string()
+        } else {
+            format!("Some({})", closure(self.expr(&tag.id)))
+        };
+        let classes = tag
+            .classes
+            .iter()
+            .map(|class| closure(self.expr(class)))
+            .collect::<Vec<_>>();
+        let attrs = tag
+            .attrs
+            .iter()
+            .map(|(k, v)| format!("({}, {})", closure(self.expr(k)), closure(self.expr(v))))
+            .collect::<Vec<_>>();
+        format!(
+            "rt::tag(\nenv,\n&rt::Tag {{\nspan: {},\nname: {},\ncustom: {},\nid: {},\nclasses: &[{}],\nattrs: &[{}],\nclosed: {},\nbody: &|env: &mut Env| -> Flow<()> {{\n{}Ok(())\n}},\n}},\n)?;\n",
+            span_of(tag.span),
+            closure(name),
+            custom.map(|c| format!("Some(&{})", c)).unwrap_or_else(|| "None".into()),
+            id,
+            classes.join(", "),
+            attrs.join(", "),
+            tag.closed,
+            self.block(&tag.body, true)
+        )
+    }
+}
+
+/// An expression from `Gen::expr()` as a `Flow<Value>`.
+fn flow(expr: String) -> String {
+    match expr.strip_suffix('?') {
+        Some(call) if call.starts_with("rt::") && !call.contains('\n') => call.to_string(),
+        _ => format!("Ok({})", expr),
+    }
+}
+
+fn span_of(span: Span) -> String {
+    format!("Span::new({}, {})", span.pos, span.len)
+}
+
+/// Indent generated code by its braces, so it can be read.
+fn indent(code: &str) -> String {
+    let mut out = String::new();
+    let mut depth = 0usize;
+    for line in code.lines() {
+        let line = line.trim();
+        if line.starts_with(['}', ')', ']']) {
+            depth = depth.saturating_sub(1);
+        }
+        if !line.is_empty() {
+            out.push_str(&"    ".repeat(depth));
+        }
+        out.push_str(line);
+        out.push('\n');
+        if line.ends_with(['{', '(', '[']) {
+            depth += 1;
+        }
+    }
+    out
+}
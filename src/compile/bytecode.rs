@@ -0,0 +1,552 @@
+//! Bytecode for the VM in `eval::env::vm`. A `Unit` is a template
+//! or function body lowered from `Stmt`s into flat blocks of `Op`s.
+//! Local variables are resolved to numbered slots when that's safe,
+//! so they don't have to be found by name at runtime.
+
+use {
+    crate::{
+        compile::resolve::{Binding, Resolver},
+        Lambda, Prerendered, Span, Stmt, Symbol, Tag, Value,
+    },
+    std::{collections::HashMap, fmt, sync::Arc, sync::OnceLock},
+};
+
+/// No parent in `Unit::chains`.
+pub(crate) const NO_CHAIN: u32 = u32::MAX;
+
+/// A template compiled to bytecode, ready to be run by
+/// `Env::render_code()` or `Env::eval_code()`. It's compiled lazily
+/// the first time it's run each way, then shared.
+pub struct Code {
+    stmts: Vec<Stmt>,
+    page: OnceLock<Arc<Unit>>,
+    script: OnceLock<Arc<Unit>>,
+}
+
+impl fmt::Debug for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Code").field("stmts", &self.stmts).finish()
+    }
+}
+
+impl From<Vec<Stmt>> for Code {
+    fn from(stmts: Vec<Stmt>) -> Code {
+        Code::new(stmts)
+    }
+}
+
+impl Code {
+    /// Create Code from compiled statements.
+    pub fn new(stmts: Vec<Stmt>) -> Code {
+        Code {
+            stmts,
+            page: OnceLock::new(),
+            script: OnceLock::new(),
+        }
+    }
+
+    /// The statements this code was made from.
+    pub fn stmts(&self) -> &[Stmt] {
+        &self.stmts
+    }
+
+    /// Bytecode for rendering, which prints every statement.
+    pub(crate) fn page(&self) -> &Arc<Unit> {
+        self.page.get_or_init(|| {
+            arc!(Lowerer::lower(
+                &self.stmts,
+                Mode::Printed,
+                &[],
+                Resolver::new(&self.stmts),
+                vec![]
+            ))
+        })
+    }
+
+    /// Bytecode for evaluating, which returns the last statement.
+    pub(crate) fn script(&self) -> &Arc<Unit> {
+        self.script.get_or_init(|| {
+            arc!(Lowerer::lower(
+                &self.stmts,
+                Mode::Want,
+                &[],
+                Resolver::new(&self.stmts),
+                vec![]
+            ))
+        })
+    }
+}
+
+/// Lower a function body. Custom tags are printed and get their
+/// rendered body as an extra `children` param.
+pub(crate) fn function(lambda: &Lambda, tag: bool) -> Unit {
+    let mut params = lambda.params.clone();
+    if tag {
+        params.push("children".into());
+    }
+    let mode = if tag { Mode::Printed } else { Mode::Want };
+    let (res, bindings) = Resolver::function(&params, &lambda.body);
+    Lowerer::lower(&lambda.body, mode, &params, res, bindings)
+}
+
+/// Where a variable lives.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Target {
+    Slot(u32), // index into the frame's slots
+    Name(u32), // index into `Unit::names`, found in the Env's scopes
+}
+
+/// One instruction. Most work on the frame's value stack.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Op {
+    Const(u32),    // push a constant
+    Load(u32),     // push a slot
+    LoadName(u32), // push a variable, by name
+    Word(Target),  // push a variable, or print the word if it isn't one
+    List(u32),     // pop N values into a list
+    Map(u32),      // pop values into a map with these keys
+    Call(u32),     // pop a function and call it
+    Fn(u32),       // push a closure over the current scope
+    Tag(u32),      // push a tag rendered to HTML
+    PrintTag(u32), // print a tag, push None
+    Stmt(u32),     // run a statement with the tree-walker
+    Check(u32),    // make sure an assignment is allowed
+    Store(u32),    // pop a value into a variable, push None
+    If(u32),       // run an if statement, push None
+    For(u32),      // run a for loop, push None
+    While(u32),    // run a while loop, push None
+    Print,         // print the top value, unless it's None
+    Echo,          // print the top value
+    Flush,         // write buffered output, if streaming
+    Pop,           // throw away the top value
+    Return,        // pop a value and return it
+    Break,         // leave the loop
+    Continue,      // next iteration
+}
+
+/// A list of ops. Running it leaves one value on the stack: the
+/// value of its last statement.
+#[derive(Debug, Default)]
+pub(crate) struct Block {
+    pub ops: Vec<Op>,
+    pub chains: Vec<u32>,  // `Unit::chains` entry of each op
+    pub scope: bool,       // push a scope while running?
+    pub clear: (u32, u32), // slots to clear after running
+}
+
+/// `x := val` or `x = val`.
+#[derive(Debug)]
+pub(crate) struct Assign {
+    pub name: Symbol,
+    pub target: Target,
+    pub reassign: bool,
+}
+
+/// A function call, with each argument in its own block.
+#[derive(Debug)]
+pub(crate) struct Call {
+    pub name: Symbol,                  // for stack traces
+    pub display: String,               // for "can't find fn" errors
+    pub args: Vec<u32>,                // blocks
+    pub keywords: Option<Vec<Symbol>>, // names of keyword args
+    pub stmts: Vec<Stmt>,              // args for Special functions
+    pub span: Span,
+}
+
+/// A `for` or `while` loop.
+#[derive(Debug)]
+pub(crate) struct Loop {
+    pub key: Option<Target>,
+    pub val: Option<Target>, // None for `while`
+    pub test: u32,           // block: thing to loop over, or `while` test
+    pub body: u32,           // block
+    pub scope: bool,         // push a scope for the loop?
+    pub clear: (u32, u32),   // slots to clear after each iteration
+}
+
+/// A <tag>, with each dynamic part in its own block.
+#[derive(Debug)]
+pub(crate) struct TagCode {
+    pub name: u32,
+    pub custom: Option<Symbol>, // "<name>", if the name is a literal
+    pub id: Option<u32>,
+    pub classes: Vec<u32>,
+    pub attrs: Vec<(u32, u32)>,
+    pub body: u32,
+    pub closed: bool,
+    pub span: Span,
+    pub declared: (u32, u32), // slots set in the body
+    pub html: Option<Arc<Prerendered>>,
+}
+
+/// Bytecode for a template or function.
+#[derive(Debug, Default)]
+pub(crate) struct Unit {
+    pub body: u32,
+    pub blocks: Vec<Block>,
+    pub consts: Vec<Value>,
+    pub names: Vec<Symbol>,
+    pub slots: Vec<Symbol>, // name of each slot
+    pub params: Vec<Target>,
+    pub chains: Vec<(Span, u32)>, // statement spans, and their parent
+    pub assigns: Vec<Assign>,
+    pub calls: Vec<Call>,
+    pub maps: Vec<Vec<Symbol>>,
+    pub ifs: Vec<Vec<(u32, u32)>>, // test and body blocks
+    pub loops: Vec<Loop>,
+    pub tags: Vec<TagCode>,
+    pub lambdas: Vec<Arc<Lambda>>,
+    pub stmts: Vec<Stmt>,
+}
+
+/// How a block's statements are printed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Plain,   // tags are printed
+    Want,    // tags are printed, except the last, which is returned
+    Printed, // everything is printed
+}
+
+/// Turns `Stmt`s into a `Unit`.
+struct Lowerer {
+    unit: Unit,
+    res: Resolver,
+    ops: Vec<Op>,
+    chains: Vec<u32>,
+    chain: u32,
+    consts: HashMap<String, u32>,
+    names: HashMap<Symbol, u32>,
+}
+
+impl Lowerer {
+    /// Lower a template or function body, resolving its variables
+    /// with `res` as we go. `params` are bound to `bindings`.
+    fn lower(
+        stmts: &[Stmt],
+        mode: Mode,
+        params: &[Symbol],
+        res: Resolver,
+        bindings: Vec<Binding>,
+    ) -> Unit {
+        let mut lowerer = Lowerer {
+            unit: Unit::default(),
+            res,
+            ops: vec![],
+            chains: vec![],
+            chain: NO_CHAIN,
+            consts: HashMap::new(),
+            names: HashMap::new(),
+        };
+        lowerer.unit.params = params
+            .iter()
+            .zip(bindings)
+            .map(|(name, binding)| lowerer.target(name, binding))
+            .collect();
+        lowerer.unit.body = lowerer.block(stmts, mode, false);
+        lowerer.unit.slots = lowerer.res.slots().to_vec();
+        lowerer.unit
+    }
+
+    /// Add an op to the current block.
+    fn emit(&mut self, op: Op) {
+        self.ops.push(op);
+        self.chains.push(self.chain);
+    }
+
+    /// Index of a constant.
+    fn constant(&mut self, val: &Value) -> u32 {
+        // only dedupe the simple ones
+        let key = match val {
+            Value::None => Some("n".to_string()),
+            Value::Bool(b) => Some(format!("b{}", b)),
+            Value::Number(n) => Some(format!("f{}", n.to_bits())),
+            Value::String(s) => Some(format!("s{}", s)),
+            _ => None,
+        };
+        if let Some(idx) = key.as_ref().and_then(|k| self.consts.get(k)) {
+            return *idx;
+        }
+        let idx = self.unit.consts.len() as u32;
+        self.unit.consts.push(val.clone());
+        if let Some(key) = key {
+            self.consts.insert(key, idx);
+        }
+        idx
+    }
+
+    /// Index of a name.
+    fn name(&mut self, name: &Symbol) -> u32 {
+        if let Some(idx) = self.names.get(name) {
+            return *idx;
+        }
+        let idx = self.unit.names.len() as u32;
+        self.unit.names.push(name.clone());
+        self.names.insert(name.clone(), idx);
+        idx
+    }
+
+    /// Where the variable `name`, bound to `binding`, lives.
+    fn target(&mut self, name: &Symbol, binding: Binding) -> Target {
+        match binding {
+            Binding::Local(local) => Target::Slot(local.index),
+            Binding::Global => Target::Name(self.name(name)),
+        }
+    }
+
+    /// Lower statements into a new block. A block with its own
+    /// `scope`, like the body of an `if`, forgets the slots declared
+    /// in it when it's done.
+    fn block(&mut self, stmts: &[Stmt], mode: Mode, scope: bool) -> u32 {
+        let ops = std::mem::take(&mut self.ops);
+        let chains = std::mem::take(&mut self.chains);
+        let start = scope.then(|| self.res.open());
+
+        for (i, stmt) in stmts.iter().enumerate() {
+            let last = i == stmts.len() - 1;
+            match (mode, stmt) {
+                (Mode::Printed, Stmt::Word(w, _))
+                    if !matches!(w.to_str(), "break" | "continue") =>
+                {
+                    self.wrap(stmt, |l| {
+                        let target = l.target(w, l.res.find(w));
+                        l.emit(Op::Word(target));
+                    });
+                    self.emit(Op::Print);
+                }
+                (Mode::Printed, Stmt::Tag(tag)) => {
+                    let idx = self.tag(tag);
+                    self.emit(Op::PrintTag(idx));
+                }
+                (Mode::Printed, _) => {
+                    self.expr(stmt);
+                    self.emit(Op::Print);
+                }
+                (Mode::Want, Stmt::Tag(..)) if last => {
+                    self.expr(stmt);
+                    self.emit(Op::Echo);
+                }
+                (_, Stmt::Tag(tag)) => {
+                    let idx = self.tag(tag);
+                    self.emit(Op::PrintTag(idx));
+                }
+                _ => self.expr(stmt),
+            }
+            self.emit(Op::Flush);
+            if !last {
+                self.emit(Op::Pop);
+            }
+        }
+
+        let clear = start.map(|start| self.res.close(start));
+        let block = Block {
+            ops: std::mem::replace(&mut self.ops, ops),
+            chains: std::mem::replace(&mut self.chains, chains),
+            scope: scope && !self.res.slotted(),
+            clear: clear.unwrap_or_default(),
+        };
+        self.unit.blocks.push(block);
+        self.unit.blocks.len() as u32 - 1
+    }
+
+    /// Lower one expression into its own block.
+    fn expr_block(&mut self, stmt: &Stmt) -> u32 {
+        let ops = std::mem::take(&mut self.ops);
+        let chains = std::mem::take(&mut self.chains);
+        self.expr(stmt);
+        let block = Block {
+            ops: std::mem::replace(&mut self.ops, ops),
+            chains: std::mem::replace(&mut self.chains, chains),
+            ..Block::default()
+        };
+        self.unit.blocks.push(block);
+        self.unit.blocks.len() as u32 - 1
+    }
+
+    /// Lower `f` as part of `stmt`, so errors without a position
+    /// get `stmt`'s.
+    fn wrap<F: FnOnce(&mut Lowerer)>(&mut self, stmt: &Stmt, f: F) {
+        self.unit.chains.push((stmt.span(), self.chain));
+        let parent = self.chain;
+        self.chain = self.unit.chains.len() as u32 - 1;
+        f(self);
+        self.chain = parent;
+    }
+
+    /// Lower an expression that leaves its value on the stack.
+    fn expr(&mut self, stmt: &Stmt) {
+        self.wrap(stmt, |l| l.expr_inner(stmt));
+    }
+
+    fn expr_inner(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::None => {
+                let idx = self.constant(&Value::None);
+                self.emit(Op::Const(idx));
+            }
+            Stmt::Value(v) => {
+                let idx = self.constant(v);
+                self.emit(Op::Const(idx));
+            }
+            Stmt::Word(w, _) => match w.to_str() {
+                "break" => self.emit(Op::Break),
+                "continue" => self.emit(Op::Continue),
+                _ => match self.target(w, self.res.find(w)) {
+                    Target::Slot(slot) => self.emit(Op::Load(slot)),
+                    Target::Name(idx) => self.emit(Op::LoadName(idx)),
+                },
+            },
+            Stmt::List(list) => {
+                for item in list {
+                    self.expr(item);
+                }
+                self.emit(Op::List(list.len() as u32));
+            }
+            Stmt::Map(pairs) => {
+                for (_, val) in pairs {
+                    self.expr(val);
+                }
+                self.unit
+                    .maps
+                    .push(pairs.iter().map(|(k, _)| k.clone()).collect());
+                self.emit(Op::Map(self.unit.maps.len() as u32 - 1));
+            }
+            Stmt::Call(target, args, span) => {
+                self.expr(target);
+                let mut keywords = None;
+                let blocks = match &args[..] {
+                    [Stmt::Args(pairs)] => {
+                        keywords = Some(pairs.iter().map(|(k, _)| k.clone()).collect());
+                        pairs.iter().map(|(_, v)| self.expr_block(v)).collect()
+                    }
+                    _ => args.iter().map(|a| self.expr_block(a)).collect(),
+                };
+                self.unit.calls.push(Call {
+                    name: target.to_str().into(),
+                    display: target.to_string(),
+                    args: blocks,
+                    keywords,
+                    stmts: args.clone(),
+                    span: *span,
+                });
+                self.emit(Op::Call(self.unit.calls.len() as u32 - 1));
+            }
+            Stmt::Return(expr, _) => {
+                self.expr(expr);
+                self.emit(Op::Return);
+            }
+            Stmt::If(conds) => {
+                let conds = conds
+                    .iter()
+                    .map(|(test, body)| {
+                        (self.expr_block(test), self.block(body, Mode::Plain, true))
+                    })
+                    .collect();
+                self.unit.ifs.push(conds);
+                self.emit(Op::If(self.unit.ifs.len() as u32 - 1));
+            }
+            Stmt::For(key, val, iter, body) => {
+                let test = self.expr_block(iter);
+                let start = self.res.open();
+                let key = key.as_ref().map(|k| {
+                    let binding = self.res.declare(k);
+                    self.target(k, binding)
+                });
+                let binding = self.res.declare(val);
+                let val = Some(self.target(val, binding));
+                let body = self.block(body, Mode::Plain, false);
+                let clear = self.res.close(start);
+                self.unit.loops.push(Loop {
+                    key,
+                    val,
+                    test,
+                    body,
+                    scope: !self.res.slotted(),
+                    clear,
+                });
+                self.emit(Op::For(self.unit.loops.len() as u32 - 1));
+            }
+            Stmt::While(test, body) => {
+                let start = self.res.open();
+                let test = self.expr_block(test);
+                let body = self.block(body, Mode::Plain, false);
+                let clear = self.res.close(start);
+                self.unit.loops.push(Loop {
+                    key: None,
+                    val: None,
+                    test,
+                    body,
+                    scope: !self.res.slotted(),
+                    clear,
+                });
+                self.emit(Op::While(self.unit.loops.len() as u32 - 1));
+            }
+            Stmt::Assign(name, expr, reassign, _) => {
+                // declared before the value, which the tree-walker
+                // checks first
+                let binding = if *reassign {
+                    self.res.find(name)
+                } else {
+                    self.res.declare(name)
+                };
+                let target = self.target(name, binding);
+                self.unit.assigns.push(Assign {
+                    name: name.clone(),
+                    target,
+                    reassign: *reassign,
+                });
+                let idx = self.unit.assigns.len() as u32 - 1;
+                self.emit(Op::Check(idx));
+                self.expr(expr);
+                self.emit(Op::Store(idx));
+            }
+            Stmt::Tag(tag) => {
+                let idx = self.tag(tag);
+                self.emit(Op::Tag(idx));
+            }
+            Stmt::Fn(params, body) => {
+                let lambda = Lambda::new(params.clone(), body.clone());
+                self.unit.lambdas.push(arc!(lambda));
+                self.emit(Op::Fn(self.unit.lambdas.len() as u32 - 1));
+            }
+            Stmt::Args(..) | Stmt::Block(..) | Stmt::Extends(..) => {
+                self.unit.stmts.push(stmt.clone());
+                self.emit(Op::Stmt(self.unit.stmts.len() as u32 - 1));
+            }
+        }
+    }
+
+    /// Lower the parts of a <tag>. Its body shares our scope.
+    fn tag(&mut self, tag: &Tag) -> u32 {
+        let custom = tag.custom_name().cloned();
+        let name = self.expr_block(&tag.tag);
+        let id = if tag.id.is_some() {
+            Some(self.expr_block(&tag.id))
+        } else {
+            None
+        };
+        let classes = tag.classes.iter().map(|c| self.expr_block(c)).collect();
+        let attrs = tag
+            .attrs
+            .iter()
+            .map(|(k, v)| (self.expr_block(k), self.expr_block(v)))
+            .collect();
+        // the body shares our scope, but custom tags need to know
+        // what it declared to forget it
+        let start = self.res.next();
+        let body = self.block(&tag.body, Mode::Printed, false);
+        let declared = (start, self.res.next());
+        self.unit.tags.push(TagCode {
+            name,
+            custom,
+            id,
+            classes,
+            attrs,
+            body,
+            closed: tag.closed,
+            span: tag.span,
+            declared,
+            html: tag.html.clone(),
+        });
+        self.unit.tags.len() as u32 - 1
+    }
+}
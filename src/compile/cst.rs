@@ -0,0 +1,191 @@
+//! The concrete syntax tree (CST) is a lossless view of source code:
+//! every byte, including whitespace and comments, belongs to exactly
+//! one token in the tree, so tools like the formatter and the
+//! language server can map anything back to where it came from.
+
+use crate::{
+    compile::{lexer::scan_lossless_parts, parser::parse_nodes},
+    Error, Span, Syntax, Token,
+};
+
+/// What part of the language a CST `Node` is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    File,     // the whole source
+    Def,      // def name(args) or def <tag(args)>
+    If,       // if .. else if .. else
+    For,      // for k, v in iter
+    While,    // while test
+    Return,   // return expr
+    Block,    // block name
+    Extends,  // extends "layout.hat"
+    Import,   // import "file.hat"
+    Body,     // indented block or `do stmt`
+    Assign,   // x := 1, x = 2, x += 3, map.x = 4
+    Unary,    // -x, !x
+    Binary,   // a + b, map.key
+    Call,     // f(args)
+    Index,    // list[idx]
+    Args,     // (args) of a call
+    Paren,    // (expr)
+    List,     // [1, 2, 3]
+    Map,      // { key: val }
+    Fn,       // fn(args) body
+    Tag,      // <tag> body </tag>
+    OpenTag,  // <tag.with #some=attributes>
+    CloseTag, // </tag>
+    TagBody,  // everything between open and close tags
+    Text,     // two or more words in a row in a tag body
+}
+
+/// A Node or a Token in the CST.
+#[derive(Debug, Clone)]
+pub enum Element<'s> {
+    Node(Node<'s>),
+    Token(Token<'s>),
+}
+
+impl<'s> Element<'s> {
+    /// Where this element is in the source.
+    pub fn span(&self) -> Span {
+        match self {
+            Element::Node(node) => node.span(),
+            Element::Token(tok) => Span::new(tok.pos, tok.text().len()),
+        }
+    }
+}
+
+/// A Node in the CST. Its children cover `pos..pos + len` in the
+/// source code exactly, with no gaps.
+#[derive(Debug, Clone)]
+pub struct Node<'s> {
+    pub kind: NodeKind,
+    pub pos: usize,
+    pub len: usize,
+    pub children: Vec<Element<'s>>,
+}
+
+/// Parse source code into a CST, returning the root `File` node and
+/// every syntax and parse error. Statements with errors are still in
+/// the tree, as tokens without a node around them.
+pub fn parse_cst(source: &str) -> (Node<'_>, Vec<Error>) {
+    let (tokens, code, mut errors) = scan_lossless_parts(source);
+    let (nodes, parse_errors) = parse_nodes(&code);
+    errors.extend(parse_errors);
+    errors.sort_by_key(|e| e.pos);
+
+    // where each of the parser's tokens is in `tokens`
+    let index = tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, t)| !matches!(t.kind, Syntax::Whitespace | Syntax::Comment | Syntax::None))
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+
+    // outer nodes first, and the parser records them last
+    let mut nodes = nodes
+        .into_iter()
+        .enumerate()
+        .map(|(order, (kind, start, end))| (kind, index[start], index[end - 1] + 1, order))
+        .collect::<Vec<_>>();
+    nodes.sort_by(|a, b| a.1.cmp(&b.1).then(b.2.cmp(&a.2)).then(b.3.cmp(&a.3)));
+
+    let mut next = 0;
+    let root = build(NodeKind::File, 0, tokens.len(), &tokens, &nodes, &mut next);
+    (root, errors)
+}
+
+/// Build a `kind` node out of `tokens[start..end]`, along with the
+/// nodes starting at `nodes[next]` that fit inside it. Nodes that
+/// overlap their neighbors are left out.
+fn build<'s>(
+    kind: NodeKind,
+    start: usize,
+    end: usize,
+    tokens: &[Token<'s>],
+    nodes: &[(NodeKind, usize, usize, usize)],
+    next: &mut usize,
+) -> Node<'s> {
+    let mut children = vec![];
+    let mut cursor = start;
+    while let Some(&(kind, child_start, child_end, _)) = nodes.get(*next) {
+        if child_start >= end {
+            break;
+        }
+        *next += 1;
+        if child_start < cursor || child_end > end {
+            continue;
+        }
+        children.extend(
+            tokens[cursor..child_start]
+                .iter()
+                .map(|t| Element::Token(*t)),
+        );
+        let child = build(kind, child_start, child_end, tokens, nodes, next);
+        children.push(Element::Node(child));
+        cursor = child_end;
+    }
+    children.extend(tokens[cursor..end].iter().map(|t| Element::Token(*t)));
+
+    let pos = tokens.get(start).map(|t| t.pos).unwrap_or_default();
+    let len = tokens[start..end].iter().map(|t| t.text().len()).sum();
+    Node {
+        kind,
+        pos,
+        len,
+        children,
+    }
+}
+
+impl<'s> Node<'s> {
+    /// Where this node is in the source.
+    pub fn span(&self) -> Span {
+        Span::new(self.pos, self.len)
+    }
+
+    /// The exact source code this node was parsed from.
+    pub fn text(&self) -> String {
+        self.tokens().map(|t| t.text()).collect()
+    }
+
+    /// Every token in this node, in order, including whitespace and
+    /// comments.
+    pub fn tokens(&self) -> impl Iterator<Item = &Token<'s>> {
+        let mut stack = vec![self.children.iter()];
+        std::iter::from_fn(move || loop {
+            match stack.last_mut()?.next() {
+                Some(Element::Token(tok)) => return Some(tok),
+                Some(Element::Node(node)) => stack.push(node.children.iter()),
+                None => {
+                    stack.pop();
+                }
+            }
+        })
+    }
+
+    /// This node and every node inside it, outermost first.
+    pub fn nodes(&self) -> Vec<&Node<'s>> {
+        let mut nodes = vec![self];
+        for child in &self.children {
+            if let Element::Node(node) = child {
+                nodes.extend(node.nodes());
+            }
+        }
+        nodes
+    }
+
+    /// The innermost node containing the byte at `pos`.
+    pub fn node_at(&self, pos: usize) -> Option<&Node<'s>> {
+        if pos < self.pos || pos >= self.pos + self.len {
+            return None;
+        }
+        for child in &self.children {
+            if let Element::Node(node) = child {
+                if let Some(found) = node.node_at(pos) {
+                    return Some(found);
+                }
+            }
+        }
+        Some(self)
+    }
+}
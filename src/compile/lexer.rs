@@ -1,20 +1,23 @@
 //! The lexer turns a string of source code into Tokens.
 
 use {
-    crate::{Result, Syntax, SyntaxTrait, Token},
-    std::{iter::Peekable, mem, str::CharIndices},
+    crate::{Error, ErrorKind, Result, Syntax, SyntaxTrait, Token},
+    std::{collections::HashMap, iter::Peekable, mem, str::CharIndices},
 };
 
 struct Lexer<'s> {
-    tokens: Vec<Token<'s>>,           // what we're building
-    source: &'s str,                  // template source code
-    pos: usize,                       // current position in `source`
-    indents: Vec<usize>,              // current depth
-    chars: Peekable<CharIndices<'s>>, // iterator
-    cur: char,                        // current character
-    mode: Mode,                       // lexing mode
-    modes: Vec<Mode>,                 // stack of recent modes
-    style: Style,                     // tabs or spaces?
+    tokens: Vec<Token<'s>>,                 // what we're building
+    source: &'s str,                        // template source code
+    pos: usize,                             // current position in `source`
+    indents: Vec<usize>,                    // current depth
+    chars: Peekable<CharIndices<'s>>,       // iterator
+    cur: char,                              // current character
+    mode: Mode,                             // lexing mode
+    modes: Vec<Mode>,                       // stack of recent modes
+    style: Style,                           // tabs or spaces?
+    errors: Vec<Error>,                     // errors we kept going after
+    comments: bool,                         // keep comments as tokens?
+    quoted: HashMap<usize, (usize, usize)>, // string token pos => range with quotes
 }
 
 #[derive(Debug, PartialEq)]
@@ -33,9 +36,99 @@ enum Style {
 
 /// Scans source code and produces a list of `Token`s.
 pub fn scan<'s>(source: &'s str) -> Result<Vec<Token<'s>>> {
+    let (tokens, mut errors) = scan_all(source);
+    if errors.is_empty() {
+        Ok(tokens)
+    } else {
+        Err(errors.remove(0))
+    }
+}
+
+/// Like `scan()`, but `# comments` become `Syntax::Comment` tokens
+/// instead of being thrown away. The parser doesn't understand
+/// them, so this is for tools like the formatter.
+pub fn scan_with_comments<'s>(source: &'s str) -> Result<Vec<Token<'s>>> {
     let mut lexer = Lexer::from(source);
+    lexer.comments = true;
     lexer.scan()?;
-    Ok(lexer.tokens)
+    match lexer.errors.into_iter().next() {
+        Some(err) => Err(err),
+        None => Ok(lexer.tokens),
+    }
+}
+
+/// Scans source code into tokens that cover every byte of it, so
+/// joining their `text()` gives back the exact source. Whitespace
+/// and comments are `Syntax::Whitespace` and `Syntax::Comment`
+/// tokens, strings include their quotes, and the Indent, Dedent, and
+/// Semi tokens the lexer makes up for newlines are empty. Like
+/// `scan_all()`, it keeps going after errors.
+pub fn scan_lossless(source: &str) -> (Vec<Token<'_>>, Vec<Error>) {
+    let (tokens, _, errors) = scan_lossless_parts(source);
+    (tokens, errors)
+}
+
+/// `scan_lossless()`, plus the tokens `scan_all()` would return so
+/// they can be parsed.
+pub(crate) fn scan_lossless_parts(source: &str) -> (Vec<Token<'_>>, Vec<Token<'_>>, Vec<Error>) {
+    let mut lexer = Lexer::from(source);
+    lexer.comments = true;
+    if let Err(err) = lexer.scan() {
+        lexer.errors.push(err);
+    }
+
+    let mut tokens = vec![];
+    let mut cursor = 0;
+    for tok in &lexer.tokens {
+        let (start, end) = match tok.kind {
+            Syntax::Semi if source[tok.pos..].starts_with(';') => (tok.pos, tok.pos + 1),
+            Syntax::Indent | Syntax::Dedent | Syntax::Semi => (cursor, cursor),
+            Syntax::String(..) if lexer.quoted.contains_key(&tok.pos) => lexer.quoted[&tok.pos],
+            _ => (tok.pos, tok.pos + tok.literal().len()),
+        };
+        let start = start.max(cursor);
+        let end = end.max(start);
+        trivia(source, cursor, start, &mut tokens);
+        tokens.push(Token::new(
+            tok.kind,
+            start,
+            end - start,
+            &source[start..end],
+        ));
+        cursor = end;
+    }
+    trivia(source, cursor, source.len(), &mut tokens);
+    lexer.tokens.retain(|t| t.kind != Syntax::Comment);
+    (tokens, lexer.tokens, lexer.errors)
+}
+
+/// Add tokens for the whitespace in `source[start..end]`. Anything
+/// else the lexer skipped becomes a `Syntax::None` token.
+fn trivia<'s>(source: &'s str, mut start: usize, end: usize, tokens: &mut Vec<Token<'s>>) {
+    while start < end {
+        let is_space = source[start..].starts_with(char::is_whitespace);
+        let len = source[start..end]
+            .find(|c: char| c.is_whitespace() != is_space)
+            .unwrap_or(end - start);
+        let kind = if is_space {
+            Syntax::Whitespace
+        } else {
+            Syntax::None
+        };
+        tokens.push(Token::new(kind, start, len, &source[start..start + len]));
+        start += len;
+    }
+}
+
+/// Like `scan()`, but keeps going after errors and returns all of
+/// them along with the tokens. An unclosed string becomes a String
+/// token running to the end of its line.
+pub fn scan_all(source: &str) -> (Vec<Token<'_>>, Vec<Error>) {
+    let mut lexer = Lexer::from(source);
+    if let Err(err) = lexer.scan() {
+        lexer.errors.push(err);
+    }
+    (lexer.tokens, lexer.errors)
 }
 
 impl<'s> Lexer<'s> {
@@ -51,6 +144,9 @@ impl<'s> Lexer<'s> {
             style: Style::None,
             mode: Mode::None,
             modes: vec![],
+            errors: vec![],
+            comments: false,
+            quoted: HashMap::new(),
         }
     }
 
@@ -142,7 +238,22 @@ impl<'s> Lexer<'s> {
                 '\n' => self.scan_newline()?,
                 ';' => Syntax::Semi,
                 ',' => Syntax::Comma,
-                '"' | '\'' | '`' => self.scan_string(c)?,
+                '"' | '\'' | '`' => {
+                    let chars = self.chars.clone(); // to rewind to
+                    match self.scan_string(c) {
+                        Ok(kind) => {
+                            self.save_quoted(start);
+                            kind
+                        }
+                        Err(err) => {
+                            self.errors.push(err);
+                            self.chars = chars;
+                            self.skip_line(start);
+                            self.save_quoted(start);
+                            continue;
+                        }
+                    }
+                }
                 '!' => {
                     if self.peek_is('!') {
                         Syntax::Op
@@ -275,18 +386,54 @@ impl<'s> Lexer<'s> {
             self.append(Syntax::Dedent)?;
         }
 
-        // Trim leading ;
-        while !self.tokens.is_empty() && self.tokens[0].kind == Syntax::Semi {
-            self.tokens.remove(0);
+        // Trim leading ; (after any comments)
+        while let Some(i) = self.tokens.iter().position(|t| t.kind != Syntax::Comment) {
+            if self.tokens[i].kind != Syntax::Semi {
+                break;
+            }
+            self.tokens.remove(i);
         }
 
         Ok(())
     }
 
+    /// Recover from an unclosed string starting at `start` by turning
+    /// the rest of its line into a String token and scanning again
+    /// from the end of the line. `self.chars` has to be just after
+    /// `start`.
+    fn skip_line(&mut self, start: usize) {
+        let eol = self.source[start..]
+            .find('\n')
+            .map(|i| start + i)
+            .unwrap_or(self.source.len());
+        self.tokens.push(Token::new(
+            Syntax::String(false),
+            start + 1,
+            eol - start - 1,
+            &self.source[start + 1..eol],
+        ));
+        while self.chars.peek().filter(|(i, _)| *i < eol).is_some() {
+            self.next();
+        }
+    }
+
+    /// Remember where the string token we just made starts and ends,
+    /// quotes and all, for `scan_lossless()`.
+    fn save_quoted(&mut self, start: usize) {
+        if let Some(tok) = self.tokens.last().filter(|_| self.comments) {
+            let end = self.pos + self.cur.len_utf8();
+            self.quoted.insert(tok.pos, (start, end));
+        }
+    }
+
     /// Scan a single line comment.
     fn scan_comment(&mut self) -> Result<Syntax> {
         self.eat(|c| c != '\n');
-        Ok(Syntax::None)
+        Ok(if self.comments {
+            Syntax::Comment
+        } else {
+            Syntax::None
+        })
     }
 
     /// Scan until we find a non-number.
@@ -399,12 +546,12 @@ impl<'s> Lexer<'s> {
             prev = c;
         }
 
-        scan_error!(
+        Err(Error::new(
+            ErrorKind::UnclosedString,
+            format!("Unclosed string. Expected closing quote: {}  ", delimiter),
             start,
             1,
-            "Unclosed string. Expected closing quote: {}  ",
-            delimiter
-        )
+        ))
     }
 
     /// Scan a word, which may have {interpolation.with(some, whitespace)}.
@@ -442,7 +589,8 @@ impl<'s> Lexer<'s> {
             self.next();
         }
 
-        Ok(match &self.source[start..=self.pos] {
+        let end = self.pos + self.cur.len_utf8();
+        Ok(match &self.source[start..end] {
             "def" => Syntax::Def,
             "do" => Syntax::Do,
             "return" => Syntax::Return,
@@ -453,12 +601,25 @@ impl<'s> Lexer<'s> {
             "while" => Syntax::While,
             "in" => Syntax::In,
             "fn" => Syntax::Fn,
+            // only keywords in `block name`, `extends "file"`, and
+            // `import "file"`, so they still work as names
+            "block" if self.followed_by(end, |c| c.is_alphabetic()) => Syntax::Block,
+            "extends" if self.followed_by(end, |c| matches!(c, '"' | '\'')) => Syntax::Extends,
+            "import" if self.followed_by(end, |c| matches!(c, '"' | '\'')) => Syntax::Import,
             "true" => Syntax::Bool(true),
             "false" => Syntax::Bool(false),
             _ => Syntax::Word,
         })
     }
 
+    /// Is the source at `pos` spaces and then a char that passes
+    /// `check`, outside of a tag?
+    fn followed_by(&self, pos: usize, check: impl Fn(char) -> bool) -> bool {
+        let rest = &self.source[pos..];
+        let next = rest.trim_start_matches([' ', '\t']);
+        !self.in_tag() && next.len() < rest.len() && next.chars().next().is_some_and(check)
+    }
+
     /// Determines if < is opening a tag or just a regular `<` sign
     fn scan_left_arrow(&mut self) -> Result<Syntax> {
         let p = *self.peek().unwrap_or(&'0');
@@ -533,7 +694,7 @@ impl<'s> Lexer<'s> {
             self.eat(|c| c == '\n');
 
             // tabs vs spaces error checking
-            self.check_indent_style()?;
+            self.check_indent_style();
 
             // count indent
             while self.peek_is(' ') || self.peek_is('\t') {
@@ -545,7 +706,13 @@ impl<'s> Lexer<'s> {
             match self.peek() {
                 Some('\n') => {}
                 Some('#') => {
-                    self.scan_comment()?;
+                    let start = self.chars.peek().map(|(i, _)| *i).unwrap_or(self.pos);
+                    if self.scan_comment()? == Syntax::Comment {
+                        let end = self.pos + self.cur.len_utf8();
+                        let lit = &self.source[start..end];
+                        self.tokens
+                            .push(Token::new(Syntax::Comment, start, end - start, lit));
+                    }
                 }
                 _ => break,
             }
@@ -596,7 +763,7 @@ impl<'s> Lexer<'s> {
     }
 
     /// Make sure tabs vs spaces use is consistent.
-    fn check_indent_style(&mut self) -> Result<()> {
+    fn check_indent_style(&mut self) {
         let style = if self.peek_is(' ') {
             Style::Spaces
         } else if self.peek_is('\t') {
@@ -608,15 +775,16 @@ impl<'s> Lexer<'s> {
         if self.style == Style::None {
             self.style = style;
         } else if style != Style::None && self.style != style {
-            return scan_error!(
+            // keep going, so we find the rest of the errors
+            self.errors.push(Error::new(
+                ErrorKind::IndentationError,
+                format!(
+                    "Can't mix tabs and spaces. Expected {:?}, found {:?}",
+                    self.style, style
+                ),
                 self.pos,
                 1,
-                "Can't mix tabs and spaces. Expected {:?}, found {:?}",
-                self.style,
-                style
-            );
+            ));
         }
-
-        Ok(())
     }
 }
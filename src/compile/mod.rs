@@ -1,5 +1,9 @@
+pub mod bytecode;
+pub mod cst;
 pub mod lexer;
+pub mod optimize;
 pub mod parser;
+pub mod resolve;
 pub mod stmt;
 pub mod syntax;
 pub mod tag;
@@ -10,3 +14,13 @@ pub fn compile<S: AsRef<str>>(s: S) -> crate::Result<Vec<stmt::Stmt>> {
     use {lexer::scan, parser::parse};
     scan(s.as_ref()).and_then(|t| parse(&t))
 }
+
+/// Find every syntax and parse error in source code, in the order
+/// they appear, instead of stopping at the first one.
+pub fn check<S: AsRef<str>>(s: S) -> Vec<crate::Error> {
+    use {lexer::scan_all, parser::parse_all};
+    let (tokens, mut errors) = scan_all(s.as_ref());
+    errors.extend(parse_all(&tokens).1);
+    errors.sort_by_key(|e| e.pos);
+    errors
+}
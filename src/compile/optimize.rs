@@ -0,0 +1,221 @@
+//! The optimizer rewrites a template's `Stmt`s so they do less work
+//! each time they're rendered:
+//!
+//! - Calls to builtin operators with literal arguments, like `2 + 2`
+//!   or `"a" + "b"`, are folded into their value.
+//! - Tags with nothing dynamic in them - literal names, ids, classes,
+//!   attributes, and bodies - are rendered to HTML ahead of time.
+//!
+//! Folding assumes the builtin operators haven't been replaced with
+//! `Env::set()`. Pre-rendered tags are more careful: before printing
+//! its HTML, the tag checks that it hasn't been defined as a custom
+//! tag, and that the words in its body still aren't variables, and
+//! falls back to printing it the normal way if either has happened.
+
+use {
+    crate::{eval::env::aot::static_html, Env, Prerendered, Stmt, Symbol, Tag, Value},
+    std::{collections::HashSet, mem, sync::Arc},
+};
+
+/// Builtins that always return the same thing given the same args.
+const PURE: &[&str] = &[
+    "+", "-", "*", "/", "%", "==", "!=", ">", ">=", "<", "<=", "!", "not", "&&", "||", "concat",
+];
+
+/// Fold constant expressions and pre-render static tags. The result
+/// renders the same as `stmts`, just faster.
+pub fn optimize(stmts: Vec<Stmt>) -> Vec<Stmt> {
+    let mut declared = HashSet::new();
+    declare(&stmts, &mut declared);
+    Optimizer {
+        env: Env::new(),
+        declared,
+    }
+    .block(stmts)
+}
+
+/// Rewrites `Stmt`s, bottom up, so folded values can make a tag
+/// static.
+struct Optimizer {
+    env: Env<'static>,         // for evaluating what's known now
+    declared: HashSet<Symbol>, // names the template sets itself
+}
+
+impl Optimizer {
+    fn block(&mut self, stmts: Vec<Stmt>) -> Vec<Stmt> {
+        stmts.into_iter().map(|stmt| self.stmt(stmt)).collect()
+    }
+
+    fn pairs(&mut self, pairs: Vec<(Symbol, Stmt)>) -> Vec<(Symbol, Stmt)> {
+        pairs
+            .into_iter()
+            .map(|(name, stmt)| (name, self.stmt(stmt)))
+            .collect()
+    }
+
+    fn expr(&mut self, mut expr: Box<Stmt>) -> Box<Stmt> {
+        *expr = self.stmt(mem::replace(&mut *expr, Stmt::None));
+        expr
+    }
+
+    fn stmt(&mut self, stmt: Stmt) -> Stmt {
+        match stmt {
+            Stmt::List(items) => Stmt::List(self.block(items)),
+            Stmt::Map(pairs) => Stmt::Map(self.pairs(pairs)),
+            Stmt::Args(pairs) => Stmt::Args(self.pairs(pairs)),
+            Stmt::Call(target, args, span) => {
+                let target = self.expr(target);
+                let args = self.block(args);
+                self.fold(Stmt::Call(target, args, span))
+            }
+            Stmt::Return(expr, span) => Stmt::Return(self.expr(expr), span),
+            Stmt::If(conds) => Stmt::If(
+                conds
+                    .into_iter()
+                    .map(|(test, body)| (self.stmt(test), self.block(body)))
+                    .collect(),
+            ),
+            Stmt::For(key, var, iter, body) => {
+                let iter = self.expr(iter);
+                Stmt::For(key, var, iter, self.block(body))
+            }
+            Stmt::While(test, body) => {
+                let test = self.expr(test);
+                Stmt::While(test, self.block(body))
+            }
+            Stmt::Assign(name, val, is_reassign, span) => {
+                Stmt::Assign(name, self.expr(val), is_reassign, span)
+            }
+            Stmt::Tag(tag) => Stmt::Tag(self.tag(tag)),
+            Stmt::Fn(params, body) => Stmt::Fn(params, self.block(body)),
+            Stmt::Block(name, body) => Stmt::Block(name, self.block(body)),
+            Stmt::Extends(expr) => Stmt::Extends(self.expr(expr)),
+            Stmt::None | Stmt::Value(..) | Stmt::Word(..) => stmt,
+        }
+    }
+
+    /// The value of a call to a `PURE` builtin with literal args, or
+    /// the call if it can't be worked out now.
+    fn fold(&mut self, call: Stmt) -> Stmt {
+        let Stmt::Call(target, args, _) = &call else {
+            return call;
+        };
+        let pure = matches!(&**target, Stmt::Word(name, _)
+            if PURE.contains(&name.to_str()) && !self.declared.contains(name));
+        if !pure || !args.iter().all(|arg| matches!(arg, Stmt::Value(..))) {
+            return call;
+        }
+        match self.env.eval(&call) {
+            Ok(val @ (Value::None | Value::Bool(..) | Value::Number(..))) => Stmt::Value(val),
+            Ok(val @ (Value::String(..) | Value::Html(..))) => Stmt::Value(val),
+            _ => call,
+        }
+    }
+
+    fn tag(&mut self, mut tag: Tag) -> Tag {
+        tag.tag = self.expr(tag.tag);
+        tag.id = self.expr(tag.id);
+        tag.classes = self.block(tag.classes);
+        tag.attrs = tag
+            .attrs
+            .into_iter()
+            .map(|(name, val)| (self.stmt(name), self.stmt(val)))
+            .collect();
+        tag.body = self.block(tag.body);
+        tag.html = self.prerender(&tag);
+        tag
+    }
+
+    /// Render `tag` now, if everything in it is known.
+    fn prerender(&mut self, tag: &Tag) -> Option<Arc<Prerendered>> {
+        static_html(tag)?;
+        let mut unset = vec![tag.custom_name()?.clone()];
+        for stmt in &tag.body {
+            let names = match stmt {
+                Stmt::None | Stmt::Value(..) => continue,
+                // printed as text, unless it's a keyword or var
+                Stmt::Word(word, _) if self.is_text(word) => std::slice::from_ref(word),
+                Stmt::Tag(Tag {
+                    html: Some(html), ..
+                }) => &html.unset[..],
+                _ => return None,
+            };
+            for name in names {
+                if !unset.contains(name) {
+                    unset.push(name.clone());
+                }
+            }
+        }
+        match self.env.eval(&Stmt::Tag(tag.clone())) {
+            Ok(Value::Html(html)) => Some(Arc::new(Prerendered {
+                html: html.to_string(),
+                unset,
+            })),
+            _ => None,
+        }
+    }
+
+    /// Is `word` text in a tag body, as long as nobody defines it?
+    fn is_text(&self, word: &Symbol) -> bool {
+        !matches!(word.to_str(), "return" | "break" | "continue")
+            && self.env.lookup_sym(word).is_none()
+    }
+}
+
+/// Collect the names of every variable, loop var, and param in
+/// `stmts`, since they might shadow a builtin.
+fn declare(stmts: &[Stmt], names: &mut HashSet<Symbol>) {
+    for stmt in stmts {
+        match stmt {
+            Stmt::None | Stmt::Value(..) | Stmt::Word(..) => {}
+            Stmt::List(items) => declare(items, names),
+            Stmt::Map(pairs) | Stmt::Args(pairs) => {
+                pairs.iter().for_each(|(_, val)| declare_one(val, names))
+            }
+            Stmt::Call(target, args, _) => {
+                declare_one(target, names);
+                declare(args, names);
+            }
+            Stmt::Return(expr, _) | Stmt::Extends(expr) => declare_one(expr, names),
+            Stmt::If(conds) => {
+                for (test, body) in conds {
+                    declare_one(test, names);
+                    declare(body, names);
+                }
+            }
+            Stmt::For(key, var, iter, body) => {
+                names.extend(key.iter().cloned());
+                names.insert(var.clone());
+                declare_one(iter, names);
+                declare(body, names);
+            }
+            Stmt::While(test, body) => {
+                declare_one(test, names);
+                declare(body, names);
+            }
+            Stmt::Assign(name, val, ..) => {
+                names.insert(name.clone());
+                declare_one(val, names);
+            }
+            Stmt::Tag(tag) => {
+                declare_one(&tag.tag, names);
+                declare_one(&tag.id, names);
+                declare(&tag.classes, names);
+                for (name, val) in &tag.attrs {
+                    declare_one(name, names);
+                    declare_one(val, names);
+                }
+                declare(&tag.body, names);
+            }
+            Stmt::Fn(params, body) => {
+                names.extend(params.iter().cloned());
+                declare(body, names);
+            }
+            Stmt::Block(_, body) => declare(body, names),
+        }
+    }
+}
+
+fn declare_one(stmt: &Stmt, names: &mut HashSet<Symbol>) {
+    declare(std::slice::from_ref(stmt), names)
+}
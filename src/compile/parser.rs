@@ -1,7 +1,7 @@
 //! The parser turns a slice of `Token` into a Vec of `Stmt`.
 
 use {
-    crate::{scan, Error, Result, Stmt, Symbol, Syntax, Tag, Token},
+    crate::{scan, Error, ErrorKind, NodeKind, Result, Stmt, Symbol, Syntax, Tag, Token, Value},
     std::mem,
 };
 
@@ -10,20 +10,45 @@ const STACK_SIZE: usize = 1000; // infinite loop protection
 
 #[derive(Debug)]
 pub struct Parser<'s, 't> {
-    tokens: &'t [Token<'s>], // code
-    ast: Vec<Stmt>,          // what we're building
-    pos: usize,              // position in tokens vec
-    tags: usize,             // open tags
+    tokens: &'t [Token<'s>],                      // code
+    ast: Vec<Stmt>,                               // what we're building
+    pos: usize,                                   // position in tokens vec
+    depth: usize,                                 // indentation depth at `pos`
+    tags: usize,                                  // open tags
+    errors: Vec<Error>,                           // errors we recovered from
+    nodes: Option<Vec<(NodeKind, usize, usize)>>, // CST nodes, as token ranges
 
     #[cfg(debug_assertions)]
     peeked: usize, // infinite loop protection hack
 }
 
 /// Main method. Turn a slice of `Token` into a Vec of `Stmt`.
-pub fn parse<'t>(tokens: &'t [Token]) -> Result<Vec<Stmt>> {
+pub fn parse(tokens: &[Token]) -> Result<Vec<Stmt>> {
+    let (ast, mut errors) = parse_all(tokens);
+    if errors.is_empty() {
+        Ok(ast)
+    } else {
+        Err(errors.remove(0))
+    }
+}
+
+/// Like `parse()`, but skips to the next statement after an error
+/// and keeps going, returning every error. Statements with errors
+/// are left out of the AST.
+pub fn parse_all(tokens: &[Token]) -> (Vec<Stmt>, Vec<Error>) {
+    let mut parser = Parser::from(tokens);
+    let _ = parser.parse();
+    (parser.ast, parser.errors)
+}
+
+/// Like `parse_all()`, but returns the nodes of the concrete syntax
+/// tree instead of the AST. Each node is its kind and the range of
+/// `tokens` it covers, and a node comes after any nodes inside it.
+pub(crate) fn parse_nodes(tokens: &[Token]) -> (Vec<(NodeKind, usize, usize)>, Vec<Error>) {
     let mut parser = Parser::from(tokens);
-    parser.parse()?;
-    Ok(parser.ast)
+    parser.nodes = Some(vec![]);
+    let _ = parser.parse();
+    (parser.nodes.unwrap_or_default(), parser.errors)
 }
 
 impl<'s, 't> Parser<'s, 't> {
@@ -34,41 +59,113 @@ impl<'s, 't> Parser<'s, 't> {
             ast: vec![],
             tags: 0,
             pos: 0,
+            depth: 0,
+            errors: vec![],
+            nodes: None,
 
             #[cfg(debug_assertions)]
             peeked: 0,
         }
     }
 
-    /// Parse `TokenStream` into `AST`.
+    /// Parse `TokenStream` into `AST`. Errors are collected, and the
+    /// first one is returned once we're done.
     pub fn parse(&mut self) -> Result<()> {
         while !self.peek_eof() {
-            let stmt = self.stmt()?;
-            self.ast.push(stmt);
+            if let Some(stmt) = self.recoverable_stmt(0) {
+                self.ast.push(stmt);
+            }
             self.eat(Syntax::Semi);
         }
-        Ok(())
+        match self.errors.first() {
+            Some(err) => Err(err.clone()),
+            None => Ok(()),
+        }
+    }
+
+    /// Parse a stmt at indentation `depth`. If it has an error, save
+    /// it and skip to the start of the next stmt at the same depth.
+    fn recoverable_stmt(&mut self, depth: usize) -> Option<Stmt> {
+        let (start, tags) = (self.pos, self.tags);
+        match self.stmt() {
+            Ok(stmt) => Some(stmt),
+            Err(err) => {
+                self.errors.push(err);
+                self.tags = tags;
+                if self.pos == start {
+                    self.skip();
+                }
+                self.synchronize(depth);
+                None
+            }
+        }
+    }
+
+    /// Skip tokens until the next stmt at `depth` starts, or the
+    /// block at `depth` ends.
+    fn synchronize(&mut self, depth: usize) {
+        let mut cur = self.depth;
+        // the error might have been the end of the stmt
+        if cur == depth
+            && matches!(
+                self.tokens.get(self.pos.wrapping_sub(1)).map(|t| t.kind),
+                Some(Syntax::Semi) | Some(Syntax::Dedent)
+            )
+        {
+            return;
+        }
+        while let Some(tok) = self.peek() {
+            match tok.kind {
+                Syntax::Semi if cur == depth => {
+                    self.skip();
+                    return;
+                }
+                Syntax::Dedent if cur <= depth => return,
+                Syntax::Dedent => {
+                    cur -= 1;
+                    self.skip();
+                    if cur == depth {
+                        return;
+                    }
+                }
+                Syntax::Indent => {
+                    cur += 1;
+                    self.skip();
+                }
+                _ => self.skip(),
+            }
+        }
+    }
+
+    /// Remember that the tokens from `start` up to here make a
+    /// `kind` node, if we're building a CST.
+    fn node(&mut self, kind: NodeKind, start: usize) {
+        if let Some(nodes) = &mut self.nodes {
+            if self.pos > start {
+                nodes.push((kind, start, self.pos));
+            }
+        }
     }
 
     /// Peek at next `Token`.
-    fn peek(&mut self) -> Option<Token> {
+    fn peek(&mut self) -> Option<Token<'_>> {
         #[cfg(debug_assertions)]
         {
             self.peeked += 1;
             if self.peeked > STACK_SIZE {
-                panic!("infinite loop while peek()ing: {:?}", self.tokens.get(0));
+                panic!("infinite loop while peek()ing: {:?}", self.tokens.first());
             }
         }
         self.tokens.get(self.pos).copied()
     }
 
     /// Peek two ahead.
-    fn peek2(&mut self) -> Option<Token> {
+    fn peek2(&mut self) -> Option<Token<'_>> {
         #[cfg(debug_assertions)]
         {
             self.peeked += 1;
             if self.peeked > STACK_SIZE {
-                panic!("infinite loop while peek()ing: {:?}", self.tokens.get(0));
+                panic!("infinite loop while peek()ing: {:?}", self.tokens.first());
             }
         }
         self.tokens.get(self.pos + 1).copied()
@@ -95,7 +192,7 @@ impl<'s, 't> Parser<'s, 't> {
     }
 
     /// Advance iterator an return next `Token`.
-    fn try_next(&mut self) -> Option<Token> {
+    fn try_next(&mut self) -> Option<Token<'_>> {
         if self.pos < self.tokens.len() {
             Some(self.next())
         } else {
@@ -107,18 +204,28 @@ impl<'s, 't> Parser<'s, 't> {
     fn back(&mut self) {
         if self.pos > 0 {
             self.pos -= 1;
+            match self.tokens[self.pos].kind {
+                Syntax::Indent => self.depth = self.depth.saturating_sub(1),
+                Syntax::Dedent => self.depth += 1,
+                _ => {}
+            }
         }
     }
 
     /// Advance iterator an return next `Token`.
-    fn next(&mut self) -> Token {
+    fn next(&mut self) -> Token<'_> {
         #[cfg(debug_assertions)]
         {
             self.peeked = 0;
         }
-        let pos = self.pos;
+        let tok = *self.tokens.get(self.pos).unwrap();
         self.pos += 1;
-        *self.tokens.get(pos).unwrap()
+        match tok.kind {
+            Syntax::Indent => self.depth += 1,
+            Syntax::Dedent => self.depth = self.depth.saturating_sub(1),
+            _ => {}
+        }
+        tok
     }
 
     /// Skip one token.
@@ -135,28 +242,28 @@ impl<'s, 't> Parser<'s, 't> {
 
     /// Trigger parse error for next() token.
     fn error<T, S: AsRef<str>>(&mut self, msg: S) -> Result<T> {
-        use crate::ErrorKind::ParseError;
-
-        Err(if let Some(got) = self.try_next() {
-            Error::new(
-                ParseError,
-                format!("expected {}, got {:?}", msg.as_ref(), got.kind),
-                got.pos,
-                got.len,
-            )
-        } else {
-            Error::new(
-                ParseError,
-                format!("expected {}, got EOF", msg.as_ref()),
-                0,
-                0,
-            )
-        })
+        let expected = msg.as_ref().to_string();
+        let (actual, pos, len) = match self.try_next() {
+            Some(got) => (format!("{:?}", got.kind), got.pos, got.len),
+            None => match self.tokens.last() {
+                Some(last) => ("EOF".to_string(), last.pos, last.len),
+                None => ("EOF".to_string(), 0, 0),
+            },
+        };
+        Err(Error::new(
+            ErrorKind::UnexpectedToken {
+                expected: expected.clone(),
+                actual: actual.clone(),
+            },
+            format!("expected {}, got {}", expected, actual),
+            pos,
+            len,
+        ))
     }
 
     /// Consumes and returns the next token if it's of `kind`,
     /// otherwise errors.
-    fn expect(&mut self, kind: Syntax) -> Result<Token> {
+    fn expect(&mut self, kind: Syntax) -> Result<Token<'_>> {
         if self.peek_kind() == kind {
             Ok(self.next())
         } else {
@@ -187,12 +294,18 @@ impl<'s, 't> Parser<'s, 't> {
         };
 
         let lit = tok.to_string();
+
+        // <!DOCTYPE> and <!-- comments --> are passed through as-is
+        if lit.starts_with("<!") {
+            return Ok(Stmt::Value(Value::Html(lit.into())));
+        }
+
         if is_interpolated && lit.contains('{') {
             let mut parts = vec![];
             let mut idx = 0;
             while let Some(i) = lit[idx..].find('{') {
                 // check for escaped \{}
-                if i > 0 && lit[idx..].bytes().nth(i - 1).unwrap_or(b'0') == b'\\' {
+                if i > 0 && lit.as_bytes()[idx + i - 1] == b'\\' {
                     parts.push(Stmt::Value(lit[idx..i + idx - 1].into()));
                     parts.push(Stmt::Value(lit[idx + i..i + idx + 1].into()));
                     idx += i + 1;
@@ -206,20 +319,20 @@ impl<'s, 't> Parser<'s, 't> {
                     }
                 }
                 idx += i + 1;
-                let mut end = idx;
-                for (x, b) in lit[idx..].bytes().enumerate() {
-                    if b == b'}' {
-                        end = idx + x;
-                        break;
-                    }
+                let end = match lit[idx..].find('}') {
+                    Some(x) => idx + x,
+                    None => return pos_error!(tok.pos + idx - 1, "unclosed { in string"),
+                };
+                // shift positions so they point into the whole source
+                let offset = tok.pos + idx;
+                let mut tokens = scan(&lit[idx..end]).map_err(|mut e| {
+                    e.pos += offset;
+                    e
+                })?;
+                for t in &mut tokens {
+                    t.pos += offset;
                 }
-                // What! Rust 'lifetime magic.
-                let mut exprs = scan(&lit[idx..end])
-                    .and_then(|t| parse(&t))
-                    .map_err(|mut e| {
-                        e.pos += tok.pos + idx - 1; // probably not right yet...
-                        e
-                    })?;
+                let mut exprs = parse(&tokens)?;
                 parts.append(&mut exprs);
                 idx = end + 1;
             }
@@ -229,7 +342,12 @@ impl<'s, 't> Parser<'s, 't> {
             if parts.len() == 1 {
                 Ok(parts.remove(0))
             } else {
-                Ok(Stmt::Call(bx!(Stmt::Word("concat".into())), parts))
+                let span = tok.span();
+                Ok(Stmt::Call(
+                    bx!(Stmt::Word("concat".into(), span)),
+                    parts,
+                    span,
+                ))
             }
         } else {
             Ok(Stmt::Value(lit.into()))
@@ -239,11 +357,12 @@ impl<'s, 't> Parser<'s, 't> {
     /// Parse a word.
     fn word(&mut self) -> Result<Stmt> {
         let word = self.expect(Syntax::Word)?;
-        Ok(Stmt::Word(word.to_str().into()))
+        Ok(Stmt::Word(word.to_sym(), word.span()))
     }
 
     /// Parse a function literal.
     fn fn_literal(&mut self) -> Result<Stmt> {
+        let start = self.pos;
         self.expect(Syntax::Fn)?;
         let mut args = vec![];
         if self.peek_is(Syntax::LParen) {
@@ -264,6 +383,7 @@ impl<'s, 't> Parser<'s, 't> {
         } else {
             vec![self.stmt()?]
         };
+        self.node(NodeKind::Fn, start);
         Ok(Stmt::Fn(args, block))
     }
 
@@ -276,12 +396,14 @@ impl<'s, 't> Parser<'s, 't> {
     /// Thanks matklad!
     /// https://matklad.github.io/2020/04/13/simple-but-powerful-pratt-parsing.html
     fn op_expr(&mut self, min_power: u8) -> Result<Stmt> {
+        let start = self.pos;
         // check for unary
         if self.peek_is(Syntax::Op) {
-            return Ok(Stmt::Call(
-                bx!(Stmt::Word(self.next().to_sym())),
-                vec![self.op_expr(min_power)?],
-            ));
+            let op = self.next();
+            let (op, span) = (op.to_sym(), op.span());
+            let expr = self.op_expr(min_power)?;
+            self.node(NodeKind::Unary, start);
+            return Ok(Stmt::Call(bx!(Stmt::Word(op, span)), vec![expr], span));
         }
 
         let mut left = self.atom()?;
@@ -295,16 +417,29 @@ impl<'s, 't> Parser<'s, 't> {
                 if power < min_power {
                     break;
                 }
-                let op = self.next().to_string();
+                let op = self.next();
+                let (op, span) = (op.to_string(), op.span());
                 match op.as_ref() {
                     "(" => {
-                        left = Stmt::Call(bx!(left), self.args()?);
+                        // point at the fn name, if there is one
+                        let span = match left {
+                            Stmt::Word(_, span) => span,
+                            _ => span,
+                        };
+                        let args_start = self.pos - 1;
+                        left = Stmt::Call(bx!(left), self.args()?, span);
+                        self.node(NodeKind::Args, args_start);
+                        self.node(NodeKind::Call, start);
                         continue;
                     }
                     "[" => {
-                        left =
-                            Stmt::Call(bx!(Stmt::Word("index".into())), vec![left, self.expr()?]);
+                        left = Stmt::Call(
+                            bx!(Stmt::Word("index".into(), span)),
+                            vec![left, self.expr()?],
+                            span,
+                        );
                         self.expect(Syntax::RStaple)?;
+                        self.node(NodeKind::Index, start);
                         continue;
                     }
                     _ => unimplemented!("op: {}", op),
@@ -315,18 +450,26 @@ impl<'s, 't> Parser<'s, 't> {
             if op_power <= min_power {
                 break;
             }
-            let op = self.next().to_sym();
+            let op = self.next();
+            let (op, span) = (op.to_sym(), op.span());
             match op.as_ref() {
                 ":=" | "=" => {
                     let reassign = op == "=";
                     match left {
-                        Stmt::Word(name) => {
-                            return Ok(Stmt::Assign(name, bx!(self.expr()?), reassign));
+                        Stmt::Word(name, span) => {
+                            let val = self.expr()?;
+                            self.node(NodeKind::Assign, start);
+                            return Ok(Stmt::Assign(name, bx!(val), reassign, span));
                         }
-                        Stmt::Call(ex, mut args) => match ex.to_str() {
+                        Stmt::Call(ex, mut args, _) => match ex.to_str() {
                             "index" | "." => {
                                 args.push(self.expr()?);
-                                return Ok(Stmt::Call(bx!(Stmt::Word("set_index".into())), args));
+                                self.node(NodeKind::Assign, start);
+                                return Ok(Stmt::Call(
+                                    bx!(Stmt::Word("set_index".into(), span)),
+                                    args,
+                                    span,
+                                ));
                             }
                             _ => {
                                 return self.error("Word or index");
@@ -337,9 +480,13 @@ impl<'s, 't> Parser<'s, 't> {
                 }
                 // convert word to str, ex: map.key => .(map, "key")
                 "." if self.peek_is(Syntax::Word) => match self.op_expr(op_power)? {
-                    Stmt::Word(word) => {
-                        left =
-                            Stmt::Call(bx!(Stmt::Word(op)), vec![left, Stmt::Value(word.into())]);
+                    Stmt::Word(word, _) => {
+                        self.node(NodeKind::Binary, start);
+                        left = Stmt::Call(
+                            bx!(Stmt::Word(op, span)),
+                            vec![left, Stmt::Value(word.into())],
+                            span,
+                        );
                         continue;
                     }
                     _ => return self.error("Word"),
@@ -348,25 +495,32 @@ impl<'s, 't> Parser<'s, 't> {
                 _ if !matches!(op.as_ref(), "==" | "!=" | ">=" | "<=" | "..=")
                     && matches!(op.bytes().last(), Some(b'=')) =>
                 {
+                    let var_span = left.span();
+                    let val = self.expr()?;
+                    self.node(NodeKind::Assign, start);
                     return Ok(Stmt::Assign(
                         left.to_str().into(),
                         bx!(Stmt::Call(
-                            bx!(Stmt::Word(op.to_str().trim_end_matches('=').into())),
-                            vec![left, self.expr()?]
+                            bx!(Stmt::Word(op.to_str().trim_end_matches('=').into(), span)),
+                            vec![left, val],
+                            span,
                         )),
                         true, // reassignment
+                        var_span,
                     ));
                 }
                 _ => {}
             }
             let right = self.op_expr(op_power)?;
-            left = Stmt::Call(bx!(Stmt::Word(op)), vec![left, right]);
+            self.node(NodeKind::Binary, start);
+            left = Stmt::Call(bx!(Stmt::Word(op, span)), vec![left, right], span);
         }
         Ok(left)
     }
 
     /// Parse an indivisible unit, as the Ancient Greeks would say.
     fn atom(&mut self) -> Result<Stmt> {
+        let start = self.pos;
         match self.peek_kind() {
             // Literal
             Syntax::Bool(..) => Ok(self.boolean()?),
@@ -382,6 +536,7 @@ impl<'s, 't> Parser<'s, 't> {
                 self.skip();
                 let expr = self.expr()?;
                 self.expect(Syntax::RParen)?;
+                self.node(NodeKind::Paren, start);
                 Ok(expr)
             }
             // List
@@ -405,6 +560,7 @@ impl<'s, 't> Parser<'s, 't> {
                 }
                 self.eat(Syntax::Semi);
                 self.expect(Syntax::RStaple)?;
+                self.node(NodeKind::List, start);
                 Ok(Stmt::List(list))
             }
             // Map
@@ -437,6 +593,7 @@ impl<'s, 't> Parser<'s, 't> {
                 }
                 self.eat(Syntax::Semi);
                 self.expect(Syntax::RCurly)?;
+                self.node(NodeKind::Map, start);
                 Ok(Stmt::Map(map))
             }
             _ => self.error("Atom"),
@@ -502,15 +659,19 @@ impl<'s, 't> Parser<'s, 't> {
     ///   or
     /// - to the next ; if the next() char isn't an Indent
     fn block(&mut self) -> Result<Vec<Stmt>> {
+        let start = self.pos;
         let mut block = vec![];
 
         // one line block via `do`
         if self.peek_is(Syntax::Do) {
             self.skip();
-            return Ok(vec![self.stmt()?]);
+            block.push(self.stmt()?);
+            self.node(NodeKind::Body, start);
+            return Ok(block);
         }
 
         self.expect(Syntax::Indent)?;
+        let depth = self.depth;
         while !self.peek_eof() {
             match self.peek_kind() {
                 // keep going if we're indented
@@ -520,16 +681,18 @@ impl<'s, 't> Parser<'s, 't> {
                 Syntax::Dedent => break,
 
                 // everything else is a stmt
-                _ => block.push(self.stmt()?),
+                _ => block.extend(self.recoverable_stmt(depth)),
             };
         }
         self.expect(Syntax::Dedent)?;
+        self.node(NodeKind::Body, start);
 
         Ok(block)
     }
 
     /// Parse the innerHTML of a <tag>. Code and stuff.
     fn tag_body(&mut self) -> Result<Vec<Stmt>> {
+        let start = self.pos;
         let mut block = vec![];
         let mut indented = false;
 
@@ -537,6 +700,7 @@ impl<'s, 't> Parser<'s, 't> {
             self.skip();
             indented = true;
         }
+        let depth = self.depth;
 
         while !self.peek_eof() {
             match self.peek_kind() {
@@ -554,6 +718,7 @@ impl<'s, 't> Parser<'s, 't> {
 
                 // two words in a row become text in tag bodies
                 Syntax::Word | Syntax::Number if self.two_words_in_a_row() => {
+                    let text_start = self.pos;
                     let mut out = self.next().to_string();
                     while !self.peek_eof() {
                         match self.peek_kind() {
@@ -563,13 +728,16 @@ impl<'s, 't> Parser<'s, 't> {
                         }
                         out.push_str(self.next().to_str())
                     }
+                    self.node(NodeKind::Text, text_start);
                     block.push(out.into());
                 }
 
                 // everything else is a stmt
+                _ if indented => block.extend(self.recoverable_stmt(depth)),
                 _ => block.push(self.stmt()?),
             };
         }
+        self.node(NodeKind::TagBody, start);
 
         Ok(block)
     }
@@ -589,9 +757,35 @@ impl<'s, 't> Parser<'s, 't> {
 
     /// Parse a single statement.
     fn stmt(&mut self) -> Result<Stmt> {
-        match self.peek_kind() {
+        let start = self.pos;
+        let kind = match self.peek_kind() {
+            Syntax::Def => Some(NodeKind::Def),
+            Syntax::Block => Some(NodeKind::Block),
+            Syntax::Extends => Some(NodeKind::Extends),
+            Syntax::Import => Some(NodeKind::Import),
+            Syntax::If => Some(NodeKind::If),
+            Syntax::For => Some(NodeKind::For),
+            Syntax::While => Some(NodeKind::While),
+            Syntax::Return => Some(NodeKind::Return),
+            _ => None,
+        };
+        let stmt = match self.peek_kind() {
             // Keyword
             Syntax::Def => self.def_stmt(),
+            Syntax::Block => self.block_stmt(),
+            Syntax::Extends => {
+                self.skip();
+                Ok(Stmt::Extends(bx!(self.expr()?)))
+            }
+            // `import "file.hat"` is sugar for `import("file.hat")`
+            Syntax::Import => {
+                let span = self.next().span();
+                Ok(Stmt::Call(
+                    bx!(Stmt::Word("import".into(), span)),
+                    vec![self.expr()?],
+                    span,
+                ))
+            }
             Syntax::If => self.if_stmt(),
             Syntax::For => self.for_stmt(),
             Syntax::While => {
@@ -599,11 +793,11 @@ impl<'s, 't> Parser<'s, 't> {
                 Ok(Stmt::While(bx!(self.expr()?), self.block()?))
             }
             Syntax::Return => {
-                self.skip();
+                let span = self.next().span();
                 let ret = if self.peek_is(Syntax::Semi) {
-                    Stmt::Return(bx!(Stmt::None))
+                    Stmt::Return(bx!(Stmt::None), span)
                 } else {
-                    Stmt::Return(bx!(self.expr()?))
+                    Stmt::Return(bx!(self.expr()?), span)
                 };
                 Ok(ret)
             }
@@ -613,7 +807,11 @@ impl<'s, 't> Parser<'s, 't> {
 
             // Unexpected
             _ => self.error("Stmt"),
+        }?;
+        if let Some(kind) = kind {
+            self.node(kind, start);
         }
+        Ok(stmt)
     }
 
     /// Parse a `for` statement:
@@ -641,14 +839,21 @@ impl<'s, 't> Parser<'s, 't> {
         Ok(Stmt::For(key, val, bx!(iter), body))
     }
 
-    /// Parse a function definition.
+    /// Parse a function definition. `def <card(title)>` defines a
+    /// tag instead, which is stored as `<card>` so it can't clash
+    /// with regular variables.
     fn def_stmt(&mut self) -> Result<Stmt> {
         self.expect(Syntax::Def)?;
-        let name = match self.peek_kind() {
-            Syntax::Word | Syntax::Op => self.next(),
-            _ => return self.error("function name"),
+        let is_tag = self.peek_is(Syntax::LCaret);
+        if is_tag {
+            self.skip();
         }
-        .to_sym();
+        let tok = match self.peek_kind() {
+            Syntax::Word => self.next(),
+            Syntax::Op if !is_tag => self.next(),
+            _ => return self.error("function name"),
+        };
+        let (mut name, span) = (tok.to_sym(), tok.span());
 
         let mut args = vec![];
         if self.peek_is(Syntax::LParen) {
@@ -666,8 +871,27 @@ impl<'s, 't> Parser<'s, 't> {
             self.expect(Syntax::RParen)?;
         }
 
+        if is_tag {
+            self.expect(Syntax::RCaret)?;
+            name = format!("<{}>", name).into();
+        }
+
         let body = self.block()?;
-        Ok(Stmt::Assign(name, bx!(Stmt::Fn(args, body)), false))
+        Ok(Stmt::Assign(name, bx!(Stmt::Fn(args, body)), false, span))
+    }
+
+    /// Parse a named block, which a layout fills with its default
+    /// body and a child template overrides:
+    ///     block title do "Default Title"
+    fn block_stmt(&mut self) -> Result<Stmt> {
+        self.expect(Syntax::Block)?;
+        let name = self.expect(Syntax::Word)?.to_sym();
+        let body = if matches!(self.peek_kind(), Syntax::Indent | Syntax::Do) {
+            self.block()?
+        } else {
+            vec![]
+        };
+        Ok(Stmt::Block(name, body))
     }
 
     /// Parse an if statement.
@@ -714,12 +938,14 @@ impl<'s, 't> Parser<'s, 't> {
     /// Parse a <tag> and its contents or a </tag>.
     fn tag(&mut self) -> Result<Stmt> {
         if self.peek2_is(Syntax::Slash) {
-            self.close_tag()?;
+            self.close_tag(None)?;
             return Ok(Stmt::None);
         }
 
+        let start = self.pos;
         let mut tag = self.open_tag()?;
         if tag.is_closed() {
+            self.node(NodeKind::Tag, start);
             return Ok(Stmt::Tag(tag));
         }
 
@@ -739,48 +965,87 @@ impl<'s, 't> Parser<'s, 't> {
                 self.tags -= 1;
                 self.skip();
             }
-            _ => self.close_tag()?,
+            _ => {
+                let name = match &*tag.tag {
+                    Stmt::Value(Value::String(name)) => Some(name.to_string()),
+                    _ => None,
+                };
+                self.close_tag(name.as_deref())?
+            }
         }
+        self.node(NodeKind::Tag, start);
 
         Ok(Stmt::Tag(tag))
     }
 
-    /// Parse just a closing tag, starting after the <
-    fn close_tag(&mut self) -> Result<()> {
+    /// Parse just a closing tag, starting after the <. If we know
+    /// the `open` tag's name, the closing tag has to match it.
+    fn close_tag(&mut self, open: Option<&str>) -> Result<()> {
         if self.tags == 0 {
             return self.error("Open Tag");
         }
         self.tags -= 1;
+        let start = self.pos;
         self.expect(Syntax::LCaret)?;
         self.expect(Syntax::Slash)?;
         // </>
         if self.peek_is(Syntax::RCaret) {
             self.skip();
+            self.node(NodeKind::CloseTag, start);
             return Ok(());
         }
-        self.expect(Syntax::Word)?;
+        let word = self.expect(Syntax::Word)?;
+        if let Some(open) = open.filter(|open| *open != word.literal()) {
+            return Err(Error::new(
+                ErrorKind::UnclosedTag {
+                    tag: open.to_string(),
+                },
+                format!("expected </{}>, got </{}>", open, word.literal()),
+                word.pos,
+                word.len,
+            ));
+        }
         self.expect(Syntax::RCaret)?;
+        self.node(NodeKind::CloseTag, start);
         Ok(())
     }
 
     /// Parse a string <opening.tag with=attributes>
     /// starting after the <
     fn open_tag(&mut self) -> Result<Tag> {
+        let start = self.pos;
         self.tags += 1;
         self.expect(Syntax::LCaret)?;
+        let span = self.peek().map(|t| t.span()).unwrap_or_default();
         let mut tag = Tag::new(match self.peek_kind() {
             Syntax::Op => Stmt::Value("div".into()),
             _ => Stmt::Value(self.expect(Syntax::Word)?.to_sym().into()),
         });
+        tag.span = span;
 
         // <#shortcuts.only.work.in@the:first-part-of-the-tag gotcha=true/>
         let mut head = true;
         loop {
+            if self.peek_eof() {
+                let name = match &*tag.tag {
+                    Stmt::Value(v) => v.to_string(),
+                    _ => String::new(),
+                };
+                return Err(Error::new(
+                    ErrorKind::UnclosedTag { tag: name.clone() },
+                    format!("unclosed tag: <{}", name),
+                    span.pos,
+                    span.len,
+                ));
+            }
             let next = self.next();
-            let pos = next.pos;
+            let (pos, span) = (next.pos, next.span());
             match next.kind {
                 Syntax::Semi => {}
                 Syntax::RCaret => break,
+                Syntax::Slash if tag.is_closed() => {
+                    return pos_error!(pos, "tag is already closed");
+                }
                 Syntax::Slash => {
                     tag.close();
                     self.tags -= 1;
@@ -791,7 +1056,11 @@ impl<'s, 't> Parser<'s, 't> {
                         if self.peek_is(Syntax::Equal) {
                             self.skip();
                             let cond = self.attr()?;
-                            tag.set_id(Stmt::Call(bx!(Stmt::Word("when".into())), vec![cond, id]));
+                            tag.set_id(Stmt::Call(
+                                bx!(Stmt::Word("when".into(), span)),
+                                vec![cond, id],
+                                span,
+                            ));
                         } else {
                             tag.set_id(id);
                         }
@@ -802,8 +1071,9 @@ impl<'s, 't> Parser<'s, 't> {
                             self.skip();
                             let cond = self.attr()?;
                             tag.add_class(Stmt::Call(
-                                bx!(Stmt::Word("when".into())),
+                                bx!(Stmt::Word("when".into(), span)),
                                 vec![cond, class],
+                                span,
                             ));
                         } else {
                             tag.add_class(class);
@@ -821,7 +1091,11 @@ impl<'s, 't> Parser<'s, 't> {
                             let cond = self.attr()?;
                             tag.add_attr(
                                 attr_name,
-                                Stmt::Call(bx!(Stmt::Word("when".into())), vec![cond, expr]),
+                                Stmt::Call(
+                                    bx!(Stmt::Word("when".into(), span)),
+                                    vec![cond, expr],
+                                    span,
+                                ),
                             );
                         } else {
                             tag.add_attr(attr_name, expr);
@@ -870,6 +1144,7 @@ impl<'s, 't> Parser<'s, 't> {
                 _ => return pos_error!(pos, "Expected Attribute or >, got {:?}", next),
             }
         }
+        self.node(NodeKind::OpenTag, start);
 
         Ok(tag)
     }
@@ -0,0 +1,236 @@
+//! The resolver keeps track of where each variable in a template or
+//! function is declared while it's being lowered to bytecode, so the
+//! VM can keep locals in numbered slots instead of looking them up by
+//! name.
+//!
+//! Hatter is dynamically scoped: a function can see the variables of
+//! whoever called it. Only names declared in the code being lowered
+//! become `Local`s. Everything else is `Global` and is looked up by
+//! name at runtime, same as the tree-walker.
+
+use {
+    crate::{Stmt, Symbol},
+    std::collections::HashMap,
+};
+
+/// A variable declared in the code being lowered: `depth` scopes out
+/// from where it's used, in slot `slot` of that scope. `index` is its
+/// slot in the whole function, which is what the VM uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Local {
+    pub depth: u32,
+    pub slot: u32,
+    pub index: u32,
+}
+
+/// What a name refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+    Local(Local),
+    Global, // looked up by name
+}
+
+/// Names declared in one lexical scope, with their slot in it and
+/// their index in the function.
+#[derive(Default)]
+struct Scope {
+    names: HashMap<Symbol, (u32, u32)>,
+}
+
+/// The scopes around the code being lowered. The lowerer opens and
+/// closes them as it walks the tree, declaring and finding names in
+/// the same order the tree-walker would.
+pub struct Resolver {
+    slotted: bool,
+    slots: Vec<Symbol>,
+    scopes: Vec<Scope>,
+}
+
+impl Resolver {
+    /// Resolver for a template. Its top level is always `Global`, so
+    /// whoever rendered it can see them when it's done.
+    pub fn new(stmts: &[Stmt]) -> Resolver {
+        Resolver {
+            slotted: stmts.iter().all(clean),
+            slots: vec![],
+            scopes: vec![],
+        }
+    }
+
+    /// Resolver for a function's body, with its params declared.
+    pub fn function(params: &[Symbol], body: &[Stmt]) -> (Resolver, Vec<Binding>) {
+        let mut res = Resolver::new(body);
+        if res.slotted {
+            res.scopes.push(Scope::default());
+        }
+        let params = params.iter().map(|p| res.declare(p)).collect();
+        (res, params)
+    }
+
+    /// Are this code's variables in slots? Not if it makes closures,
+    /// which capture scopes by name, or has `block`s or `extends`,
+    /// which the tree-walker runs.
+    pub fn slotted(&self) -> bool {
+        self.slotted
+    }
+
+    /// Name of every slot declared so far, by index.
+    pub fn slots(&self) -> &[Symbol] {
+        &self.slots
+    }
+
+    /// Number of the next slot.
+    pub fn next(&self) -> u32 {
+        self.slots.len() as u32
+    }
+
+    /// Declare a variable in the current scope. A name that's already
+    /// there keeps its slot.
+    pub fn declare(&mut self, name: &Symbol) -> Binding {
+        let index = self.next();
+        let Some(scope) = self.scopes.last_mut() else {
+            return Binding::Global;
+        };
+        let (slot, index) = match scope.names.get(name) {
+            Some(found) => *found,
+            None => {
+                let slot = scope.names.len() as u32;
+                scope.names.insert(name.clone(), (slot, index));
+                self.slots.push(name.clone());
+                (slot, index)
+            }
+        };
+        Binding::Local(Local {
+            depth: 0,
+            slot,
+            index,
+        })
+    }
+
+    /// Find a variable, starting in the innermost scope.
+    pub fn find(&self, name: &Symbol) -> Binding {
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(&(slot, index)) = scope.names.get(name) {
+                return Binding::Local(Local {
+                    depth: depth as u32,
+                    slot,
+                    index,
+                });
+            }
+        }
+        Binding::Global
+    }
+
+    /// Start a new scope, like an `if` branch or a loop, returning
+    /// its first slot.
+    pub fn open(&mut self) -> u32 {
+        if self.slotted {
+            self.scopes.push(Scope::default());
+        }
+        self.next()
+    }
+
+    /// End the scope that started at `start`, returning the slots
+    /// declared in it.
+    pub fn close(&mut self, start: u32) -> (u32, u32) {
+        if self.slotted {
+            self.scopes.pop();
+        }
+        (start, self.next())
+    }
+}
+
+/// Can this code's variables live in slots?
+fn clean(stmt: &Stmt) -> bool {
+    let all = |stmts: &[Stmt]| stmts.iter().all(clean);
+    match stmt {
+        Stmt::Fn(..) | Stmt::Block(..) | Stmt::Extends(..) => false,
+        Stmt::None | Stmt::Value(..) | Stmt::Word(..) => true,
+        Stmt::List(list) => all(list),
+        Stmt::Map(pairs) | Stmt::Args(pairs) => pairs.iter().all(|(_, v)| clean(v)),
+        Stmt::Call(target, args, _) => clean(target) && all(args),
+        Stmt::Return(expr, _) => clean(expr),
+        Stmt::If(conds) => conds.iter().all(|(test, body)| clean(test) && all(body)),
+        Stmt::For(_, _, iter, body) => clean(iter) && all(body),
+        Stmt::While(test, body) => clean(test) && all(body),
+        Stmt::Assign(_, expr, _, _) => clean(expr),
+        Stmt::Tag(tag) => {
+            clean(&tag.tag)
+                && clean(&tag.id)
+                && all(&tag.classes)
+                && tag.attrs.iter().all(|(k, v)| clean(k) && clean(v))
+                && all(&tag.body)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, crate::compile};
+
+    fn local(depth: u32, slot: u32, index: u32) -> Binding {
+        Binding::Local(Local { depth, slot, index })
+    }
+
+    #[test]
+    fn test_resolve_globals() {
+        let stmts = compile("x := 1\nx + y").unwrap();
+        let mut res = Resolver::new(&stmts);
+        assert!(res.slotted());
+        assert_eq!(Binding::Global, res.declare(&"x".into()));
+        assert_eq!(Binding::Global, res.find(&"x".into()));
+        assert!(res.slots().is_empty());
+    }
+
+    #[test]
+    fn test_resolve_locals() {
+        // def f(a)
+        //   if a
+        //     b := 1
+        //     b + a
+        //   a
+        let (a, b) = (Symbol::from("a"), Symbol::from("b"));
+        let (mut res, params) = Resolver::function(std::slice::from_ref(&a), &[]);
+        assert!(res.slotted());
+        assert_eq!(vec![local(0, 0, 0)], params);
+
+        let start = res.open();
+        assert_eq!(local(0, 0, 1), res.declare(&b));
+        assert_eq!(local(0, 0, 1), res.find(&b));
+        assert_eq!(local(1, 0, 0), res.find(&a));
+        assert_eq!((1, 2), res.close(start));
+
+        assert_eq!(local(0, 0, 0), res.find(&a));
+        assert_eq!(Binding::Global, res.find(&b));
+        assert_eq!(&[a, b], res.slots());
+    }
+
+    #[test]
+    fn test_resolve_loops() {
+        // def f(xs)
+        //   for i, x in xs
+        //     x := x
+        let (i, x) = (Symbol::from("i"), Symbol::from("x"));
+        let (mut res, _) = Resolver::function(&["xs".into()], &[]);
+        let start = res.open();
+        assert_eq!(local(0, 0, 1), res.declare(&i));
+        assert_eq!(local(0, 1, 2), res.declare(&x));
+        // declaring it again keeps its slot
+        assert_eq!(local(0, 1, 2), res.declare(&x));
+        assert_eq!((1, 3), res.close(start));
+        assert_eq!(Binding::Global, res.find(&i));
+    }
+
+    #[test]
+    fn test_resolve_unslotted() {
+        // closures capture scopes by name, so nothing goes in slots
+        let body = compile("g := fn() a\na").unwrap();
+        let (mut res, params) = Resolver::function(&["a".into()], &body);
+        assert!(!res.slotted());
+        assert_eq!(vec![Binding::Global], params);
+        let start = res.open();
+        assert_eq!(Binding::Global, res.declare(&"b".into()));
+        assert_eq!((0, 0), res.close(start));
+        assert!(res.slots().is_empty());
+    }
+}
@@ -1,22 +1,39 @@
 use crate::{Symbol, Tag, Value};
 
+/// Where a Stmt came from in the source code, so runtime errors can
+/// point at it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub pos: usize,
+    pub len: usize,
+}
+
+impl Span {
+    /// Create a Span.
+    pub fn new(pos: usize, len: usize) -> Span {
+        Span { pos, len }
+    }
+}
+
 /// Stmt is an AST node.
 #[derive(Debug, Clone, PartialEq)]
 pub enum Stmt {
     None,
     Value(Value),
-    Word(Symbol),
+    Word(Symbol, Span),
     List(Vec<Stmt>),
     Map(Vec<(Symbol, Stmt)>),
-    Call(Box<Stmt>, Vec<Stmt>), // fn, args
-    Return(Box<Stmt>),
+    Call(Box<Stmt>, Vec<Stmt>, Span), // fn, args
+    Return(Box<Stmt>, Span),
     If(Vec<(Stmt, Vec<Stmt>)>),
     For(Option<Symbol>, Symbol, Box<Stmt>, Vec<Stmt>), // key, val, iter, body
     While(Box<Stmt>, Vec<Stmt>),
-    Assign(Symbol, Box<Stmt>, bool), // var, val, reassign?
+    Assign(Symbol, Box<Stmt>, bool, Span), // var, val, reassign?
     Tag(Tag),
     Fn(Vec<Symbol>, Vec<Stmt>), // args, body
     Args(Vec<(Symbol, Stmt)>),  // keyword args
+    Block(Symbol, Vec<Stmt>),   // name, default body
+    Extends(Box<Stmt>),         // layout
 }
 
 impl From<Tag> for Stmt {
@@ -48,11 +65,77 @@ impl Stmt {
         matches!(self, Stmt::None)
     }
 
+    /// Where this Stmt is in the source, if we know.
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::Word(_, span)
+            | Stmt::Call(_, _, span)
+            | Stmt::Return(_, span)
+            | Stmt::Assign(_, _, _, span) => *span,
+            Stmt::Tag(tag) => tag.span,
+            _ => Span::default(),
+        }
+    }
+
+    /// Reset every `Span` in this Stmt to the default, so Stmts
+    /// compiled from differently laid out source compare equal.
+    pub fn clear_spans(&mut self) {
+        let clear = |stmts: &mut Vec<Stmt>| stmts.iter_mut().for_each(Stmt::clear_spans);
+        match self {
+            Stmt::None | Stmt::Value(..) => {}
+            Stmt::Word(_, span) => *span = Span::default(),
+            Stmt::List(items) => clear(items),
+            Stmt::Map(pairs) | Stmt::Args(pairs) => {
+                pairs.iter_mut().for_each(|(_, val)| val.clear_spans())
+            }
+            Stmt::Call(target, args, span) => {
+                target.clear_spans();
+                clear(args);
+                *span = Span::default();
+            }
+            Stmt::Return(expr, span) => {
+                expr.clear_spans();
+                *span = Span::default();
+            }
+            Stmt::If(conds) => {
+                for (test, body) in conds {
+                    test.clear_spans();
+                    clear(body);
+                }
+            }
+            Stmt::For(_, _, iter, body) => {
+                iter.clear_spans();
+                clear(body);
+            }
+            Stmt::While(test, body) => {
+                test.clear_spans();
+                clear(body);
+            }
+            Stmt::Assign(_, val, _, span) => {
+                val.clear_spans();
+                *span = Span::default();
+            }
+            Stmt::Tag(tag) => {
+                tag.tag.clear_spans();
+                tag.id.clear_spans();
+                clear(&mut tag.classes);
+                for (name, val) in &mut tag.attrs {
+                    name.clear_spans();
+                    val.clear_spans();
+                }
+                clear(&mut tag.body);
+                tag.span = Span::default();
+            }
+            Stmt::Fn(_, body) | Stmt::Block(_, body) => clear(body),
+            Stmt::Extends(expr) => expr.clear_spans(),
+        }
+    }
+
     /// If this is a String or a Word, get a &str of its literal value.
     pub fn to_str(&self) -> &str {
         match self {
             Stmt::Value(Value::String(s)) => s.to_str(),
-            Stmt::Word(s) => s.to_str(),
+            Stmt::Word(s, _) => s.to_str(),
             _ => "",
         }
     }
@@ -63,9 +146,9 @@ impl Stmt {
             Stmt::None => "Stmt::None".to_string(),
             Stmt::Value(Value::String(s)) => format!(r#""{}""#, s),
             Stmt::Value(v) => format!("{}", v),
-            Stmt::Word(s) => s.to_string(),
+            Stmt::Word(s, _) => s.to_string(),
             Stmt::Tag(tag) => format!("{:?}", tag),
-            Stmt::Return(ex) => format!("return {:?}", ex),
+            Stmt::Return(ex, _) => format!("return {:?}", ex),
             Stmt::Args(args) => args
                 .iter()
                 .map(|(k, v)| format!("{}: {:?}", k, v))
@@ -81,18 +164,20 @@ impl Stmt {
             Stmt::Map(map) => format!(
                 "{{{}}}",
                 map.iter()
-                    .map(|(k, v)| format!("{}: {}", k.to_string(), v.to_string()))
+                    .map(|(k, v)| format!("{}: {}", k, v.to_string()))
                     .collect::<Vec<_>>()
                     .join(", ")
             ),
-            Stmt::Assign(var, expr, re) => {
+            Stmt::Assign(var, expr, re, _) => {
                 format!("{} {}= {:?}", var, if *re { ":" } else { "" }, expr)
             }
             Stmt::If(..) => "IF: Coming Soon™".to_string(),
             Stmt::For(..) => "FOR: Coming Soon™".to_string(),
             Stmt::While(test, body) => format!("while({:?}) {:?}", test, body),
             Stmt::Fn(args, body) => format!("fn({:?}) {:?}", args, body),
-            Stmt::Call(name, args) => format!(
+            Stmt::Block(name, body) => format!("block {} {:?}", name, body),
+            Stmt::Extends(layout) => format!("extends {}", layout.to_string()),
+            Stmt::Call(name, args, _) => format!(
                 "{}({})",
                 name.to_string(),
                 args.iter()
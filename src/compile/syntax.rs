@@ -23,6 +23,8 @@ pub enum Syntax {
     RCaret,       // >
     Slash,        // / (only in <tags>)
     Equal,        // = (only in <tags>)
+    Comment,      // # comment (only from `scan_with_comments()`)
+    Whitespace,   // spaces and newlines (only from `scan_lossless()`)
 
     // Keywords
     Def,
@@ -35,6 +37,9 @@ pub enum Syntax {
     While,
     In,
     Fn,
+    Block,
+    Extends,
+    Import,
 }
 
 impl Syntax {
@@ -51,10 +56,11 @@ impl Syntax {
     pub fn is_word_or_keyword(&self) -> bool {
         use Syntax::*;
         match self {
-            Word | Def | Do | Return | If | Then | Else | For | While | In | Fn => true,
+            Word | Def | Do | Return | If | Then | Else | For | While | In | Fn | Block
+            | Extends | Import => true,
             None | Indent | Dedent | Bool(..) | Number | String(..) | JS | Op | Semi | Colon
             | Comma | LParen | RParen | LCurly | RCurly | LStaple | RStaple | LCaret | RCaret
-            | Slash | Equal => false,
+            | Slash | Equal | Comment | Whitespace => false,
         }
     }
 }
@@ -1,16 +1,32 @@
-use crate::Stmt;
+use {
+    crate::{Span, Stmt, Symbol, Value},
+    std::sync::{Arc, OnceLock},
+};
 
 /// Since HTML tags are a bit more involved than IF statements or
 /// variable assignment, they have their own data structure. This is
 /// still basically just a Stmt, though.
 #[derive(Debug, Clone)]
 pub struct Tag {
-    pub tag: Box<Stmt>,           // tag name
-    pub id: Box<Stmt>,            // tag id
-    pub classes: Vec<Stmt>,       // classes
-    pub attrs: Vec<(Stmt, Stmt)>, // other attributes
-    pub body: Vec<Stmt>,          // Bunch o' expressions
-    pub closed: bool,             // <self-closing/> ?
+    pub tag: Box<Stmt>,                 // tag name
+    pub id: Box<Stmt>,                  // tag id
+    pub classes: Vec<Stmt>,             // classes
+    pub attrs: Vec<(Stmt, Stmt)>,       // other attributes
+    pub body: Vec<Stmt>,                // Bunch o' expressions
+    pub closed: bool,                   // <self-closing/> ?
+    pub span: Span,                     // where the tag name is
+    pub html: Option<Arc<Prerendered>>, // set by optimize()
+    custom: OnceLock<Option<Symbol>>,   // "<name>", if the name is a literal
+}
+
+/// The HTML of a tag with nothing dynamic in it, rendered ahead of
+/// time by `optimize()`. It's only printed if none of the names in
+/// `unset` have been defined, since a custom tag or a variable with
+/// one of them would change what the tag prints.
+#[derive(Debug)]
+pub struct Prerendered {
+    pub html: String,
+    pub unset: Vec<Symbol>,
 }
 
 impl PartialEq for Tag {
@@ -20,6 +36,7 @@ impl PartialEq for Tag {
             && self.classes == other.classes
             && self.body == other.body
             && self.closed == other.closed
+            && self.span == other.span
             && self.attrs.iter().all(|(k, v)| {
                 if let Some((_, o)) = other.attrs.iter().find(|(ok, _)| k == ok) {
                     o == v
@@ -40,9 +57,24 @@ impl Tag {
             attrs: vec![],
             closed: false,
             body: vec![],
+            span: Span::default(),
+            html: None,
+            custom: OnceLock::new(),
         }
     }
 
+    /// The name a custom version of this tag would be defined as,
+    /// `"<name>"`, if its name is a literal. It's only worked out
+    /// once, so printing the tag doesn't have to.
+    pub fn custom_name(&self) -> Option<&Symbol> {
+        self.custom
+            .get_or_init(|| match &*self.tag {
+                Stmt::Value(Value::String(name)) => Some(format!("<{}>", name).into()),
+                _ => None,
+            })
+            .as_ref()
+    }
+
     /// Is this a <self-closing/> tag?
     pub fn is_closed(&self) -> bool {
         self.closed
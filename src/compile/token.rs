@@ -1,4 +1,4 @@
-use crate::{Error, ErrorKind, Result, Symbol, Syntax};
+use crate::{Error, ErrorKind, Result, Span, Symbol, Syntax};
 
 /// A Token is an element of source code, a `Syntax` and its position.
 #[derive(Debug, Clone, Copy)]
@@ -20,6 +20,11 @@ impl<'s> Token<'s> {
         }
     }
 
+    /// Where this token is in the source.
+    pub fn span(&self) -> Span {
+        Span::new(self.pos, self.len)
+    }
+
     /// Will it produce a `literal()`?
     pub fn is_literal(&self) -> bool {
         use Syntax::*;
@@ -27,7 +32,8 @@ impl<'s> Token<'s> {
         match self.kind {
             None | Bool(..) | Number | String(..) | Word | JS | Op | Colon | Comma | LParen
             | RParen | LCurly | RCurly | LStaple | RStaple | LCaret | RCaret | Slash | Equal
-            | Def | Do | Return | If | Then | Else | For | While | In | Fn => true,
+            | Def | Do | Return | If | Then | Else | For | While | In | Fn | Block | Extends
+            | Import | Comment | Whitespace => true,
             Indent | Dedent | Semi => false,
         }
     }
@@ -41,6 +47,12 @@ impl<'s> Token<'s> {
         }
     }
 
+    /// The source code the lexer saved for this token, even if it
+    /// doesn't have a `literal()`, like a `;` from `scan_lossless()`.
+    pub fn text(&self) -> &'s str {
+        self.lit
+    }
+
     /// Also...
     pub fn to_str(&self) -> &str {
         self.literal()
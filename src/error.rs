@@ -1,29 +1,105 @@
 #![allow(unused_macros)]
 use {
-    crate::Jump,
-    std::{error, fmt, io, num},
+    crate::{FileLoader, Loader, Span, Symbol},
+    std::{
+        error, fmt, io, num,
+        path::{Path, PathBuf},
+    },
 };
 
-/// What kind of error?
-#[derive(Debug, PartialEq)]
+/// What kind of error? Kinds with fields carry what went wrong in a
+/// form tools can match on, while `Error.details` is the message for
+/// humans.
+#[derive(Debug, Clone, PartialEq)]
 pub enum ErrorKind {
+    // compile time
     SyntaxError,
     ParseError,
+    IndentationError,
+    UnclosedString,
+    UnclosedTag { tag: String },
+    UnexpectedToken { expected: String, actual: String },
+
+    // run time
     RuntimeError,
+    IoError,
+    TemplateNotFound { name: String },
+    IncludeCycle { name: String },
+    UndefinedVariable { name: String },
+    AlreadyDefined { name: String },
+    NotCallable { name: String },
+    ArgNotFound { index: usize },
+    ArityMismatch { expected: usize, actual: usize },
+    TypeMismatch { expected: String, actual: String },
+    UnknownAttribute { tag: String, attr: String },
+    UnexpectedJump { keyword: String },
+}
 
-    ArgNotFound,
-    WrongArgType,
+impl ErrorKind {
+    /// Name of the kind, without its fields.
+    pub fn name(&self) -> &'static str {
+        use ErrorKind::*;
+        match self {
+            SyntaxError => "SyntaxError",
+            ParseError => "ParseError",
+            IndentationError => "IndentationError",
+            UnclosedString => "UnclosedString",
+            UnclosedTag { .. } => "UnclosedTag",
+            UnexpectedToken { .. } => "UnexpectedToken",
+            RuntimeError => "RuntimeError",
+            IoError => "IoError",
+            TemplateNotFound { .. } => "TemplateNotFound",
+            IncludeCycle { .. } => "IncludeCycle",
+            UndefinedVariable { .. } => "UndefinedVariable",
+            AlreadyDefined { .. } => "AlreadyDefined",
+            NotCallable { .. } => "NotCallable",
+            ArgNotFound { .. } => "ArgNotFound",
+            ArityMismatch { .. } => "ArityMismatch",
+            TypeMismatch { .. } => "TypeMismatch",
+            UnknownAttribute { .. } => "UnknownAttribute",
+            UnexpectedJump { .. } => "UnexpectedJump",
+        }
+    }
 
-    Jump(Jump),
+    /// Did this happen while compiling, rather than running?
+    pub fn is_compile_error(&self) -> bool {
+        use ErrorKind::*;
+        matches!(
+            self,
+            SyntaxError
+                | ParseError
+                | IndentationError
+                | UnclosedString
+                | UnclosedTag { .. }
+                | UnexpectedToken { .. }
+        )
+    }
 }
 
 /// Usually source-related.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Error {
     pub kind: ErrorKind,
     pub details: String,
     pub pos: usize,
     pub len: usize,
+    trace: Option<Box<Trace>>, // boxed to keep `Result`s small
+}
+
+/// Where an error happened, beyond its position.
+#[derive(Debug, Clone, PartialEq, Default)]
+struct Trace {
+    path: Option<String>, // template `pos` is in, if not the main one
+    stack: Vec<Frame>,    // calls we were in, innermost first
+}
+
+/// A function call an error happened inside of.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub name: String,         // fn or <tag> name
+    pub pos: usize,           // where it was called
+    pub len: usize,           //
+    pub path: Option<String>, // template it was called from, if not the main one
 }
 
 impl Error {
@@ -33,8 +109,48 @@ impl Error {
             details,
             pos,
             len,
+            trace: None,
         }
     }
+
+    /// The template `pos` is in, or None for the main one.
+    pub fn path(&self) -> Option<&str> {
+        self.trace.as_ref()?.path.as_deref()
+    }
+
+    /// The calls this error happened inside of, innermost first.
+    pub fn stack(&self) -> &[Frame] {
+        self.trace.as_ref().map_or(&[], |t| &t.stack)
+    }
+
+    /// Say which template this error happened in.
+    pub(crate) fn set_path(&mut self, path: Option<String>) {
+        self.trace.get_or_insert_with(Default::default).path = path;
+    }
+
+    /// Add a call it happened inside of, outside the ones we have.
+    pub(crate) fn push_frame(&mut self, frame: Frame) {
+        self.trace
+            .get_or_insert_with(Default::default)
+            .stack
+            .push(frame);
+    }
+
+    /// Does this error know where it happened yet?
+    pub(crate) fn has_pos(&self) -> bool {
+        self.pos != 0 || self.len != 0 || self.path().is_some()
+    }
+
+    /// Give this error a position in the template `path` (None for
+    /// the main one) if it doesn't have one yet.
+    pub(crate) fn at(mut self, span: Span, path: Option<&Symbol>) -> Error {
+        if !self.has_pos() {
+            self.pos = span.pos;
+            self.len = span.len;
+            self.set_path(path.map(|p| p.to_string()));
+        }
+        self
+    }
 }
 
 impl error::Error for Error {
@@ -56,6 +172,7 @@ impl From<num::ParseIntError> for Error {
             details: format!("{}", error),
             pos: 0,
             len: 0,
+            trace: None,
         }
     }
 }
@@ -63,17 +180,18 @@ impl From<num::ParseIntError> for Error {
 impl From<io::Error> for Error {
     fn from(error: io::Error) -> Self {
         Error {
-            kind: ErrorKind::RuntimeError,
+            kind: ErrorKind::IoError,
             details: format!("{}", error),
             pos: 0,
             len: 0,
+            trace: None,
         }
     }
 }
 
 impl From<Error> for io::Error {
     fn from(error: Error) -> Self {
-        io::Error::new(io::ErrorKind::Other, error.details)
+        io::Error::other(error.details)
     }
 }
 
@@ -84,7 +202,7 @@ macro_rules! scan_error {
         Err(Error::new(ErrorKind::SyntaxError, $msg.into(), $pos, $len))
     }};
     ($pos:expr, $len:expr, $msg:expr, $($args:expr),+) => {
-        scan_error!($pos, $len, format!($msg, $($args),*));
+        scan_error!($pos, $len, format!($msg, $($args),*))
     };
 }
 
@@ -95,26 +213,24 @@ macro_rules! pos_error {
         Err(Error::new(ErrorKind::ParseError, $msg.into(), $pos, 1))
     }};
     ($pos:expr, $msg:expr, $($args:expr),+) => {
-        pos_error!($pos, format!($msg, $($args),+));
+        pos_error!($pos, format!($msg, $($args),+))
     };
 }
 
-/// Create a Jump (break, continue, etc)
-macro_rules! jump {
-    ($jump:expr) => {{
+/// Convenient way to create an error of ErrorKind, with or without
+/// fields: `error_kind!(UndefinedVariable { name }, "can't find var")`
+macro_rules! error_kind {
+    ($kind:ident { $($field:ident $(: $val:expr)?),* $(,)? }, $msg:expr $(, $args:expr)* $(,)?) => {{
         use crate::{Error, ErrorKind};
-        Err(Error::new(ErrorKind::Jump($jump), "".to_string(), 0, 0))
+        let details = format!($msg $(, $args)*);
+        Error::new(ErrorKind::$kind { $($field $(: $val)?),* }, details, 0, 0)
     }};
-}
-
-/// Convenient way to create an error of ErrorKind.
-macro_rules! error_kind {
     ($kind:ident, $msg:expr) => {{
         use crate::{Error, ErrorKind};
         Error::new(ErrorKind::$kind, $msg.into(), 0, 0)
     }};
     ($kind:ident, $msg:expr, $($args:expr),*) => {
-        error_kind!($kind, format!($msg, $($args),*));
+        error_kind!($kind, format!($msg, $($args),*))
     };
 }
 
@@ -125,71 +241,240 @@ macro_rules! error {
         Err(Error::new(ErrorKind::RuntimeError, $msg.into(), 0, 0))
     }};
     ($msg:expr, $($args:expr),*) => {
-        error!(format!($msg, $($args),*));
+        error!(format!($msg, $($args),*))
     };
 }
 
 /// Pretty-print an error message, complete with colors and the line
-/// in question.
-pub fn print_error<P: AsRef<std::path::Path>, S: AsRef<str>>(path: P, source: S, err: Error) {
-    let path = path.as_ref();
-    let source = source.as_ref();
+/// in question, followed by the calls it happened inside of.
+///
+/// `path` and `source` are the main template's. Errors in other
+/// templates are shown by loading them from files next to it. Use
+/// `print_error_with()` if they came from somewhere else.
+pub fn print_error<P: AsRef<Path>, S: AsRef<str>>(path: P, source: S, err: Error) {
+    let dir = path
+        .as_ref()
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .to_path_buf();
+    print_error_with(path, source, err, &FileLoader::new(dir));
+}
+
+/// `print_error()`, loading other templates from `loader`, which
+/// should be the one the template was rendered with.
+pub fn print_error_with<P: AsRef<Path>, S: AsRef<str>>(
+    path: P,
+    source: S,
+    err: Error,
+    loader: &dyn Loader,
+) {
+    let main = Main {
+        path: path.as_ref(),
+        source: source.as_ref(),
+        loader,
+    };
+    let (path, source) = main.source_of(err.path());
+    let source = source.unwrap_or_default();
     let (red, blue, _gold, clear) = if std::env::var("NO_COLOR").is_ok() {
         ("", "", "", "")
     } else {
         ("\x1b[91m", "\x1b[1;94m", "\x1b[1;93m", "\x1b[0m")
     };
 
-    let (line, col) = line_and_col(source, err.pos);
-    // println!("(line, col) = {:?}", (line, col));
-    println!(
-        " {}-->{} {}:{}:{}",
-        blue,
-        clear,
-        path.to_str().unwrap(),
-        line,
-        col
-    );
+    let (line, col) = line_and_col(&source, err.pos);
+    println!(" {}-->{} {}:{}:{}", blue, clear, path.display(), line, col);
     println!("   {}|{}", blue, clear);
 
-    let lines = source.split('\n');
-    let pline = |num| {
-        if let Some(line) = lines.clone().nth(num) {
-            println!("{}   |{} {}", blue, clear, line)
+    for (num, text) in context(&source, line) {
+        if num == line {
+            println!("{}{: <3}|{} {}", blue, line, clear, text);
+            println!(
+                "   {}|{} {}{} {}{}",
+                blue,
+                red,
+                " ".repeat(if col > 0 { col - 1 } else { 0 }),
+                "^".repeat(std::cmp::min(err.len, 20)),
+                err.details,
+                clear
+            );
+        } else {
+            println!("{}   |{} {}", blue, clear, text);
         }
+    }
+
+    for frame in err.stack() {
+        println!(
+            "   {}={} in {}, called at {}",
+            blue,
+            clear,
+            frame.name,
+            main.location(frame.path.as_deref(), frame.pos)
+        );
+    }
+}
+
+/// Render an error as a standalone HTML page, with the line in
+/// question highlighted and the calls it happened inside of. Handy
+/// for showing template errors in the browser during development.
+/// It takes the same arguments as `print_error_with()`:
+///
+/// ```ignore
+/// match templates.render("index", &mut env) {
+///     Ok(html) => html,
+///     Err(err) => hatter::error_page("templates/index.hat", &source, &err, &*templates),
+/// }
+/// ```
+pub fn error_page<P: AsRef<Path>, S: AsRef<str>>(
+    path: P,
+    source: S,
+    err: &Error,
+    loader: &dyn Loader,
+) -> String {
+    use crate::escape_html as esc;
+
+    let main = Main {
+        path: path.as_ref(),
+        source: source.as_ref(),
+        loader,
     };
+    let (_, source) = main.source_of(err.path());
+    let source = source.unwrap_or_default();
+    let (line, col) = line_and_col(&source, err.pos);
+    let path = main.location(err.path(), err.pos);
+    let kind = err.kind.name();
+
+    let mut code = String::new();
+    for (num, text) in context(&source, line) {
+        if num != line {
+            code.push_str(&format!(
+                "<tr><th>{}</th><td>{}</td></tr>\n",
+                num,
+                esc(text)
+            ));
+            continue;
+        }
+        // highlight the `len` chars starting at `col`
+        let start = if col > 0 { col - 1 } else { 0 };
+        let end = start + std::cmp::max(err.len, 1);
+        let chars = text.chars().collect::<Vec<_>>();
+        let part = |from: usize, to: usize| -> String {
+            let to = std::cmp::min(to, chars.len());
+            let from = std::cmp::min(from, to);
+            esc(&chars[from..to].iter().collect::<String>())
+        };
+        code.push_str(&format!(
+            "<tr class='error'><th>{}</th><td>{}<mark>{}</mark>{}</td></tr>\n",
+            num,
+            part(0, start),
+            part(start, end),
+            part(end, chars.len())
+        ));
+        code.push_str(&format!(
+            "<tr class='details'><th></th><td>{}{} {}</td></tr>\n",
+            " ".repeat(start),
+            "^".repeat(err.len.clamp(1, 20)),
+            esc(&err.details)
+        ));
+    }
+
+    let mut stack = String::new();
+    for frame in err.stack() {
+        stack.push_str(&format!(
+            "<li>in <code>{}</code>, called at {}</li>\n",
+            esc(&frame.name),
+            esc(&main.location(frame.path.as_deref(), frame.pos))
+        ));
+    }
+    if !stack.is_empty() {
+        stack = format!("<ol class='stack'>\n{}</ol>\n", stack);
+    }
+
+    format!(
+        r#"<!DOCTYPE html>
+<html><head><meta charset='utf-8' />
+<title>{kind}: {title}</title>
+<style>
+body {{ font-family: sans-serif; margin: 2em; color: #222; }}
+h1 {{ color: #c00; font-size: 1.4em; }}
+.details, .path, .stack {{ font-family: monospace; }}
+table {{ border-collapse: collapse; font-family: monospace; white-space: pre; background: #f6f6f6; }}
+th {{ color: #888; font-weight: normal; text-align: right; padding: 0 1em; }}
+td {{ padding-right: 1em; }}
+tr.error {{ background: #fdd; }}
+tr.details td {{ color: #c00; }}
+mark {{ background: #f99; }}
+</style>
+</head>
+<body><h1>{kind}</h1>
+<p class='details'>{details}</p>
+<p class='path'>{path}</p>
+<table>
+{code}</table>
+{stack}</body>
+</html>
+"#,
+        kind = kind,
+        title = esc(&err.details),
+        details = esc(&err.details),
+        path = esc(&path),
+        code = code,
+        stack = stack,
+    )
+}
+
+/// The template an error was rendered from, and how to find the
+/// others it used.
+struct Main<'a> {
+    path: &'a Path,
+    source: &'a str,
+    loader: &'a dyn Loader,
+}
+
+impl Main<'_> {
+    /// Path and source of the template named `file`, or the main
+    /// template's if `file` is None. Other templates come from the
+    /// loader, and their paths are shown relative to the main one's.
+    fn source_of(&self, file: Option<&str>) -> (PathBuf, Option<String>) {
+        match file {
+            None => (self.path.to_path_buf(), Some(self.source.to_string())),
+            Some(file) => {
+                let dir = self.path.parent().unwrap_or_else(|| Path::new("."));
+                (dir.join(file), self.loader.load(file).ok())
+            }
+        }
+    }
+
+    /// `path:line:col` of `pos` in the template named `file`, or just
+    /// its path if we can't load it.
+    fn location(&self, file: Option<&str>, pos: usize) -> String {
+        match self.source_of(file) {
+            (path, Some(source)) => {
+                let (line, col) = line_and_col(&source, pos);
+                format!("{}:{}:{}", path.display(), line, col)
+            }
+            (path, None) => path.display().to_string(),
+        }
+    }
+}
+
+/// Line numbers and text of the two lines before and after `line`,
+/// and `line` itself.
+fn context(source: &str, line: usize) -> Vec<(usize, &str)> {
+    source
+        .split('\n')
+        .enumerate()
+        .map(|(i, text)| (i + 1, text))
+        .skip(line.saturating_sub(3))
+        .take(if line > 2 { 5 } else { line + 2 })
+        .collect()
+}
 
-    if line > 2 {
-        pline(line - 3);
-    }
-    if line > 1 {
-        pline(line - 2);
-    }
-    println!(
-        "{}{: <3}|{} {}",
-        blue,
-        line,
-        clear,
-        lines.clone().nth(line - 1).unwrap()
-    );
-    println!(
-        "   {}|{} {}{} {}{}",
-        blue,
-        red,
-        " ".repeat(if col > 0 { col - 1 } else { 0 }),
-        "^".repeat(std::cmp::min(err.len, 20)),
-        err.details,
-        clear
-    );
-    pline(line);
-    pline(line + 1);
-}
-
-/// Calculate line # and col position for a position in a source file.
+/// Calculate line # and col position for a byte position in a source
+/// file.
 pub fn line_and_col(source: &str, pos: usize) -> (usize, usize) {
     let mut line = 1;
     let mut col = 0;
-    for (i, c) in source.chars().enumerate() {
+    for (i, c) in source.char_indices() {
         if c == '\n' {
             if i == pos {
                 return (line, col + 1);
@@ -0,0 +1,72 @@
+//! HTML escaping. Hatter escapes everything it prints unless it's a
+//! `Value::Html`, which is how tags and `raw()` output get through.
+
+/// Escape text for use in the body of a tag.
+///
+/// `escape_html("<b>Tom & Jerry</b>") #=> "&lt;b&gt;Tom &amp; Jerry&lt;/b&gt;"`
+pub fn escape_html(s: &str) -> String {
+    escape(s, false)
+}
+
+/// Escape text for use in a single quoted attribute value, like
+/// `<a href='HERE'>`, which is how Hatter prints all attributes.
+///
+/// `escape_attr("it's") #=> "it&#39;s"`
+pub fn escape_attr(s: &str) -> String {
+    escape(s, true)
+}
+
+/// Escape text for the body of a <script> or <style> tag, which
+/// the browser doesn't decode entities in, so the usual escaping
+/// would mangle it. Only `</` and `<!--`, which could end the tag
+/// early, are escaped, with a backslash that JavaScript strings and
+/// CSS both ignore.
+///
+/// `escape_raw_text("'</script>'") #=> "'<\/script>'"`
+pub fn escape_raw_text(s: &str) -> String {
+    s.replace("</", "<\\/").replace("<!--", "<\\!--")
+}
+
+/// Do the actual escaping. Single quotes only need to be escaped in
+/// attribute values.
+fn escape(s: &str, quote: bool) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '\'' if quote => out.push_str("&#39;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!("Tom &amp; Jerry", escape_html("Tom & Jerry"));
+        assert_eq!(
+            "&lt;script&gt;alert('hi')&lt;/script&gt;",
+            escape_html("<script>alert('hi')</script>")
+        );
+    }
+
+    #[test]
+    fn test_escape_attr() {
+        assert_eq!(r#"it&#39;s "fine""#, escape_attr(r#"it's "fine""#));
+        assert_eq!("a&amp;b", escape_attr("a&b"));
+    }
+
+    #[test]
+    fn test_escape_raw_text() {
+        assert_eq!("if (1 < 2) x()", escape_raw_text("if (1 < 2) x()"));
+        assert_eq!("'<\\/script><img>'", escape_raw_text("'</script><img>'"));
+        assert_eq!("<\\/STYLE>", escape_raw_text("</STYLE>"));
+        assert_eq!("<\\!-- x", escape_raw_text("<!-- x"));
+    }
+}
@@ -6,25 +6,25 @@ use {
 /// The `Args` struct makes it easier to work with arguments when
 /// writing Hatter functions in Rust.
 #[derive(Debug)]
-pub struct Args<'e> {
-    pub env: &'e mut Env,
+pub struct Args<'e, 'w> {
+    pub env: &'e mut Env<'w>,
     args: Vec<Value>,
 }
 
-impl<'e> ops::Deref for Args<'e> {
+impl ops::Deref for Args<'_, '_> {
     type Target = Vec<Value>;
     fn deref(&self) -> &Self::Target {
         &self.args
     }
 }
 
-impl<'e> ops::DerefMut for Args<'e> {
+impl ops::DerefMut for Args<'_, '_> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.args
     }
 }
 
-impl<'e> IntoIterator for Args<'e> {
+impl IntoIterator for Args<'_, '_> {
     type Item = Value;
     type IntoIter = vec::IntoIter<Value>;
     fn into_iter(self) -> Self::IntoIter {
@@ -32,8 +32,8 @@ impl<'e> IntoIterator for Args<'e> {
     }
 }
 
-impl<'e> Args<'e> {
-    pub fn new(env: &'e mut Env, args: Vec<Value>) -> Args<'e> {
+impl<'e, 'w> Args<'e, 'w> {
+    pub fn new(env: &'e mut Env<'w>, args: Vec<Value>) -> Args<'e, 'w> {
         Args { env, args }
     }
 
@@ -43,11 +43,10 @@ impl<'e> Args<'e> {
 
     /// Like `get()` but returns an error.
     pub fn need(&self, idx: usize) -> Result<Value> {
-        self.args.get(idx).map(Value::clone).ok_or(error_kind!(
-            ArgNotFound,
-            "Argument Not Found: {}",
-            idx
-        ))
+        self.args
+            .get(idx)
+            .cloned()
+            .ok_or_else(|| error_kind!(ArgNotFound { index: idx }, "Argument Not Found: {}", idx))
     }
 
     /// Errors if the arg doesn't exist or isn't a Number.
@@ -55,10 +54,14 @@ impl<'e> Args<'e> {
         if let Value::Number(num) = self.need(idx)? {
             Ok(num)
         } else {
+            let val = self.need(idx)?;
             Err(error_kind!(
-                WrongArgType,
+                TypeMismatch {
+                    expected: "number".to_string(),
+                    actual: val.typename().to_string(),
+                },
                 "Expected Number, got: {:?}",
-                self.need(idx)?
+                val
             ))
         }
     }
@@ -68,10 +71,14 @@ impl<'e> Args<'e> {
         if let Some(Value::String(s)) = self.args.get(idx) {
             Ok(s.to_str())
         } else {
+            let val = self.need(idx)?;
             Err(error_kind!(
-                WrongArgType,
+                TypeMismatch {
+                    expected: "string".to_string(),
+                    actual: val.typename().to_string(),
+                },
                 "Expected String, got: {:?}",
-                self.need(idx)?
+                val
             ))
         }
     }
@@ -81,10 +88,14 @@ impl<'e> Args<'e> {
         if let Some(li @ Value::List(..)) = self.args.get(idx) {
             Ok(li.clone())
         } else {
+            let val = self.need(idx)?;
             Err(error_kind!(
-                WrongArgType,
+                TypeMismatch {
+                    expected: "list".to_string(),
+                    actual: val.typename().to_string(),
+                },
                 "Expected List, got: {:?}",
-                self.need(idx)?
+                val
             ))
         }
     }
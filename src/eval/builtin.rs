@@ -13,83 +13,112 @@
 // other applications, too.
 
 use {
-    crate::{Args, Env, Native, Result, Special, Stmt, Symbol, Value},
-    std::{collections::HashMap, rc::Rc},
+    crate::{escape_html, Args, Env, Native, OMap, Result, Special, Stmt, Symbol, Value},
+    std::{collections::HashMap, sync::Arc},
 };
 
 /// Return the builtin Special functions that come with Hatter.
 /// Ideally we build this at compile time, at least in release mode,
 /// instead of creating them all at runtime.
-pub(crate) fn specials() -> HashMap<String, Rc<Special>> {
-    let mut map: HashMap<String, Rc<Special>> = HashMap::new();
+pub(crate) fn specials() -> HashMap<String, Arc<Special>> {
+    let mut map: HashMap<String, Arc<Special>> = HashMap::new();
 
-    map.insert("&&".into(), rc!(and));
-    map.insert("||".into(), rc!(or));
+    map.insert("&&".into(), arc!(and));
+    map.insert("||".into(), arc!(or));
 
     map
 }
 
 /// Return the builtin Native functions that come with Hatter.
-pub(crate) fn natives() -> HashMap<String, Rc<Native>> {
-    let mut map: HashMap<String, Rc<Native>> = HashMap::new();
-
-    macro_rules! native {
-        ($name:expr => $fn:expr) => {
-            map.insert($name.to_string(), rc!($fn));
-        };
+pub(crate) fn natives() -> HashMap<String, Arc<Native>> {
+    let mut map: HashMap<String, Arc<Native>> = HashMap::new();
+    for (name, f, _) in NATIVES {
+        map.insert(name.to_string(), arc!(*f));
     }
-
-    native!("type" => r#type);
-    native!("when" => when);
-    native!("==" => eq);
-    native!("!=" => neq);
-    native!("not" => not);
-    native!("!" => not);
-    native!("concat" => concat);
-    native!("index" => index);
-    native!("set_index" => set_index);
-    native!("<<" => push);
-    native!("push" => push);
-    native!("pop" => pop);
-    native!("." => index);
-    native!(".." => range);
-    native!("..=" => range_inclusive);
-    native!("+" => add);
-    native!("-" => sub);
-    native!("*" => mul);
-    native!("/" => div);
-    native!("%" => r#mod);
-    native!(">" => gt);
-    native!(">=" => gte);
-    native!("<" => lt);
-    native!("<=" => lte);
-    native!("print" => print);
-    native!("puts" => puts);
-    native!("to-uppercase" => to_uppercase);
-    native!("to-lowercase" => to_lowercase);
-    native!("replace" => replace);
-    native!("contains?" => contains_);
-    native!("split" => split);
-    native!("count" => count);
-    native!("len" => len);
-    native!("empty?" => empty_);
-
     map
 }
 
+/// A builtin Native function, before it's put in an `Arc`.
+type NativeFn = fn(Args) -> Result<Value>;
+
+/// Table of `"name" => function` pairs.
+macro_rules! natives {
+    ($($name:literal => $fn:ident,)*) => {
+        &[$(($name, $fn, stringify!($fn))),*]
+    };
+}
+
+/// Every builtin Native function: its name in Hatter, the function,
+/// and its name in Rust, which editors use to find its docs.
+pub(crate) const NATIVES: &[(&str, NativeFn, &str)] = natives![
+    "type" => r#type,
+    "when" => when,
+    "==" => eq,
+    "!=" => neq,
+    "not" => not,
+    "!" => not,
+    "concat" => concat,
+    "index" => index,
+    "set_index" => set_index,
+    "<<" => push,
+    "push" => push,
+    "pop" => pop,
+    "." => index,
+    ".." => range,
+    "..=" => range_inclusive,
+    "+" => add,
+    "-" => sub,
+    "*" => mul,
+    "/" => div,
+    "%" => r#mod,
+    ">" => gt,
+    ">=" => gte,
+    "<" => lt,
+    "<=" => lte,
+    "raw" => raw,
+    "include" => include,
+    "import" => import,
+    "print" => print,
+    "puts" => puts,
+    "to-uppercase" => to_uppercase,
+    "to-lowercase" => to_lowercase,
+    "replace" => replace,
+    "contains?" => contains_,
+    "split" => split,
+    "count" => count,
+    "len" => len,
+    "empty?" => empty_,
+    "error" => error,
+    "try" => r#try,
+    "to-json" => to_json,
+    "parse-json" => parse_json,
+];
+
 //////////////////////////////////////////////////////////////////////
 // Internal Functions
 
 /// Combine several Values into a String.
 /// Used internally by String interpolation.
 ///
+/// If any of the Values are HTML, the others are escaped and the
+/// result is HTML too. That way `"Hi {<b> name}"` prints a tag.
+///
 /// `concat("hi", "-", 23) #=> "hi-23"`
 pub fn concat(args: Args) -> Result<Value> {
+    let is_html = args.iter().any(|a| matches!(a, Value::Html(..)));
     let mut sum = String::new();
     for arg in args {
-        sum.push_str(&arg.to_string());
+        match arg {
+            Value::Html(html) => sum.push_str(&html),
+            _ if is_html => sum.push_str(&escape_html(&arg.to_string())),
+            _ => sum.push_str(&arg.to_string()),
+        }
+    }
+    if is_html {
+        Value::Html(sum.into()).ok()
+    } else {
+        Ok(sum.into())
     }
-    Ok(sum.into())
 }
 
 /// Returns a Value if a condition is true.
@@ -111,8 +140,8 @@ pub fn when(args: Args) -> Result<Value> {
 /// Returns the String name of a Value's type.
 ///
 /// ```ignore
-/// type('heyo')  #=> "String"
-/// type(123)     #=> "Number"
+/// type('heyo')  #=> "string"
+/// type(123)     #=> "number"
 /// ```
 pub fn r#type(args: Args) -> Result<Value> {
     Value::String(args.need(0)?.typename().into()).ok()
@@ -124,7 +153,14 @@ pub fn r#type(args: Args) -> Result<Value> {
 /// Special: Short-circuiting `&&` operator.
 pub fn and(env: &mut Env, args: &[Stmt]) -> Result<Value> {
     if args.len() != 2 {
-        return error!("Expected 2 args, got {}", 2);
+        return Err(error_kind!(
+            ArityMismatch {
+                expected: 2,
+                actual: args.len(),
+            },
+            "Expected 2 args, got {}",
+            args.len()
+        ));
     }
     Ok((env.eval(&args[0])?.to_bool() && env.eval(&args[1])?.to_bool()).into())
 }
@@ -132,7 +168,14 @@ pub fn and(env: &mut Env, args: &[Stmt]) -> Result<Value> {
 /// Special: Short-circuiting `||` operator.
 pub fn or(env: &mut Env, args: &[Stmt]) -> Result<Value> {
     if args.len() != 2 {
-        return error!("Expected 2 args, got {}", 2);
+        return Err(error_kind!(
+            ArityMismatch {
+                expected: 2,
+                actual: args.len(),
+            },
+            "Expected 2 args, got {}",
+            args.len()
+        ));
     }
     Ok((env.eval(&args[0])?.to_bool() || env.eval(&args[1])?.to_bool()).into())
 }
@@ -154,6 +197,10 @@ pub fn eq(args: Args) -> Result<Value> {
                 Some(Value::String(s2)) => s1 == s2,
                 _ => false,
             },
+            Value::Html(s1) => match args.get(1) {
+                Some(Value::Html(s2)) => s1 == s2,
+                _ => false,
+            },
             _ => false,
         }
         .into()
@@ -274,7 +321,7 @@ pub fn count(args: Args) -> Result<Value> {
 }
 
 /// Split a string into a List by a separator.
-/// `split("Mr Rogers", " ")` #=> ["Mr", "Rogers"]`
+/// `split("Mr Rogers", " ") #=> ["Mr", "Rogers"]`
 pub fn split(args: Args) -> Result<Value> {
     Value::from(
         args.need_string(0)?
@@ -316,7 +363,7 @@ pub fn index(args: Args) -> Result<Value> {
             let mut idx = args.need_number(1)? as isize;
             if idx < 0 {
                 let len = list.borrow().len();
-                if (idx.abs() as usize) <= len {
+                if idx.unsigned_abs() <= len {
                     idx += list.borrow().len() as isize;
                 }
             }
@@ -342,13 +389,13 @@ fn set_index(args: Args) -> Result<Value> {
     match args.need(0)? {
         Value::Map(map) => {
             map.borrow_mut()
-                .insert(Symbol::from(args.need_string(1)?), args.need(2)?.into());
+                .insert(Symbol::from(args.need_string(1)?), args.need(2)?);
         }
         Value::List(list) => {
             let mut idx = args.need_number(1)? as isize;
             if idx < 0 {
                 let len = list.borrow().len();
-                if (idx.abs() as usize) < len {
+                if idx.unsigned_abs() < len {
                     idx += list.borrow().len() as isize;
                 }
             }
@@ -356,7 +403,7 @@ fn set_index(args: Args) -> Result<Value> {
             if idx > list.borrow().len() {
                 return Value::None.ok();
             }
-            list.borrow_mut().insert(idx, args.need(2)?.into());
+            list.borrow_mut().insert(idx, args.need(2)?);
         }
         Value::Object(o) => {
             o.set(args.need_string(1)?, args.need(2)?);
@@ -448,17 +495,109 @@ pub fn range_inclusive(args: Args) -> Result<Value> {
     Ok((start..=end).collect::<Vec<_>>().into())
 }
 
+//////////////////////////////////////////////////////////////////////
+// Error Functions
+
+/// Raise an error, halting the program unless it's inside a `try()`.
+///
+/// `error("Panic!")`
+pub fn error(args: Args) -> Result<Value> {
+    error!(args.need(0)?.to_string())
+}
+
+/// Call a function with some arguments, catching any error it
+/// raises. Returns a map with the function's return value in `ok`
+/// and the error message in `err`, if there was one. Anything the
+/// function printed before failing is thrown away.
+///
+/// ```ignore
+/// ret := try(combine, "Mr.", "Robinson Crusoe")
+/// if ret.err
+///     print(ret.err)
+/// else
+///     print(ret.ok)
+/// ```
+pub fn r#try(mut args: Args) -> Result<Value> {
+    let f = args.need(0)?;
+    let rest = args.drain(1..).collect();
+    let mut map = OMap::new();
+    match args.env.rescue(|env| env.call(&f, rest)) {
+        Ok(val) => {
+            map.insert("ok", val);
+            map.insert("err", Value::None);
+        }
+        Err(err) => {
+            map.insert("ok", Value::None);
+            map.insert("err", err.details.into());
+        }
+    }
+    Ok(Value::Map(map.into()))
+}
+
+//////////////////////////////////////////////////////////////////////
+// JSON Functions
+
+/// Encode a Value as JSON. It's safe to print inside a `<script>`.
+///
+/// `to-json({name: "Bob", age: 30}) #=> "{\"name\":\"Bob\",\"age\":30}"`
+pub fn to_json(args: Args) -> Result<Value> {
+    Ok(args.need(0)?.to_json().into())
+}
+
+/// Decode a JSON string into a Value.
+///
+/// `parse-json("[1, 2]") #=> [1, 2]`
+pub fn parse_json(args: Args) -> Result<Value> {
+    Value::from_json(args.need_string(0)?)
+}
+
 //////////////////////////////////////////////////////////////////////
 // I/O Functions
 
+/// Mark a Value as trusted HTML so it's printed without escaping.
+/// Only use this on HTML you trust - never on user input.
+///
+/// ```ignore
+/// <div> "<b>hi</b>"       #=> <div>&lt;b&gt;hi&lt;/b&gt;</div>
+/// <div> raw("<b>hi</b>")  #=> <div><b>hi</b></div>
+/// ```
+pub fn raw(args: Args) -> Result<Value> {
+    match args.need(0)? {
+        html @ Value::Html(..) => html,
+        val => Value::Html(val.to_string().into()),
+    }
+    .ok()
+}
+
+/// Render another template and return its HTML. It can see the
+/// variables in the current scope, but its own stay inside it.
+///
+/// `<nav> include("partials/nav.hat")`
+pub fn include(args: Args) -> Result<Value> {
+    let name = args.need_string(0)?.to_string();
+    args.env.include(&name)
+}
+
+/// Evaluate another template in the current scope without printing
+/// anything, to pull in the functions it defines.
+///
+/// ```ignore
+/// import "helpers.hat"
+/// import("helpers.hat")
+/// ```
+pub fn import(args: Args) -> Result<Value> {
+    let name = args.need_string(0)?.to_string();
+    args.env.import(&name)?;
+    Value::None.ok()
+}
+
 /// Print one or more Values, without newline.
 pub fn print(mut args: Args) -> Result<Value> {
     while !args.is_empty() {
         let arg = args.remove(0);
-        if args.is_empty() {
-            args.env.print(format!("{}", arg));
-        } else {
-            args.env.print(format!("{} ", arg));
+        args.env.print(arg);
+        if !args.is_empty() {
+            args.env.print(" ");
         }
     }
     Value::None.ok()
@@ -468,10 +607,9 @@ pub fn print(mut args: Args) -> Result<Value> {
 pub fn puts(mut args: Args) -> Result<Value> {
     while !args.is_empty() {
         let arg = args.remove(0);
-        if args.is_empty() {
-            args.env.print(format!("{}", arg));
-        } else {
-            args.env.print(format!("{} ", arg));
+        args.env.print(arg);
+        if !args.is_empty() {
+            args.env.print(" ");
         }
     }
     args.env.print("\n");
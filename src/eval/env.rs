@@ -1,13 +1,19 @@
 use {
-    crate::{builtin, compile, Args, ErrorKind, Fn, OMap, Result, Stmt, Symbol, Tag, Value},
-    std::{
-        cell::{Ref, RefCell},
-        collections::HashMap,
-        mem,
-        rc::Rc,
+    crate::{
+        builtin, compile, escape_attr, escape_html, escape_raw_text, Args, Error, FileLoader, Fn,
+        Frame, Lambda, Loader, OMap, Prerendered, Result, Shared, Span, Stmt, Symbol, Tag, Value,
     },
+    std::{collections::HashMap, fmt, io, mem, sync::Arc},
 };
 
+use super::output::Output;
+
+pub(crate) mod aot;
+mod vm;
+
+/// How much output to buffer before writing it when streaming.
+const CHUNK_SIZE: usize = 8 * 1024;
+
 /// Eval a list of Stmts and return the last's Value.
 pub fn eval(src: &str) -> Result<Value> {
     let mut env = Env::new();
@@ -20,7 +26,7 @@ pub fn render(source: &str) -> Result<String> {
     env.render(source)
 }
 
-/// Error-ish that lets us abort what we're doing.
+/// `break`, `continue`, or `return`.
 #[derive(Debug, PartialEq)]
 pub enum Jump {
     Break,
@@ -28,46 +34,109 @@ pub enum Jump {
     Return(Value),
 }
 
+impl Jump {
+    /// Error for a jump that left the loop or function it's for.
+    fn stray(self, span: Span, file: Option<&Symbol>) -> Error {
+        let (keyword, place) = match self {
+            Jump::Break => ("break", "loop"),
+            Jump::Continue => ("continue", "loop"),
+            Jump::Return(..) => ("return", "function"),
+        };
+        error_kind!(
+            UnexpectedJump {
+                keyword: keyword.to_string()
+            },
+            "{} outside of a {}",
+            keyword,
+            place
+        )
+        .at(span, file)
+    }
+}
+
+/// Why evaluation stopped early: a real error, or a jump on its way
+/// to the loop or function that handles it.
+#[derive(Debug)]
+pub enum Unwind {
+    Error(Error),
+    Jump(Jump, Span),
+}
+
+impl From<Error> for Unwind {
+    fn from(err: Error) -> Unwind {
+        Unwind::Error(err)
+    }
+}
+
+/// Result of evaluating code that can `break`, `continue`, or `return`.
+pub type Flow<T> = std::result::Result<T, Unwind>;
+
 /// You know what it is.
-pub type Scope = Rc<RefCell<HashMap<String, Value>>>;
+pub type Scope = Shared<HashMap<Symbol, Value>>;
 
 /// Env is the top-level environment in which a Hatter template is
-/// evaluated and rendered.
+/// evaluated and rendered. `'w` is how long the writer it's
+/// streaming to, if any, is borrowed for.
 #[derive(Debug)]
-pub struct Env {
+pub struct Env<'w> {
     scopes: Vec<Scope>,
     out: String,
+    raw_text: bool,                     // in <script> or <style>?
+    blocks: HashMap<Symbol, Vec<Stmt>>, // `block`s set by child templates
+    loader: Arc<dyn Loader>,            // finds templates for `extends` etc
+    files: Vec<String>,                 // templates we're currently in
+    file: Option<Symbol>,               // template being run, if not the main one
+    output: Option<Output<'w>>,         // where to stream `out`, if anywhere
+    capturing: usize,                   // how many captures deep are we?
+    frames: Vec<vm::Frame>,             // bytecode being run by the VM
+    stack: Vec<Value>,                  // the VM's value stack
 }
 
-impl Default for Env {
-    fn default() -> Env {
+impl Default for Env<'_> {
+    fn default() -> Self {
         let mut scope = HashMap::new();
         for (name, fun) in builtin::natives() {
-            scope.insert(name, Value::Fn(Fn::Native(fun)));
+            scope.insert(name.into(), Value::Fn(Fn::Native(fun)));
         }
         for (name, fun) in builtin::specials() {
-            scope.insert(name, Value::Fn(Fn::Special(fun)));
+            scope.insert(name.into(), Value::Fn(Fn::Special(fun)));
         }
         Env {
-            scopes: vec![rcell!(scope)],
+            scopes: vec![Shared::new(scope)],
             out: String::new(),
+            raw_text: false,
+            blocks: HashMap::new(),
+            loader: arc!(FileLoader::new(".")),
+            files: vec![],
+            file: None,
+            output: None,
+            capturing: 0,
+            frames: vec![],
+            stack: vec![],
         }
     }
 }
 
-impl Env {
+impl<'w> Env<'w> {
     /// New, top-level Env.
-    pub fn new() -> Env {
+    pub fn new() -> Env<'w> {
         Env::default()
     }
 
     /// Return and clear output.
     pub fn out(&mut self) -> String {
-        mem::replace(&mut self.out, String::new())
+        mem::take(&mut self.out)
+    }
+
+    /// Set the Loader used to find templates for `extends`,
+    /// `include()`, and `import`. Defaults to loading files relative
+    /// to the current directory.
+    pub fn set_loader<L: Loader + 'static>(&mut self, loader: L) {
+        self.loader = arc!(loader);
     }
 
     /// Create an empty Args struct, to call a helper function.
-    pub fn empty_args(&mut self) -> Args {
+    pub fn empty_args(&mut self) -> Args<'_, 'w> {
         Args::new(self, vec![])
     }
 
@@ -78,7 +147,7 @@ impl Env {
 
     /// Add a new scope to the stack, which becomes active.
     pub fn push_scope(&mut self) {
-        self.scopes.push(rcell!(HashMap::new()));
+        self.scopes.push(Shared::new(HashMap::new()));
     }
 
     /// Kill the active scope.
@@ -104,67 +173,317 @@ impl Env {
     }
 
     /// Find a value, looking first in the most recently pushed scope.
-    pub fn lookup(&self, key: &str) -> Option<Ref<'_, Value>> {
-        self.find_scope(key)
-            .map(|scope| Ref::map(scope.borrow(), |v| v.get(key).unwrap()))
+    pub fn lookup(&self, key: &str) -> Option<Value> {
+        self.lookup_sym(&Symbol::find(key)?)
+    }
+
+    /// `lookup()` a name that's already a Symbol, without hashing its
+    /// string.
+    pub(crate) fn lookup_sym(&self, key: &Symbol) -> Option<Value> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.borrow().get(key).cloned())
+            .or_else(|| self.lookup_slot(key))
     }
 
     /// Find the `Scope` in which a var exists, if there is one.
-    fn find_scope(&self, key: &str) -> Option<&Scope> {
-        for scope in self.scopes.iter().rev() {
-            if scope.borrow().contains_key(key) {
-                return Some(scope);
-            }
-        }
-        None
+    fn find_scope(&self, key: &Symbol) -> Option<&Scope> {
+        self.scopes
+            .iter()
+            .rev()
+            .find(|scope| scope.borrow().contains_key(key))
     }
 
     /// Set a value to the nearest scope.
-    pub fn set<V: Into<Value>>(&self, key: &str, val: V) {
-        self.scope()
-            .borrow_mut()
-            .insert(key.to_string(), val.into());
+    pub fn set<K: Into<Symbol>, V: Into<Value>>(&self, key: K, val: V) {
+        self.scope().borrow_mut().insert(key.into(), val.into());
+    }
+
+    /// Set a value to the nearest scope, converting anything that
+    /// implements `Serialize` with `to_value()`.
+    #[cfg(feature = "serde")]
+    pub fn set_serialize<K, T>(&self, key: K, val: &T) -> Result<()>
+    where
+        K: Into<Symbol>,
+        T: serde::Serialize + ?Sized,
+    {
+        self.set(key, crate::to_value(val)?);
+        Ok(())
     }
 
     /// Set a value in a parent scope, or create it in the nearest.
-    pub fn update<V: Into<Value>>(&self, key: &str, val: V) {
-        if let Some(scope) = self.find_scope(key) {
+    pub fn update<K: Into<Symbol>, V: Into<Value>>(&self, key: K, val: V) {
+        let key = key.into();
+        if let Some(scope) = self.find_scope(&key) {
             scope
         } else {
             self.scope()
         }
         .borrow_mut()
-        .insert(key.to_string(), val.into());
+        .insert(key, val.into());
     }
 
     /// Add something to our render buffer without a trailing line.
+    /// Everything but `Value::Html` is escaped. In the body of a
+    /// <script> or <style> tag only what could end it is escaped.
     pub fn print<V: Into<Value>>(&mut self, val: V) {
-        self.out.push_str(&val.into().to_string());
+        match val.into() {
+            Value::Html(html) => self.out.push_str(&html),
+            val if self.raw_text => self.out.push_str(&escape_raw_text(&val.to_string())),
+            val => self.out.push_str(&escape_html(&val.to_string())),
+        }
     }
 
-    /// Render statements into a String.
+    /// Render source code into a String.
     pub fn render(&mut self, source: &str) -> Result<String> {
         let stmts = compile(source)?;
-        let autohtml = self.first_is_head(&stmts);
+        self.render_stmts(&stmts)
+    }
+
+    /// Render already compiled statements into a String, so you
+    /// only pay to compile a template once.
+    pub fn render_stmts(&mut self, stmts: &[Stmt]) -> Result<String> {
+        let res = self.render_page(stmts);
+        self.blocks.clear();
+        if res.is_err() {
+            self.out.clear();
+        }
+        res?;
+        Ok(self.out())
+    }
+
+    /// Render compiled statements into an `io::Write`, like a file or
+    /// socket, writing output in chunks as it's rendered instead of
+    /// keeping the whole page in memory.
+    ///
+    /// Tags and a layout's `block`s are written as they're rendered.
+    /// Anything rendered to become a value - `include()`d templates,
+    /// custom tags, and tags returned by functions - is buffered until
+    /// it's done.
+    pub fn render_to_io(&mut self, stmts: &[Stmt], out: &mut dyn io::Write) -> Result<()> {
+        self.render_to(stmts, Output::Io(out))
+    }
+
+    /// Render compiled statements into a `fmt::Write`, like a String
+    /// or `fmt::Formatter`, writing output in chunks as it's
+    /// rendered. Buffers the same things as `render_to_io()`.
+    pub fn render_to_fmt(&mut self, stmts: &[Stmt], out: &mut dyn fmt::Write) -> Result<()> {
+        self.render_to(stmts, Output::Fmt(out))
+    }
+
+    /// Render while streaming to `out`. `out` is only borrowed for
+    /// this call, so our state is moved into an `Env` that borrows it
+    /// and moved back when we're done, even if rendering panics.
+    fn render_to(&mut self, stmts: &[Stmt], out: Output<'_>) -> Result<()> {
+        struct Lent<'a, 'w, 'o> {
+            home: &'a mut Env<'w>,
+            env: Env<'o>,
+        }
+        impl Drop for Lent<'_, '_, '_> {
+            fn drop(&mut self) {
+                self.home.swap_state(&mut self.env);
+            }
+        }
+        let mut env = Env {
+            scopes: vec![],
+            out: String::new(),
+            raw_text: false,
+            blocks: HashMap::new(),
+            loader: self.loader.clone(),
+            files: vec![],
+            file: None,
+            output: Some(out),
+            capturing: 0,
+            frames: vec![],
+            stack: vec![],
+        };
+        self.swap_state(&mut env);
+        let mut lent = Lent { home: self, env };
+        let html = lent.env.render_stmts(stmts)?;
+        match &mut lent.env.output {
+            Some(out) => out.write(&html),
+            None => Ok(()),
+        }
+    }
+
+    /// Swap everything but the output with `other`.
+    fn swap_state(&mut self, other: &mut Env<'_>) {
+        let Env {
+            scopes,
+            out,
+            raw_text,
+            blocks,
+            loader,
+            files,
+            file,
+            output: _,
+            capturing,
+            frames,
+            stack,
+        } = self;
+        mem::swap(scopes, &mut other.scopes);
+        mem::swap(out, &mut other.out);
+        mem::swap(raw_text, &mut other.raw_text);
+        mem::swap(blocks, &mut other.blocks);
+        mem::swap(loader, &mut other.loader);
+        mem::swap(files, &mut other.files);
+        mem::swap(file, &mut other.file);
+        mem::swap(capturing, &mut other.capturing);
+        mem::swap(frames, &mut other.frames);
+        mem::swap(stack, &mut other.stack);
+    }
+
+    /// Write our buffered output if we're streaming, not capturing
+    /// output for a value, and have enough of it.
+    fn flush(&mut self) -> Result<()> {
+        if self.capturing == 0 && self.out.len() >= CHUNK_SIZE {
+            if let Some(output) = &mut self.output {
+                output.write(&self.out)?;
+                self.out.clear();
+            }
+        }
+        Ok(())
+    }
+
+    /// Run `f`, returning what it prints instead of printing it.
+    fn capture<T, F>(&mut self, f: F) -> (String, T)
+    where
+        F: FnOnce(&mut Env) -> T,
+    {
+        let old_out = mem::take(&mut self.out);
+        self.capturing += 1;
+        let res = f(self);
+        self.capturing -= 1;
+        (mem::replace(&mut self.out, old_out), res)
+    }
+
+    /// Render a full page. If the page `extends` a layout, its
+    /// `block`s are saved and the layout is rendered instead.
+    fn render_page(&mut self, stmts: &[Stmt]) -> Result<()> {
+        if let Some(layout) = self.extends(stmts)? {
+            return self.in_file(layout.to_str(), |env, stmts| env.render_page(stmts));
+        }
+
+        let autohtml = self.first_is_head(stmts);
         if autohtml {
-            self.print("<!DOCTYPE html>\n<html>");
+            self.print(Value::Html("<!DOCTYPE html>\n<html>".into()));
         }
-        self.printed_block(&stmts)?;
+        self.printed_block(stmts)?;
         if autohtml {
-            self.print("\n</html>\n");
+            self.print(Value::Html("\n</html>\n".into()));
         }
-        Ok(self.out())
+        Ok(())
+    }
+
+    /// If a page has an `extends` statement, save its `block`s and
+    /// evaluate everything else without printing, then return the
+    /// name of the layout. A child's blocks win over its layout's,
+    /// so blocks that are already set aren't replaced.
+    fn extends(&mut self, stmts: &[Stmt]) -> Result<Option<Value>> {
+        if !stmts.iter().any(|s| matches!(s, Stmt::Extends(..))) {
+            return Ok(None);
+        }
+
+        let mut layout = None;
+        for stmt in stmts {
+            match stmt {
+                Stmt::Extends(expr) => layout = Some(self.eval(expr)?),
+                Stmt::Block(name, body) => {
+                    if !self.blocks.contains_key(name) {
+                        self.blocks.insert(name.clone(), body.clone());
+                    }
+                }
+                _ => {
+                    self.eval(stmt)?;
+                }
+            }
+        }
+        Ok(layout)
+    }
+
+    /// Render another template and return its output as HTML. It gets
+    /// its own scope, so it can see our variables but not set them.
+    pub fn include(&mut self, name: &str) -> Result<Value> {
+        self.in_file(name, |env, stmts| {
+            let (html, res) = env.capture(|env| {
+                env.push_scope();
+                let res = env.printed_block(stmts);
+                env.pop_scope();
+                res
+            });
+            res.map(|_| Value::Html(html.into()))
+        })
+    }
+
+    /// Evaluate another template in the current scope, throwing away
+    /// its output, so the functions and variables it defines can be
+    /// used by the caller.
+    pub fn import(&mut self, name: &str) -> Result<()> {
+        self.in_file(name, |env, stmts| {
+            env.capture(|env| env.block(stmts)).1.map(|_| ())
+        })
+    }
+
+    /// Load and compile the template `name`, then run `f` on it.
+    /// Errors inside it are tagged with `name` so we can show the
+    /// right source code, and templates can't load themselves.
+    fn in_file<T, F>(&mut self, name: &str, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Env, &[Stmt]) -> Result<T>,
+    {
+        if self.files.iter().any(|f| f == name) {
+            return Err(error_kind!(
+                IncludeCycle {
+                    name: name.to_string()
+                },
+                "include cycle: {} -> {}",
+                self.files.join(" -> "),
+                name
+            ));
+        }
+        let template = self.loader.template(name)?;
+        let stmts = template.stmts().map_err(|mut err| {
+            err.set_path(Some(name.to_string()));
+            err
+        })?;
+        self.files.push(name.to_string());
+        let file = self.file.replace(name.into());
+        let res = f(self, stmts);
+        self.file = file;
+        self.files.pop();
+        res
     }
 
     /// Evaluate a block of statements, returning the last one.
     pub fn block(&mut self, stmts: &[Stmt]) -> Result<Value> {
+        let res = self.inner_block(stmts, true);
+        self.settle(res)
+    }
+
+    /// Evaluate a block of statements, printing tags as we go. Tags
+    /// are written straight to the output, except for the last one
+    /// if we `want` its value.
+    fn inner_block(&mut self, stmts: &[Stmt], want: bool) -> Flow<Value> {
         let mut out = Value::None;
-        for stmt in stmts {
-            let is_tag = matches!(stmt, Stmt::Tag(..));
-            out = self.eval(&stmt)?;
-            if is_tag {
-                self.print(&out);
-            }
+        for (i, stmt) in stmts.iter().enumerate() {
+            out = match stmt {
+                Stmt::Tag(tag) if !want || i < stmts.len() - 1 => {
+                    self.print_tag(tag)?;
+                    Value::None
+                }
+                Stmt::Block(name, default) if !want || i < stmts.len() - 1 => {
+                    self.print_block(name, default)?;
+                    Value::None
+                }
+                Stmt::Tag(..) => {
+                    let out = self.run(stmt)?;
+                    self.print(&out);
+                    out
+                }
+                _ => self.run(stmt)?,
+            };
+            self.flush()?;
         }
         Ok(out)
     }
@@ -172,23 +491,66 @@ impl Env {
     /// Evaluate a block of statements and print what each returns.
     /// "Outside of a tag, tags are printed.
     ///  Inside of a tag, everything is printed."
+    /// Tags are written straight to the output, so the Value of the
+    /// last statement is only returned if it isn't a tag.
     pub fn printed_block(&mut self, stmts: &[Stmt]) -> Result<Value> {
+        let res = self.inner_printed_block(stmts);
+        self.settle(res)
+    }
+
+    /// `printed_block()` that passes jumps along.
+    fn inner_printed_block(&mut self, stmts: &[Stmt]) -> Flow<Value> {
         let mut out = Value::None;
         for stmt in stmts {
-            if let Stmt::Word(w) = &stmt {
-                if !self.is_keyword_or_var(w) {
+            out = match stmt {
+                Stmt::Word(w, _) if !self.is_keyword_or_var(w) => {
                     self.print(w);
-                    continue;
+                    Value::None
                 }
-            }
-            out = self.eval(&stmt)?;
+                Stmt::Tag(tag) => {
+                    self.print_tag(tag)?;
+                    Value::None
+                }
+                Stmt::Block(name, default) => {
+                    self.print_block(name, default)?;
+                    Value::None
+                }
+                _ => self.run(stmt)?,
+            };
             if !matches!(out, Value::None) {
                 self.print(&out);
             }
+            self.flush()?;
         }
         Ok(out)
     }
 
+    /// Call a Hatter function with already evaluated arguments.
+    pub fn call(&mut self, f: &Value, args: Vec<Value>) -> Result<Value> {
+        let args = args.into_iter().map(Stmt::Value).collect();
+        let call = Stmt::Call(bx!(Stmt::Value(f.clone())), args, Span::default());
+        self.eval(&call)
+    }
+
+    /// Run `f`, throwing away anything it printed and cleaning up
+    /// after it if it fails, so the error can be recovered from.
+    /// That includes any VM frames and stack values it left behind.
+    pub(crate) fn rescue<T, F>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Env) -> Result<T>,
+    {
+        let (scopes, frames, stack) = (self.scopes.len(), self.frames.len(), self.stack.len());
+        let (out, res) = self.capture(f);
+        if res.is_ok() {
+            self.out.push_str(&out);
+        } else {
+            self.scopes.truncate(scopes);
+            self.frames.truncate(frames);
+            self.stack.truncate(stack);
+        }
+        res
+    }
+
     /// Evaluate source code, returning its Value.
     pub fn eval_src(&mut self, src: &str) -> Result<Value> {
         compile(src).and_then(|c| self.block(&c))
@@ -196,48 +558,79 @@ impl Env {
 
     /// Evaluate one statement, returning its Value.
     pub fn eval(&mut self, stmt: &Stmt) -> Result<Value> {
+        let res = self.run(stmt);
+        self.settle(res)
+    }
+
+    /// Turn a jump that made it all the way out of a loop or function
+    /// into an error.
+    fn settle<T>(&self, res: Flow<T>) -> Result<T> {
+        match res {
+            Ok(v) => Ok(v),
+            Err(Unwind::Error(e)) => Err(e),
+            Err(Unwind::Jump(jump, span)) => Err(jump.stray(span, self.file.as_ref())),
+        }
+    }
+
+    /// Evaluate one statement, passing jumps along. Errors without a
+    /// position get this statement's.
+    fn run(&mut self, stmt: &Stmt) -> Flow<Value> {
+        self.eval_stmt(stmt).map_err(|u| match u {
+            Unwind::Error(e) => Unwind::Error(e.at(stmt.span(), self.file.as_ref())),
+            jump => jump,
+        })
+    }
+
+    /// Actually evaluate one statement.
+    fn eval_stmt(&mut self, stmt: &Stmt) -> Flow<Value> {
         Ok(match stmt {
             Stmt::None => Value::None,
             Stmt::Value(v) => v.clone(),
             Stmt::Tag(x) => self.eval_tag(x)?,
             Stmt::List(x) => x
                 .iter()
-                .map(|v| self.eval(&v))
-                .collect::<Result<Vec<_>>>()?
+                .map(|v| self.run(v))
+                .collect::<Flow<Vec<_>>>()?
                 .into(),
             Stmt::Map(pairs) => {
                 let mut map = OMap::new();
                 for (k, v) in pairs {
-                    map.insert(k, self.eval(v)?);
+                    map.insert(k, self.run(v)?);
                 }
                 Value::Map(map.into())
             }
-            Stmt::Word(word) => {
+            Stmt::Word(word, span) => {
                 match word.as_ref() {
-                    "break" => return jump!(Jump::Break),
-                    "continue" => return jump!(Jump::Continue),
+                    "break" => return Err(Unwind::Jump(Jump::Break, *span)),
+                    "continue" => return Err(Unwind::Jump(Jump::Continue, *span)),
                     _ => {}
                 }
-                if let Some(val) = self.lookup(&word) {
-                    val.clone()
+                if let Some(val) = self.lookup_sym(word) {
+                    val
                 } else {
-                    return error!("can't find var: {}", word);
+                    let name = word.to_string();
+                    return Err(error_kind!(
+                        UndefinedVariable { name },
+                        "can't find var: {}",
+                        word
+                    )
+                    .into());
                 }
             }
-            Stmt::Call(target, args) => {
+            Stmt::Call(target, args, span) => {
                 // eval the target and see if it's a Hatter function
-                if let Value::Fn(inner_fn) = self.eval(&target)? {
+                if let Value::Fn(inner_fn) = self.run(target)? {
                     match inner_fn {
                         Fn::Special(f) => f.clone()(self, args)?,
                         Fn::Native(f) => {
                             let f = f.clone();
-                            let args = args
-                                .iter()
-                                .map(|a| self.eval(&a))
-                                .collect::<Result<Vec<_>>>()?;
-                            f(Args::new(self, args))?
+                            let args =
+                                args.iter().map(|a| self.run(a)).collect::<Flow<Vec<_>>>()?;
+                            f(Args::new(self, args))
+                                .map_err(|e| self.called(e, target.to_str(), *span))?
                         }
-                        Fn::Fn(params, body, scope) => {
+                        Fn::Fn(lambda, scope, file) => {
+                            let params = &lambda.params;
                             let mut kw_args = None;
                             if args.len() == 1 {
                                 if let Stmt::Args(inner) = &args[0] {
@@ -252,7 +645,7 @@ impl Env {
                                 }
                             }
                             let kw_args = if let Some(mut inner) = kw_args {
-                                inner.sort_by(|a, b| a.0.cmp(&b.0));
+                                inner.sort_by_key(|a| a.0);
                                 inner.into_iter().map(|p| p.1).collect::<Vec<_>>()
                             } else {
                                 vec![]
@@ -260,42 +653,58 @@ impl Env {
                             let args = if !kw_args.is_empty() { &kw_args } else { args };
 
                             if params.len() != args.len() {
-                                return error!(
+                                return Err(error_kind!(
+                                    ArityMismatch {
+                                        expected: params.len(),
+                                        actual: args.len(),
+                                    },
                                     "expected {} args, got {}",
                                     params.len(),
                                     args.len()
-                                );
+                                )
+                                .into());
                             }
+                            // args are evaluated in the caller's scope
+                            let args =
+                                args.iter().map(|a| self.run(a)).collect::<Flow<Vec<_>>>()?;
                             self.scopes.push(scope);
                             self.push_scope();
-                            for (i, a) in args.iter().enumerate() {
-                                if let Some(name) = params.get(i) {
-                                    let val = self.eval(&a)?;
-                                    self.set(name, val);
-                                }
+                            for (name, val) in params.iter().zip(args) {
+                                self.set(name, val);
                             }
-                            let out = self.block(&body);
+                            let file = mem::replace(&mut self.file, file);
+                            // `break` and `continue` can't leave a function
+                            let out = match self.inner_block(&lambda.body, true) {
+                                Err(Unwind::Jump(Jump::Return(v), _)) => Ok(v),
+                                res => self.settle(res),
+                            };
+                            self.file = file;
                             self.pop_scope();
-                            match out {
-                                Ok(v) => v,
-                                Err(e) => match e.kind {
-                                    ErrorKind::Jump(Jump::Return(v)) => v,
-                                    _ => return Err(e),
-                                },
-                            }
+                            self.pop_scope();
+                            out.map_err(|e| self.called(e, target.to_str(), *span))?
                         }
                     }
                 } else {
-                    return error!("can't find fn: {}", target.to_string());
+                    let name = target.to_string();
+                    return Err(error_kind!(
+                        NotCallable { name },
+                        "can't find fn: {}",
+                        target.to_string()
+                    )
+                    .into());
                 }
             }
-            Stmt::Return(expr) => return jump!(Jump::Return(self.eval(expr)?)),
+            Stmt::Return(expr, span) => {
+                let val = self.run(expr)?;
+                return Err(Unwind::Jump(Jump::Return(val), *span));
+            }
             Stmt::If(conds) => {
                 for (test, body) in conds {
-                    if self.eval(test)?.to_bool() {
+                    if self.run(test)?.to_bool() {
                         self.push_scope();
-                        self.block(body)?;
+                        let res = self.inner_block(body, false);
                         self.pop_scope();
+                        res?;
                         break;
                     }
                 }
@@ -303,79 +712,182 @@ impl Env {
             }
             Stmt::For(..) => self.eval_for(stmt)?,
             Stmt::While(test, body) => {
-                self.push_scope();
-                while self.eval(&test)?.to_bool() {
-                    match self.block(&body) {
-                        Ok(_) => {}
-                        Err(e) => match e.kind {
-                            ErrorKind::Jump(Jump::Break) => break,
-                            ErrorKind::Jump(Jump::Continue) => continue,
-                            _ => return Err(e),
-                        },
-                    }
-                    self.scope().borrow_mut().clear();
-                }
-                self.pop_scope();
+                self.while_loop(
+                    |env| env.run(test),
+                    |env| env.inner_block(body, false).map(|_| ()),
+                )?;
                 Value::None
             }
-            Stmt::Assign(name, expr, is_reassign) => {
-                let exists = self.var_exists(name);
-                if exists && !is_reassign {
-                    return error!("{} already set", name);
-                } else if !exists && *is_reassign {
-                    return error!("{} is not set", name);
-                }
-
-                let val = self.eval(expr)?;
-                if *is_reassign {
-                    if self.lookup(name).unwrap().typename() != val.typename() {
-                        return error!("{} is type {}", name, val.typename());
-                    }
-                    self.update(name, val);
-                } else {
-                    self.set(name, val);
-                }
+            Stmt::Assign(name, expr, is_reassign, _) => {
+                self.assign(name, *is_reassign, |env| env.run(expr))?;
                 Value::None
             }
-            Stmt::Fn(params, body) => {
-                Value::Fn(Fn::Fn(params.clone(), body.clone(), self.scope().clone()))
+            Stmt::Fn(params, body) => Value::Fn(Fn::Fn(
+                arc!(Lambda::new(params.clone(), body.clone())),
+                self.scope().clone(),
+                self.file.clone(),
+            )),
+            Stmt::Block(name, default) => {
+                let (html, res) = self.capture(|env| env.print_block(name, default));
+                res?;
+                Value::Html(html.into())
+            }
+            Stmt::Extends(..) => {
+                return Err(error_kind!(
+                    RuntimeError,
+                    "extends only works at the top level of a template"
+                )
+                .into());
             }
             Stmt::Args(..) => unimplemented!(),
         })
     }
 
+    /// Print a `block`: what the child template set it to, or
+    /// `default`. Like a tag, it's written straight to the output.
+    fn print_block(&mut self, name: &Symbol, default: &[Stmt]) -> Flow<()> {
+        let body = self.blocks.get(name).cloned();
+        self.push_scope();
+        let res = self.inner_printed_block(body.as_deref().unwrap_or(default));
+        self.pop_scope();
+        res.map(|_| ())
+    }
+
+    /// Set a var to what `val` returns, with `:=` or, if
+    /// `is_reassign`, `=`.
+    fn assign<F>(&mut self, name: &Symbol, is_reassign: bool, val: F) -> Flow<()>
+    where
+        F: FnOnce(&mut Env) -> Flow<Value>,
+    {
+        let exists = self.lookup_sym(name).is_some();
+        if exists && !is_reassign {
+            let name = name.to_string();
+            return Err(error_kind!(AlreadyDefined { name }, "{} already set", name).into());
+        } else if !exists && is_reassign {
+            let name = name.to_string();
+            return Err(error_kind!(UndefinedVariable { name }, "{} is not set", name).into());
+        }
+
+        let val = val(self)?;
+        if is_reassign {
+            let old = self.lookup_sym(name).unwrap();
+            if old.typename() != val.typename() {
+                return Err(error_kind!(
+                    TypeMismatch {
+                        expected: old.typename().to_string(),
+                        actual: val.typename().to_string(),
+                    },
+                    "{} is type {}",
+                    name,
+                    val.typename()
+                )
+                .into());
+            }
+            self.update(name, val);
+        } else {
+            self.set(name, val);
+        }
+        Ok(())
+    }
+
     /// Turn a <tag> into a Value::String.
-    fn eval_tag(&mut self, tag: &Tag) -> Result<Value> {
-        let mut out = String::new();
-        out.push('<');
+    fn eval_tag<T: TagParts>(&mut self, tag: &T) -> Flow<Value> {
+        let (html, res) = self.capture(|env| env.print_tag(tag));
+        res?;
+        Ok(if html.is_empty() {
+            Value::None
+        } else {
+            Value::Html(html.into())
+        })
+    }
+
+    /// Print a <tag>, writing its body straight to the output.
+    fn print_tag<T: TagParts>(&mut self, tag: &T) -> Flow<()> {
+        self.print_tag_body(tag).map_err(|u| match u {
+            Unwind::Error(e) => Unwind::Error(e.at(tag.span(), self.file.as_ref())),
+            jump => jump,
+        })
+    }
+
+    /// Actually print a <tag>.
+    fn print_tag_body<T: TagParts>(&mut self, tag: &T) -> Flow<()> {
+        // rendered by optimize()
+        if let Some(html) = tag.html() {
+            if self.can_print(html) {
+                self.out.push_str(&html.html);
+                return Ok(());
+            }
+        }
 
         // tag name
-        let tagname = self.eval(&tag.tag)?.to_string();
-        out.push_str(&tagname);
-        out.push(' ');
+        let tagname = tag.name(self)?.to_string();
+        let custom = tag.custom(self, &tagname);
+        if let Some(Value::Fn(Fn::Fn(lambda, scope, file))) = custom {
+            let val = self.eval_custom_tag(tag, &tagname, &lambda, scope, file)?;
+            if !matches!(val, Value::None) {
+                self.print(val);
+            }
+            return Ok(());
+        }
 
         // don't run <debug> code in --release
         #[cfg(not(debug_assertions))]
         if tagname == "debug" {
-            return Ok(Value::None);
+            return Ok(());
+        }
+
+        // <debug> gets eval'd but not print'd in --debug mode
+        if tagname == "debug" {
+            return self.capture(|env| tag.body(env)).1.map(|_| ());
         }
 
+        let out = self.open_tag(tag, &tagname)?;
+        self.out.push_str(&out);
+        if tag.is_closed() {
+            return Ok(());
+        }
+        self.flush()?;
+
+        // body
+        let raw_text = mem::replace(
+            &mut self.raw_text,
+            matches!(tagname.as_ref(), "script" | "style"),
+        );
+        let body = tag.body(self);
+        self.raw_text = raw_text;
+        body?;
+
+        // closing tag
+        self.out.push_str("</");
+        self.out.push_str(&tagname);
+        self.out.push_str(">\n");
+        Ok(())
+    }
+
+    /// HTML for the start of a tag, with its attributes, or all of
+    /// it if it's <self-closing/>.
+    fn open_tag<T: TagParts>(&mut self, tag: &T, tagname: &str) -> Flow<String> {
+        let mut out = String::new();
+        out.push('<');
+        out.push_str(tagname);
+        out.push(' ');
+
         // id
-        if tag.id.is_some() {
-            let val = self.eval(&tag.id)?;
+        if tag.has_id() {
+            let val = tag.id(self)?;
             if val.to_bool() {
-                out.push_str(&format!("id='{}'", val.to_string()));
+                out.push_str(&format!("id='{}'", escape_attr(&val.to_string())));
                 out.push(' ');
             }
         }
 
         // classes
-        if !tag.classes.is_empty() {
+        if tag.classes() > 0 {
             let mut classes = vec![];
-            for class in &tag.classes {
-                let val = self.eval(class)?;
+            for i in 0..tag.classes() {
+                let val = tag.class(self, i)?;
                 if val.to_bool() {
-                    classes.push(val.to_string());
+                    classes.push(escape_attr(&val.to_string()));
                 }
             }
             out.push_str(&format!("class='{}'", classes.join(" ")));
@@ -386,38 +898,46 @@ impl Env {
         let is_form = tagname == "form";
         let is_link = tagname == "a";
         let mut has_href = !is_link;
-        for (name, val) in &tag.attrs {
-            let val = self.eval(val)?;
+        for i in 0..tag.attrs() {
+            let val = tag.attr_value(self, i)?;
             if !val.to_bool() {
                 continue;
             }
 
-            let attr_name = self.eval(name)?.to_string();
+            let attr_name = tag.attr_name(self, i)?.to_string();
             if is_form && matches!(attr_name.as_ref(), "GET" | "POST") {
-                out.push_str(&format!("method='{}' action='{}'", attr_name, val));
+                out.push_str(&format!(
+                    "method='{}' action='{}'",
+                    attr_name,
+                    escape_attr(&val.to_string())
+                ));
             } else {
                 if attr_name == "href" {
                     has_href = true;
                 }
                 match val {
-                    Value::String(s) => out.push_str(&format!("{}='{}'", attr_name, s)),
                     Value::Bool(..) => out.push_str(&attr_name),
-                    _ => out.push_str(&format!("{}={}", attr_name, val)),
+                    Value::Number(..) => out.push_str(&format!("{}={}", attr_name, val)),
+                    _ => out.push_str(&format!(
+                        "{}='{}'",
+                        attr_name,
+                        escape_attr(&val.to_string())
+                    )),
                 }
                 out.push(' ');
             }
         }
 
         if is_link && !has_href {
-            out.push_str(&"href='#' ");
+            out.push_str("href='#' ");
         }
 
         // check for self-closing tag
-        if tag.closed {
+        if tag.is_closed() {
             out.push('/');
             out.push('>');
             out.push('\n');
-            return Ok(out.into());
+            return Ok(out);
         }
 
         // trim trailing space
@@ -427,48 +947,161 @@ impl Env {
 
         // close tag
         out.push('>');
+        Ok(out)
+    }
 
-        // body
-        let old_out = self.out();
-        self.printed_block(&tag.body)?;
-        out.push_str(&mem::replace(&mut self.out, old_out));
+    /// Expand a tag defined with `def <name(params)>`. Attributes are
+    /// passed in by name, with `id` and `class` for the shortcuts,
+    /// and its rendered body is passed in as `children`.
+    fn eval_custom_tag<T: TagParts>(
+        &mut self,
+        tag: &T,
+        tagname: &str,
+        lambda: &Arc<Lambda>,
+        scope: Scope,
+        file: Option<Symbol>,
+    ) -> Flow<Value> {
+        let params = &lambda.params;
+        let mut attrs = vec![];
+        if tag.has_id() {
+            attrs.push(("id".to_string(), tag.id(self)?));
+        }
+        if tag.classes() > 0 {
+            let mut classes = vec![];
+            for i in 0..tag.classes() {
+                let val = tag.class(self, i)?;
+                if val.to_bool() {
+                    classes.push(val.to_string());
+                }
+            }
+            attrs.push(("class".to_string(), classes.join(" ").into()));
+        }
+        for i in 0..tag.attrs() {
+            attrs.push((
+                tag.attr_name(self, i)?.to_string(),
+                tag.attr_value(self, i)?,
+            ));
+        }
+        if let Some((name, _)) = attrs
+            .iter()
+            .find(|(name, _)| !params.contains(&name.into()))
+        {
+            return Err(error_kind!(
+                UnknownAttribute {
+                    tag: tagname.to_string(),
+                    attr: name.to_string(),
+                },
+                "<{}> doesn't take attribute: {}",
+                tagname,
+                name
+            )
+            .into());
+        }
 
-        // closing tag
-        out.push_str(&format!("</{}>\n", tagname));
+        let (children, res) = self.capture(|env| {
+            env.push_scope();
+            let res = tag.children(env);
+            env.pop_scope();
+            res
+        });
+        res?;
 
-        // <debug> gets eval'd but not print'd in --debug mode
-        if tagname == "debug" {
-            Ok(Value::None)
-        } else {
-            Ok(out.into())
+        self.scopes.push(scope);
+        self.push_scope();
+        let mut args = params
+            .iter()
+            .map(|param| {
+                let val = attrs.iter().find(|(name, _)| name == param.to_str());
+                val.map(|(_, v)| v.clone()).unwrap_or(Value::None)
+            })
+            .collect::<Vec<_>>();
+        args.push(Value::Html(children.into()));
+        let file = mem::replace(&mut self.file, file);
+        let (out, res) = self.capture(|env| match T::expand(env, lambda, args) {
+            Err(Unwind::Jump(Jump::Return(v), _)) => Ok(Some(v)),
+            res => env.settle(res).map(|_| None),
+        });
+        self.file = file;
+        self.pop_scope();
+        self.pop_scope();
+
+        match res {
+            Ok(Some(v)) => Ok(v),
+            Ok(None) => Ok(Value::Html(out.into())),
+            Err(e) => Err(self.called(e, &format!("<{}>", tagname), tag.span()).into()),
+        }
+    }
+
+    /// Add a stack frame to an error that happened somewhere inside
+    /// the function `name`, which was called at `span`. Errors
+    /// raised by the call itself already point at `span`.
+    fn called(&self, mut err: Error, name: &str, span: Span) -> Error {
+        if err.has_pos() {
+            err.push_frame(Frame {
+                name: if name.is_empty() { "fn" } else { name }.to_string(),
+                pos: span.pos,
+                len: span.len,
+                path: self.file.as_ref().map(|f| f.to_string()),
+            });
         }
+        err
     }
 
     /// Evaluate a for loop.
-    fn eval_for(&mut self, stmt: &Stmt) -> Result<Value> {
+    fn eval_for(&mut self, stmt: &Stmt) -> Flow<Value> {
         if let Stmt::For(key, val, expr, body) = stmt {
-            match self.eval(&expr)? {
-                Value::List(list) => {
-                    self.inner_for(key, val, list.borrow().iter().enumerate(), body)?
-                }
-                Value::Map(map) => self.inner_for(key, val, map.borrow().iter(), body)?,
-                v => return error!("expected List or Map, got {:?}", v),
-            }
+            let iter = self.run(expr)?;
+            self.for_each(key.as_ref(), val, iter, |env| {
+                env.inner_block(body, false).map(|_| ())
+            })?;
         }
 
         Ok(Value::None)
     }
 
+    /// Run `body` for each item in a list or map, in a scope with
+    /// `key` and `var` set.
+    fn for_each<F>(&mut self, key: Option<&Symbol>, var: &Symbol, iter: Value, body: F) -> Flow<()>
+    where
+        F: FnMut(&mut Env) -> Flow<()>,
+    {
+        match iter {
+            // loop over a copy, so the body can change the original
+            Value::List(list) => {
+                let list = list.borrow().clone();
+                self.inner_for(key, var, list.iter().enumerate(), body)
+            }
+            Value::Map(map) => {
+                let pairs = map
+                    .borrow()
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect::<Vec<_>>();
+                self.inner_for(key, var, pairs.iter().map(|(k, v)| (k, v)), body)
+            }
+            v => Err(error_kind!(
+                TypeMismatch {
+                    expected: "list or map".to_string(),
+                    actual: v.typename().to_string(),
+                },
+                "expected List or Map, got {:?}",
+                v
+            )
+            .into()),
+        }
+    }
+
     /// Shared "inner" for loop, over both maps and lists.
-    fn inner_for<'o, K>(
+    fn inner_for<'o, K, F>(
         &mut self,
-        key: &Option<Symbol>,
-        var: &str,
+        key: Option<&Symbol>,
+        var: &Symbol,
         iter: impl Iterator<Item = (K, &'o Value)>,
-        body: &[Stmt],
-    ) -> Result<()>
+        mut body: F,
+    ) -> Flow<()>
     where
         K: Into<Value>,
+        F: FnMut(&mut Env) -> Flow<()>,
     {
         self.push_scope();
         for (k, v) in iter {
@@ -476,13 +1109,46 @@ impl Env {
                 self.set(keyvar, k);
             }
             self.set(var, v.clone());
-            match self.block(&body) {
+            match body(self) {
                 Ok(_) => {}
-                Err(e) => match e.kind {
-                    ErrorKind::Jump(Jump::Break) => break,
-                    ErrorKind::Jump(Jump::Continue) => continue,
-                    _ => return Err(e),
-                },
+                Err(Unwind::Jump(Jump::Break, _)) => break,
+                Err(Unwind::Jump(Jump::Continue, _)) => continue,
+                Err(e) => {
+                    self.pop_scope();
+                    return Err(e);
+                }
+            }
+            self.scope().borrow_mut().clear();
+        }
+        self.pop_scope();
+        Ok(())
+    }
+
+    /// Run `body` in a new scope until `test` is false. The scope is
+    /// cleared after each pass, except on `continue`.
+    fn while_loop<T, B>(&mut self, mut test: T, mut body: B) -> Flow<()>
+    where
+        T: FnMut(&mut Env) -> Flow<Value>,
+        B: FnMut(&mut Env) -> Flow<()>,
+    {
+        self.push_scope();
+        loop {
+            match test(self) {
+                Ok(test) if test.to_bool() => {}
+                Ok(_) => break,
+                Err(e) => {
+                    self.pop_scope();
+                    return Err(e);
+                }
+            }
+            match body(self) {
+                Ok(_) => {}
+                Err(Unwind::Jump(Jump::Break, _)) => break,
+                Err(Unwind::Jump(Jump::Continue, _)) => continue,
+                Err(e) => {
+                    self.pop_scope();
+                    return Err(e);
+                }
             }
             self.scope().borrow_mut().clear();
         }
@@ -492,7 +1158,7 @@ impl Env {
 
     /// Is the first stmt a <head> tag?
     fn first_is_head(&self, stmts: &[Stmt]) -> bool {
-        if let Some(Stmt::Tag(tag)) = stmts.get(0) {
+        if let Some(Stmt::Tag(tag)) = stmts.first() {
             if let Stmt::Value(Value::String(tag)) = &*tag.tag {
                 return tag == "head";
             }
@@ -501,7 +1167,107 @@ impl Env {
     }
 
     /// Is the given string a keyword or existing variable?
-    fn is_keyword_or_var(&self, it: &str) -> bool {
-        self.var_exists(it) || matches!(it, "return" | "break" | "continue")
+    fn is_keyword_or_var(&self, it: &Symbol) -> bool {
+        self.lookup_sym(it).is_some() || matches!(it.to_str(), "return" | "break" | "continue")
+    }
+
+    /// Can a tag rendered by `optimize()` be printed as-is here?
+    fn can_print(&self, html: &Prerendered) -> bool {
+        !self.raw_text
+            && html
+                .unset
+                .iter()
+                .all(|name| self.lookup_sym(name).is_none())
+    }
+}
+
+/// The parts of a <tag>, so the tree-walker and the VM can share the
+/// code that prints tags and expands custom ones.
+trait TagParts {
+    /// Where the tag name is.
+    fn span(&self) -> Span;
+    /// Evaluate the tag name.
+    fn name(&self, env: &mut Env) -> Flow<Value>;
+    /// Custom tag defined with `def <name>`, if there is one.
+    fn custom(&self, env: &Env, tagname: &str) -> Option<Value> {
+        env.lookup(&format!("<{}>", tagname))
+    }
+    fn has_id(&self) -> bool;
+    fn id(&self, env: &mut Env) -> Flow<Value>;
+    /// Number of classes.
+    fn classes(&self) -> usize;
+    fn class(&self, env: &mut Env, i: usize) -> Flow<Value>;
+    /// Number of attributes.
+    fn attrs(&self) -> usize;
+    fn attr_name(&self, env: &mut Env, i: usize) -> Flow<Value>;
+    fn attr_value(&self, env: &mut Env, i: usize) -> Flow<Value>;
+    /// Is this a <self-closing/> tag?
+    fn is_closed(&self) -> bool;
+    /// The tag's HTML, if `optimize()` rendered it.
+    fn html(&self) -> Option<&Prerendered> {
+        None
+    }
+    /// Print the body.
+    fn body(&self, env: &mut Env) -> Flow<()>;
+    /// Print the body as the `children` of a custom tag, in its own
+    /// scope.
+    fn children(&self, env: &mut Env) -> Flow<()> {
+        self.body(env)
+    }
+    /// Run a custom tag's code, with its params and `children` set
+    /// to `args`.
+    fn expand(env: &mut Env, lambda: &Arc<Lambda>, args: Vec<Value>) -> Flow<()>;
+}
+
+impl TagParts for Tag {
+    fn span(&self) -> Span {
+        self.span
+    }
+    fn name(&self, env: &mut Env) -> Flow<Value> {
+        env.run(&self.tag)
+    }
+    fn custom(&self, env: &Env, tagname: &str) -> Option<Value> {
+        match self.custom_name() {
+            Some(custom) => env.lookup_sym(custom),
+            None => env.lookup(&format!("<{}>", tagname)),
+        }
+    }
+    fn has_id(&self) -> bool {
+        self.id.is_some()
+    }
+    fn id(&self, env: &mut Env) -> Flow<Value> {
+        env.run(&self.id)
+    }
+    fn classes(&self) -> usize {
+        self.classes.len()
+    }
+    fn class(&self, env: &mut Env, i: usize) -> Flow<Value> {
+        env.run(&self.classes[i])
+    }
+    fn attrs(&self) -> usize {
+        self.attrs.len()
+    }
+    fn attr_name(&self, env: &mut Env, i: usize) -> Flow<Value> {
+        env.run(&self.attrs[i].0)
+    }
+    fn attr_value(&self, env: &mut Env, i: usize) -> Flow<Value> {
+        env.run(&self.attrs[i].1)
+    }
+    fn is_closed(&self) -> bool {
+        self.closed
+    }
+    fn html(&self) -> Option<&Prerendered> {
+        self.html.as_deref()
+    }
+    fn body(&self, env: &mut Env) -> Flow<()> {
+        env.inner_printed_block(&self.body).map(|_| ())
+    }
+    fn expand(env: &mut Env, lambda: &Arc<Lambda>, mut args: Vec<Value>) -> Flow<()> {
+        let children = args.pop().unwrap_or(Value::None);
+        for (param, val) in lambda.params.iter().zip(args) {
+            env.set(param, val);
+        }
+        env.set("children", children);
+        env.inner_printed_block(&lambda.body).map(|_| ())
     }
 }
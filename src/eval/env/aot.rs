@@ -0,0 +1,305 @@
+//! What templates compiled to Rust by `hatter::aot` run. Generated
+//! code calls these instead of evaluating `Stmt`s, and hands the
+//! tree-walker anything it didn't compile, like calls to Hatter
+//! functions and custom tags.
+//!
+//! None of this is meant to be used by hand.
+
+use {
+    super::{Env, Flow, TagParts, Unwind},
+    crate::{Args, Fn, Lambda, Result, Span, Stmt, Symbol, Tag as StmtTag, Value},
+    std::sync::{Arc, OnceLock},
+};
+
+/// A compiled expression.
+pub type Expr<'a> = &'a dyn std::ops::Fn(&mut Env) -> Flow<Value>;
+
+/// What a compiled template needs at runtime: its names, as
+/// `Symbol`s, and the `Stmt`s it leaves to the tree-walker, which
+/// are built by `build` the first time they're needed.
+pub struct Parts {
+    pub syms: Vec<Symbol>,
+    nodes: Vec<OnceLock<Stmt>>,
+    stmts: OnceLock<Vec<Stmt>>,
+    build: fn(&Parts, usize) -> Stmt,
+}
+
+impl Parts {
+    pub fn new(syms: &[&str], nodes: usize, build: fn(&Parts, usize) -> Stmt) -> Parts {
+        Parts {
+            syms: syms.iter().map(|s| Symbol::from(*s)).collect(),
+            nodes: (0..nodes).map(|_| OnceLock::new()).collect(),
+            stmts: OnceLock::new(),
+            build,
+        }
+    }
+
+    /// The `i`th `Stmt`.
+    pub fn node(&self, i: usize) -> &Stmt {
+        self.nodes[i].get_or_init(|| (self.build)(self, i))
+    }
+
+    /// All of them, in order. Layouts are compiled as a list of
+    /// their top level `Stmt`s.
+    pub fn stmts(&self) -> &[Stmt] {
+        self.stmts.get_or_init(|| {
+            (0..self.nodes.len())
+                .map(|i| (self.build)(self, i))
+                .collect()
+        })
+    }
+}
+
+/// Render a template, like `Env::render_stmts()`. `head` is whether
+/// it starts with a <head> tag, so it gets wrapped in <html>.
+pub fn render<F>(env: &mut Env, head: bool, body: F) -> Result<String>
+where
+    F: FnOnce(&mut Env) -> Flow<()>,
+{
+    if head {
+        env.print(Value::Html("<!DOCTYPE html>\n<html>".into()));
+    }
+    let res = body(env);
+    let res = env.settle(res);
+    if res.is_ok() && head {
+        env.print(Value::Html("\n</html>\n".into()));
+    }
+    env.blocks.clear();
+    if res.is_err() {
+        env.out.clear();
+    }
+    res?;
+    Ok(env.out())
+}
+
+/// Give errors without a position this one.
+pub fn at<T>(env: &Env, res: Flow<T>, span: Span) -> Flow<T> {
+    res.map_err(|u| match u {
+        Unwind::Error(e) => Unwind::Error(e.at(span, env.file.as_ref())),
+        jump => jump,
+    })
+}
+
+/// Evaluate a `Stmt` with the tree-walker.
+pub fn run(env: &mut Env, stmt: &Stmt) -> Flow<Value> {
+    env.run(stmt)
+}
+
+/// Print a value, unless it's `None`.
+pub fn print(env: &mut Env, val: Value) {
+    if !matches!(val, Value::None) {
+        env.print(val);
+    }
+}
+
+/// Look up a var, or `break` or `continue`.
+pub fn word(env: &mut Env, name: &Symbol, span: Span) -> Flow<Value> {
+    match env.lookup_sym(name) {
+        Some(val) => Ok(val),
+        // jumps and errors
+        None => env.run(&Stmt::Word(name.clone(), span)),
+    }
+}
+
+/// A word in a tag body: text, unless it's a var or keyword.
+pub fn print_word(env: &mut Env, name: &Symbol, span: Span) -> Flow<()> {
+    if env.is_keyword_or_var(name) {
+        let val = word(env, name, span)?;
+        print(env, val);
+    } else {
+        env.print(name);
+    }
+    Ok(())
+}
+
+/// Call `f`, which `node` names. Native functions get the `args`
+/// compiled with the call, everything else goes through the
+/// tree-walker, which knows about keyword args and special forms.
+pub fn call<A>(env: &mut Env, f: Value, node: &Stmt, args: A) -> Flow<Value>
+where
+    A: FnOnce(&mut Env) -> Flow<Vec<Value>>,
+{
+    match (f, node) {
+        (Value::Fn(Fn::Native(f)), Stmt::Call(target, _, span)) => {
+            let res = args(env).and_then(|args| {
+                f(Args::new(env, args)).map_err(|e| env.called(e, target.to_str(), *span).into())
+            });
+            at(env, res, *span)
+        }
+        _ => env.run(node),
+    }
+}
+
+/// `name := val` or `name = val`.
+pub fn assign<F>(env: &mut Env, name: &Symbol, is_reassign: bool, span: Span, val: F) -> Flow<()>
+where
+    F: FnOnce(&mut Env) -> Flow<Value>,
+{
+    let res = env.assign(name, is_reassign, val);
+    at(env, res, span)
+}
+
+/// Run `body` in a new scope, like the body of an `if`.
+pub fn scoped<F>(env: &mut Env, body: F) -> Flow<()>
+where
+    F: FnOnce(&mut Env) -> Flow<()>,
+{
+    env.push_scope();
+    let res = body(env);
+    env.pop_scope();
+    res
+}
+
+/// A `for` loop over `iter`.
+pub fn for_each<F>(
+    env: &mut Env,
+    key: Option<&Symbol>,
+    var: &Symbol,
+    iter: Value,
+    body: F,
+) -> Flow<()>
+where
+    F: FnMut(&mut Env) -> Flow<()>,
+{
+    env.for_each(key, var, iter, body)
+}
+
+/// A `while` loop.
+pub fn while_loop<T, B>(env: &mut Env, test: T, body: B) -> Flow<()>
+where
+    T: FnMut(&mut Env) -> Flow<Value>,
+    B: FnMut(&mut Env) -> Flow<()>,
+{
+    env.while_loop(test, body)
+}
+
+/// Print a tag whose name and attributes were known when it was
+/// compiled, so its HTML is too: `open` and `close`, which is `None`
+/// if it's <self-closing/>. If it's been defined as a custom tag,
+/// `node` is expanded by the tree-walker instead.
+pub fn static_tag<F>(
+    env: &mut Env,
+    custom: &Symbol,
+    node: &Stmt,
+    open: &str,
+    close: Option<&str>,
+    body: F,
+) -> Flow<()>
+where
+    F: FnOnce(&mut Env) -> Flow<()>,
+{
+    let Stmt::Tag(tag) = node else {
+        return env.run(node).map(|_| ());
+    };
+    if let Some(Value::Fn(Fn::Fn(..))) = env.lookup_sym(custom) {
+        return env.print_tag(tag);
+    }
+    env.out.push_str(open);
+    let Some(close) = close else {
+        return Ok(());
+    };
+    let res = env.flush().map_err(Unwind::from).and_then(|_| body(env));
+    at(env, res, tag.span)?;
+    env.out.push_str(close);
+    Ok(())
+}
+
+/// Print a tag `optimize()` rendered when it was compiled, unless
+/// one of the names in `unset` has been defined since. Then `node` is
+/// printed by the tree-walker instead.
+pub fn html(env: &mut Env, unset: &[&Symbol], html: &str, node: &Stmt) -> Flow<()> {
+    if !env.raw_text && unset.iter().all(|name| env.lookup_sym(name).is_none()) {
+        env.out.push_str(html);
+        return Ok(());
+    }
+    match node {
+        Stmt::Tag(tag) => env.print_tag(tag),
+        _ => env.run(node).map(|_| ()),
+    }
+}
+
+/// Print a tag with parts that have to be evaluated.
+pub fn tag(env: &mut Env, tag: &Tag) -> Flow<()> {
+    env.print_tag(tag)
+}
+
+/// A compiled <tag>.
+pub struct Tag<'a> {
+    pub span: Span,
+    pub name: Expr<'a>,
+    pub custom: Option<&'a Symbol>, // "<name>", if the name is a literal
+    pub id: Option<Expr<'a>>,
+    pub classes: &'a [Expr<'a>],
+    pub attrs: &'a [(Expr<'a>, Expr<'a>)],
+    pub closed: bool,
+    pub body: &'a dyn std::ops::Fn(&mut Env) -> Flow<()>,
+}
+
+impl TagParts for Tag<'_> {
+    fn span(&self) -> Span {
+        self.span
+    }
+    fn name(&self, env: &mut Env) -> Flow<Value> {
+        (self.name)(env)
+    }
+    fn custom(&self, env: &Env, tagname: &str) -> Option<Value> {
+        match self.custom {
+            Some(custom) => env.lookup_sym(custom),
+            None => env.lookup(&format!("<{}>", tagname)),
+        }
+    }
+    fn has_id(&self) -> bool {
+        self.id.is_some()
+    }
+    fn id(&self, env: &mut Env) -> Flow<Value> {
+        match self.id {
+            Some(id) => id(env),
+            None => Ok(Value::None),
+        }
+    }
+    fn classes(&self) -> usize {
+        self.classes.len()
+    }
+    fn class(&self, env: &mut Env, i: usize) -> Flow<Value> {
+        (self.classes[i])(env)
+    }
+    fn attrs(&self) -> usize {
+        self.attrs.len()
+    }
+    fn attr_name(&self, env: &mut Env, i: usize) -> Flow<Value> {
+        (self.attrs[i].0)(env)
+    }
+    fn attr_value(&self, env: &mut Env, i: usize) -> Flow<Value> {
+        (self.attrs[i].1)(env)
+    }
+    fn is_closed(&self) -> bool {
+        self.closed
+    }
+    fn body(&self, env: &mut Env) -> Flow<()> {
+        (self.body)(env)
+    }
+    fn expand(env: &mut Env, lambda: &Arc<Lambda>, args: Vec<Value>) -> Flow<()> {
+        StmtTag::expand(env, lambda, args)
+    }
+}
+
+/// The HTML that opens `tag`, if its name and attributes are all
+/// literals, and what closes it, if it isn't <self-closing/>. Tags
+/// that need the `Env`, like <script> and <debug>, get `None`.
+pub(crate) fn static_html(tag: &StmtTag) -> Option<(String, Option<String>)> {
+    let literal = |stmt: &Stmt| matches!(stmt, Stmt::Value(..));
+    let name = match &*tag.tag {
+        Stmt::Value(Value::String(name)) => name.to_string(),
+        _ => return None,
+    };
+    if matches!(name.as_ref(), "debug" | "script" | "style")
+        || !(tag.id.is_none() || literal(&tag.id))
+        || !tag.classes.iter().all(literal)
+        || !tag.attrs.iter().all(|(k, v)| literal(k) && literal(v))
+    {
+        return None;
+    }
+    let open = Env::new().open_tag(tag, &name).ok()?;
+    let close = (!tag.closed).then(|| format!("</{}>\n", name));
+    Some((open, close))
+}
@@ -0,0 +1,663 @@
+//! Stack VM that runs the bytecode from `compile::bytecode`. It lives
+//! inside `Env`, shares its scopes and output, and behaves just like
+//! the tree-walker: same output, same errors.
+
+use {
+    super::{Env, Flow, Jump, TagParts, Unwind},
+    crate::{
+        compile::bytecode::{function, Code, Loop, Op, TagCode, Target, Unit, NO_CHAIN},
+        Args, Fn, Lambda, OMap, Prerendered, Result, Scope, Span, Stmt, Symbol, Value,
+    },
+    std::{mem, sync::Arc},
+};
+
+/// A running template or function.
+#[derive(Debug)]
+pub(super) struct Frame {
+    unit: Arc<Unit>,
+    slots: Vec<Option<Value>>,
+}
+
+impl Env<'_> {
+    /// Render bytecode into a String. Like `render_stmts()`, but
+    /// faster when the same `Code` is rendered more than once.
+    pub fn render_code(&mut self, code: &Code) -> Result<String> {
+        // layouts are handled by the tree-walker
+        if code.stmts().iter().any(|s| matches!(s, Stmt::Extends(..))) {
+            return self.render_stmts(code.stmts());
+        }
+
+        let autohtml = self.first_is_head(code.stmts());
+        if autohtml {
+            self.print(Value::Html("<!DOCTYPE html>\n<html>".into()));
+        }
+        let res = self.enter(code.page(), vec![]);
+        let res = self.settle(res);
+        if autohtml && res.is_ok() {
+            self.print(Value::Html("\n</html>\n".into()));
+        }
+        self.blocks.clear();
+        if res.is_err() {
+            self.out.clear();
+        }
+        res?;
+        Ok(self.out())
+    }
+
+    /// Evaluate bytecode, returning the last statement's Value.
+    pub fn eval_code(&mut self, code: &Code) -> Result<Value> {
+        let res = self.enter(code.script(), vec![]);
+        self.settle(res)
+    }
+
+    /// Find a variable that lives in a slot, looking in the most
+    /// recently called function first. Hatter is dynamically scoped,
+    /// so functions can see their callers' variables.
+    pub(super) fn lookup_slot(&self, key: &Symbol) -> Option<Value> {
+        self.frames.iter().rev().find_map(|frame| {
+            frame
+                .slots
+                .iter()
+                .zip(&frame.unit.slots)
+                .rev()
+                .find(|(val, name)| val.is_some() && *name == key)
+                .and_then(|(val, _)| val.clone())
+        })
+    }
+
+    /// Run `unit` in a new frame, with its params set to `args`.
+    fn enter(&mut self, unit: &Arc<Unit>, args: Vec<Value>) -> Flow<Value> {
+        let mut slots = vec![None; unit.slots.len()];
+        for (param, val) in unit.params.iter().zip(args) {
+            match param {
+                Target::Slot(slot) => slots[*slot as usize] = Some(val),
+                Target::Name(idx) => self.set(&unit.names[*idx as usize], val),
+            }
+        }
+        self.frames.push(Frame {
+            unit: unit.clone(),
+            slots,
+        });
+        let res = self.exec(unit, unit.body);
+        self.frames.pop();
+        res
+    }
+
+    /// Run a block, returning the value it leaves on the stack.
+    fn exec(&mut self, unit: &Arc<Unit>, block: u32) -> Flow<Value> {
+        let code = &unit.blocks[block as usize];
+        if code.scope {
+            self.push_scope();
+        }
+        let base = self.stack.len();
+        let mut res = Ok(());
+        for (op, chain) in code.ops.iter().zip(&code.chains) {
+            if let Err(err) = self.step(unit, *op, *chain) {
+                res = Err(match err {
+                    Unwind::Error(mut err) => {
+                        // like `run()`, errors get their statement's span
+                        let mut chain = *chain;
+                        while chain != NO_CHAIN && !err.has_pos() {
+                            let (span, parent) = unit.chains[chain as usize];
+                            err = err.at(span, self.file.as_ref());
+                            chain = parent;
+                        }
+                        Unwind::Error(err)
+                    }
+                    jump => jump,
+                });
+                break;
+            }
+        }
+        let out = if self.stack.len() > base {
+            self.stack.pop()
+        } else {
+            None
+        };
+        self.stack.truncate(base);
+        if code.scope {
+            self.pop_scope();
+        }
+        self.clear_slots(code.clear);
+        res.map(|_| out.unwrap_or(Value::None))
+    }
+
+    /// Run one op.
+    fn step(&mut self, unit: &Arc<Unit>, op: Op, chain: u32) -> Flow<()> {
+        match op {
+            Op::Const(idx) => self.stack.push(unit.consts[idx as usize].clone()),
+            Op::Load(slot) => {
+                let val = self.get(unit, Target::Slot(slot));
+                self.stack
+                    .push(val.ok_or_else(|| undefined(&unit.slots[slot as usize]))?);
+            }
+            Op::LoadName(idx) => {
+                let name = &unit.names[idx as usize];
+                self.stack
+                    .push(self.lookup_sym(name).ok_or_else(|| undefined(name))?);
+            }
+            Op::Word(target) => {
+                if let Some(val) = self.get(unit, target) {
+                    self.stack.push(val);
+                } else {
+                    let name = unit.name(target);
+                    if name == "return" {
+                        return Err(undefined(name));
+                    }
+                    self.print(name);
+                    self.stack.push(Value::None);
+                }
+            }
+            Op::List(len) => {
+                let list = self.stack.split_off(self.stack.len() - len as usize);
+                self.stack.push(list.into());
+            }
+            Op::Map(idx) => {
+                let keys = &unit.maps[idx as usize];
+                let vals = self.stack.split_off(self.stack.len() - keys.len());
+                let mut map = OMap::new();
+                for (k, v) in keys.iter().zip(vals) {
+                    map.insert(k, v);
+                }
+                self.stack.push(Value::Map(map.into()));
+            }
+            Op::Call(idx) => {
+                let val = self.call_site(unit, idx)?;
+                self.stack.push(val);
+            }
+            Op::Fn(idx) => self.stack.push(Value::Fn(Fn::Fn(
+                unit.lambdas[idx as usize].clone(),
+                self.scope().clone(),
+                self.file.clone(),
+            ))),
+            Op::Tag(idx) => {
+                let val = self.eval_tag(&VmTag::new(unit, idx))?;
+                self.stack.push(val);
+            }
+            Op::PrintTag(idx) => {
+                self.print_tag(&VmTag::new(unit, idx))?;
+                self.stack.push(Value::None);
+            }
+            Op::Stmt(idx) => {
+                let val = self.run(&unit.stmts[idx as usize])?;
+                self.stack.push(val);
+            }
+            Op::Check(idx) => {
+                let assign = &unit.assigns[idx as usize];
+                let exists = self.has(unit, assign.target);
+                if exists && !assign.reassign {
+                    let name = assign.name.to_string();
+                    return Err(error_kind!(AlreadyDefined { name }, "{} already set", name).into());
+                } else if !exists && assign.reassign {
+                    let name = assign.name.to_string();
+                    return Err(
+                        error_kind!(UndefinedVariable { name }, "{} is not set", name).into(),
+                    );
+                }
+            }
+            Op::Store(idx) => {
+                let assign = &unit.assigns[idx as usize];
+                let val = self.stack.pop().unwrap_or(Value::None);
+                if assign.reassign {
+                    if let Some(old) = self.get(unit, assign.target) {
+                        if old.typename() != val.typename() {
+                            return Err(error_kind!(
+                                TypeMismatch {
+                                    expected: old.typename().to_string(),
+                                    actual: val.typename().to_string(),
+                                },
+                                "{} is type {}",
+                                assign.name,
+                                val.typename()
+                            )
+                            .into());
+                        }
+                    }
+                    self.update_target(unit, assign.target, val);
+                } else {
+                    self.put(unit, assign.target, val);
+                }
+                self.stack.push(Value::None);
+            }
+            Op::If(idx) => {
+                for (test, body) in &unit.ifs[idx as usize] {
+                    if self.exec(unit, *test)?.to_bool() {
+                        self.exec(unit, *body)?;
+                        break;
+                    }
+                }
+                self.stack.push(Value::None);
+            }
+            Op::For(idx) => {
+                let lp = &unit.loops[idx as usize];
+                match self.exec(unit, lp.test)? {
+                    // a copy, like the tree-walker
+                    Value::List(list) => {
+                        let list = list.borrow().clone();
+                        self.exec_for(unit, lp, list.iter().enumerate())?
+                    }
+                    Value::Map(map) => {
+                        let pairs = map
+                            .borrow()
+                            .iter()
+                            .map(|(k, v)| (k.clone(), v.clone()))
+                            .collect::<Vec<_>>();
+                        self.exec_for(unit, lp, pairs.iter().map(|(k, v)| (k, v)))?
+                    }
+                    v => {
+                        return Err(error_kind!(
+                            TypeMismatch {
+                                expected: "list or map".to_string(),
+                                actual: v.typename().to_string(),
+                            },
+                            "expected List or Map, got {:?}",
+                            v
+                        )
+                        .into())
+                    }
+                }
+                self.stack.push(Value::None);
+            }
+            Op::While(idx) => {
+                self.exec_while(unit, &unit.loops[idx as usize])?;
+                self.stack.push(Value::None);
+            }
+            Op::Print => {
+                if let Some(val) = self.stack.last() {
+                    if !matches!(val, Value::None) {
+                        let val = val.clone();
+                        self.print(val);
+                    }
+                }
+            }
+            Op::Echo => {
+                if let Some(val) = self.stack.last().cloned() {
+                    self.print(val);
+                }
+            }
+            Op::Flush => self.flush()?,
+            Op::Pop => {
+                self.stack.pop();
+            }
+            Op::Return => {
+                let val = self.stack.pop().unwrap_or(Value::None);
+                return Err(Unwind::Jump(Jump::Return(val), span(unit, chain)));
+            }
+            Op::Break => return Err(Unwind::Jump(Jump::Break, span(unit, chain))),
+            Op::Continue => return Err(Unwind::Jump(Jump::Continue, span(unit, chain))),
+        }
+        Ok(())
+    }
+
+    /// Value of a variable, if it's set.
+    fn get(&self, unit: &Unit, target: Target) -> Option<Value> {
+        match target {
+            Target::Slot(slot) => {
+                let frame = self.frames.last()?;
+                match &frame.slots[slot as usize] {
+                    Some(val) => Some(val.clone()),
+                    None => self.lookup_sym(&unit.slots[slot as usize]),
+                }
+            }
+            Target::Name(idx) => self.lookup_sym(&unit.names[idx as usize]),
+        }
+    }
+
+    /// Is a variable set?
+    fn has(&self, unit: &Unit, target: Target) -> bool {
+        match target {
+            Target::Slot(slot) => {
+                matches!(self.frames.last(), Some(f) if f.slots[slot as usize].is_some())
+                    || self.lookup_sym(&unit.slots[slot as usize]).is_some()
+            }
+            Target::Name(idx) => self.lookup_sym(&unit.names[idx as usize]).is_some(),
+        }
+    }
+
+    /// Set a new variable, like `set()`.
+    fn put(&mut self, unit: &Unit, target: Target, val: Value) {
+        match target {
+            Target::Slot(slot) => {
+                if let Some(frame) = self.frames.last_mut() {
+                    frame.slots[slot as usize] = Some(val);
+                }
+            }
+            Target::Name(idx) => self.set(&unit.names[idx as usize], val),
+        }
+    }
+
+    /// Change an existing variable, like `update()`, wherever it is.
+    fn update_target(&mut self, unit: &Unit, target: Target, val: Value) {
+        let name = match target {
+            Target::Slot(slot) => {
+                let frame = self.frames.last_mut().expect("update_target");
+                if frame.slots[slot as usize].is_some() {
+                    frame.slots[slot as usize] = Some(val);
+                    return;
+                }
+                &unit.slots[slot as usize]
+            }
+            Target::Name(idx) => &unit.names[idx as usize],
+        };
+        if self.find_scope(name).is_none() {
+            for frame in self.frames.iter_mut().rev() {
+                let found = frame
+                    .slots
+                    .iter()
+                    .zip(&frame.unit.slots)
+                    .rposition(|(val, slot)| val.is_some() && slot == name);
+                if let Some(slot) = found {
+                    frame.slots[slot] = Some(val);
+                    return;
+                }
+            }
+        }
+        self.update(name, val);
+    }
+
+    /// Unset slots that have gone out of scope.
+    fn clear_slots(&mut self, (start, end): (u32, u32)) {
+        if start < end {
+            if let Some(frame) = self.frames.last_mut() {
+                for slot in &mut frame.slots[start as usize..end as usize] {
+                    *slot = None;
+                }
+            }
+        }
+    }
+
+    /// Forget a loop's variables before its next iteration.
+    fn clear_loop(&mut self, lp: &Loop) {
+        if lp.scope {
+            self.scope().borrow_mut().clear();
+        } else {
+            self.clear_slots(lp.clear);
+        }
+    }
+
+    /// Run a for loop over both maps and lists.
+    fn exec_for<'o, K>(
+        &mut self,
+        unit: &Arc<Unit>,
+        lp: &Loop,
+        iter: impl Iterator<Item = (K, &'o Value)>,
+    ) -> Flow<()>
+    where
+        K: Into<Value>,
+    {
+        if lp.scope {
+            self.push_scope();
+        }
+        let mut res = Ok(());
+        for (k, v) in iter {
+            if let Some(key) = lp.key {
+                self.put(unit, key, k.into());
+            }
+            if let Some(val) = lp.val {
+                self.put(unit, val, v.clone());
+            }
+            match self.exec(unit, lp.body) {
+                Ok(_) => {}
+                Err(Unwind::Jump(Jump::Break, _)) => break,
+                // the tree-walker doesn't clear the scope either
+                Err(Unwind::Jump(Jump::Continue, _)) => continue,
+                Err(e) => {
+                    res = Err(e);
+                    break;
+                }
+            }
+            self.clear_loop(lp);
+        }
+        if lp.scope {
+            self.pop_scope();
+        }
+        self.clear_slots(lp.clear);
+        res
+    }
+
+    /// Run a while loop.
+    fn exec_while(&mut self, unit: &Arc<Unit>, lp: &Loop) -> Flow<()> {
+        if lp.scope {
+            self.push_scope();
+        }
+        let mut res = Ok(());
+        loop {
+            match self.exec(unit, lp.test) {
+                Ok(test) if test.to_bool() => {}
+                Ok(_) => break,
+                Err(e) => {
+                    res = Err(e);
+                    break;
+                }
+            }
+            match self.exec(unit, lp.body) {
+                Ok(_) => {}
+                Err(Unwind::Jump(Jump::Break, _)) => break,
+                Err(Unwind::Jump(Jump::Continue, _)) => continue,
+                Err(e) => {
+                    res = Err(e);
+                    break;
+                }
+            }
+            self.clear_loop(lp);
+        }
+        if lp.scope {
+            self.pop_scope();
+        }
+        self.clear_slots(lp.clear);
+        res
+    }
+
+    /// Call the function on top of the stack.
+    fn call_site(&mut self, unit: &Arc<Unit>, idx: u32) -> Flow<Value> {
+        let site = &unit.calls[idx as usize];
+        let name = site.name.to_str();
+        let f = match self.stack.pop() {
+            Some(Value::Fn(f)) => f,
+            _ => {
+                let name = site.display.clone();
+                return Err(
+                    error_kind!(NotCallable { name }, "can't find fn: {}", site.display).into(),
+                );
+            }
+        };
+
+        match f {
+            // `&&` and `||` short-circuit without the tree-walker
+            Fn::Special(_)
+                if site.keywords.is_none()
+                    && site.args.len() == 2
+                    && matches!(name, "&&" | "||") =>
+            {
+                let res = self.exec(unit, site.args[0]);
+                let left = self.settle(res)?.to_bool();
+                if left != (name == "&&") {
+                    return Ok(left.into());
+                }
+                let res = self.exec(unit, site.args[1]);
+                Ok(self.settle(res)?.to_bool().into())
+            }
+            Fn::Special(f) => Ok(f(self, &site.stmts)?),
+            Fn::Native(f) => {
+                let args = if site.keywords.is_some() {
+                    // same as the tree-walker
+                    site.stmts
+                        .iter()
+                        .map(|a| self.run(a))
+                        .collect::<Flow<Vec<_>>>()?
+                } else {
+                    self.exec_args(unit, &site.args)?
+                };
+                Ok(f(Args::new(self, args)).map_err(|e| self.called(e, name, site.span))?)
+            }
+            Fn::Fn(lambda, scope, file) => {
+                let params = &lambda.params;
+                let mut blocks = site.args.clone();
+                if let Some(keywords) = &site.keywords {
+                    blocks = params
+                        .iter()
+                        .filter_map(|p| keywords.iter().position(|k| k == p))
+                        .map(|i| site.args[i])
+                        .collect();
+                }
+                // no matching keywords means one arg: the keywords
+                let len = if blocks.is_empty() && site.keywords.is_some() {
+                    1
+                } else {
+                    blocks.len()
+                };
+                if params.len() != len {
+                    return Err(error_kind!(
+                        ArityMismatch {
+                            expected: params.len(),
+                            actual: len,
+                        },
+                        "expected {} args, got {}",
+                        params.len(),
+                        len
+                    )
+                    .into());
+                }
+                // args are evaluated in the caller's scope
+                let args = if blocks.len() != len {
+                    vec![self.run(&site.stmts[0])?]
+                } else {
+                    self.exec_args(unit, &blocks)?
+                };
+                Ok(self
+                    .call_lambda(&lambda, scope, file, args)
+                    .map_err(|e| self.called(e, name, site.span))?)
+            }
+        }
+    }
+
+    /// Evaluate arguments.
+    fn exec_args(&mut self, unit: &Arc<Unit>, blocks: &[u32]) -> Flow<Vec<Value>> {
+        blocks.iter().map(|b| self.exec(unit, *b)).collect()
+    }
+
+    /// Call a Hatter function, compiling it the first time.
+    fn call_lambda(
+        &mut self,
+        lambda: &Arc<Lambda>,
+        scope: Scope,
+        file: Option<Symbol>,
+        args: Vec<Value>,
+    ) -> Result<Value> {
+        let unit = lambda
+            .code
+            .get_or_init(|| arc!(function(lambda, false)))
+            .clone();
+        self.scopes.push(scope);
+        self.push_scope();
+        let file = mem::replace(&mut self.file, file);
+        // `break` and `continue` can't leave a function
+        let out = match self.enter(&unit, args) {
+            Err(Unwind::Jump(Jump::Return(v), _)) => Ok(v),
+            res => self.settle(res),
+        };
+        self.file = file;
+        self.pop_scope();
+        self.pop_scope();
+        out
+    }
+}
+
+/// Span of the statement an op came from.
+fn span(unit: &Unit, chain: u32) -> Span {
+    unit.chains
+        .get(chain as usize)
+        .map(|(span, _)| *span)
+        .unwrap_or_default()
+}
+
+/// Error for a variable that isn't set.
+fn undefined(name: &Symbol) -> Unwind {
+    let name = name.to_string();
+    error_kind!(UndefinedVariable { name }, "can't find var: {}", name).into()
+}
+
+impl Unit {
+    /// Name of a variable.
+    fn name(&self, target: Target) -> &Symbol {
+        match target {
+            Target::Slot(slot) => &self.slots[slot as usize],
+            Target::Name(idx) => &self.names[idx as usize],
+        }
+    }
+}
+
+/// A <tag> in bytecode.
+struct VmTag<'u> {
+    unit: &'u Arc<Unit>,
+    code: &'u TagCode,
+}
+
+impl<'u> VmTag<'u> {
+    fn new(unit: &'u Arc<Unit>, idx: u32) -> VmTag<'u> {
+        VmTag {
+            unit,
+            code: &unit.tags[idx as usize],
+        }
+    }
+}
+
+impl TagParts for VmTag<'_> {
+    fn span(&self) -> Span {
+        self.code.span
+    }
+    fn name(&self, env: &mut Env) -> Flow<Value> {
+        env.exec(self.unit, self.code.name)
+    }
+    fn custom(&self, env: &Env, tagname: &str) -> Option<Value> {
+        match &self.code.custom {
+            Some(custom) => env.lookup_sym(custom),
+            None => env.lookup(&format!("<{}>", tagname)),
+        }
+    }
+    fn has_id(&self) -> bool {
+        self.code.id.is_some()
+    }
+    fn id(&self, env: &mut Env) -> Flow<Value> {
+        match self.code.id {
+            Some(id) => env.exec(self.unit, id),
+            None => Ok(Value::None),
+        }
+    }
+    fn classes(&self) -> usize {
+        self.code.classes.len()
+    }
+    fn class(&self, env: &mut Env, i: usize) -> Flow<Value> {
+        env.exec(self.unit, self.code.classes[i])
+    }
+    fn attrs(&self) -> usize {
+        self.code.attrs.len()
+    }
+    fn attr_name(&self, env: &mut Env, i: usize) -> Flow<Value> {
+        env.exec(self.unit, self.code.attrs[i].0)
+    }
+    fn attr_value(&self, env: &mut Env, i: usize) -> Flow<Value> {
+        env.exec(self.unit, self.code.attrs[i].1)
+    }
+    fn is_closed(&self) -> bool {
+        self.code.closed
+    }
+    fn html(&self) -> Option<&Prerendered> {
+        self.code.html.as_deref()
+    }
+    fn body(&self, env: &mut Env) -> Flow<()> {
+        env.exec(self.unit, self.code.body).map(|_| ())
+    }
+    fn children(&self, env: &mut Env) -> Flow<()> {
+        let res = self.body(env);
+        env.clear_slots(self.code.declared);
+        res
+    }
+    fn expand(env: &mut Env, lambda: &Arc<Lambda>, args: Vec<Value>) -> Flow<()> {
+        let unit = lambda
+            .tag_code
+            .get_or_init(|| arc!(function(lambda, true)))
+            .clone();
+        env.enter(&unit, args).map(|_| ())
+    }
+}
@@ -1,4 +1,5 @@
 pub mod args;
 pub mod builtin;
 pub mod env;
+mod output;
 pub mod value;
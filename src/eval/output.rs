@@ -0,0 +1,34 @@
+//! Places besides a String that `Env` can stream rendered HTML into.
+
+use {
+    crate::Result,
+    std::{fmt, io},
+};
+
+/// A writer rendered HTML is written to as it's produced, borrowed
+/// for one call to `Env::render_to_io()` or `render_to_fmt()`.
+pub(crate) enum Output<'w> {
+    Io(&'w mut dyn io::Write),
+    Fmt(&'w mut dyn fmt::Write),
+}
+
+impl Output<'_> {
+    /// Write a chunk of HTML.
+    pub(crate) fn write(&mut self, html: &str) -> Result<()> {
+        match self {
+            Output::Io(out) => Ok(out.write_all(html.as_bytes())?),
+            Output::Fmt(out) => {
+                if out.write_str(html).is_err() {
+                    return error!("couldn't write output");
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Output<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Output")
+    }
+}
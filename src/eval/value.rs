@@ -3,12 +3,17 @@
 
 use {
     crate::{Result, Symbol},
-    std::{fmt, rc::Rc},
+    std::{fmt, sync::Arc},
 };
 
 mod from;
 mod inner;
-pub use inner::{Fn, List, Map, Native, Object, Special};
+pub use inner::{Fn, Lambda, List, Map, Native, Object, Special};
+
+#[cfg(feature = "serde")]
+mod serde;
+#[cfg(feature = "serde")]
+pub use self::serde::to_value;
 
 /// Value is the dynamic type that represents an active Hatter object,
 /// including objects you define in your own code.
@@ -18,17 +23,18 @@ pub enum Value {
     Bool(bool),
     Number(f64),
     String(Symbol),
+    Html(Symbol),
     List(List),
     Map(Map),
     Fn(Fn),
-    Object(Rc<dyn Object>),
+    Object(Arc<dyn Object>),
 }
 
 impl fmt::Display for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Value::Number(num) => write!(f, "{}", num),
-            Value::String(s) => write!(f, "{}", s),
+            Value::String(s) | Value::Html(s) => write!(f, "{}", s),
             Value::List(..) => write!(f, "{:?}", self),
             _ => write!(f, "{}", self.to_str()),
         }
@@ -43,6 +49,7 @@ impl fmt::Debug for Value {
             Bool(b) => write!(f, "{}", b),
             Number(num) => write!(f, "{}", num),
             String(s) => write!(f, r#""{}""#, s),
+            Html(s) => write!(f, "Html({:?})", s.to_str()),
             Fn(..) => f.debug_struct("Function").field("val", &"?").finish(),
             List(list) => write!(
                 f,
@@ -81,6 +88,13 @@ impl PartialEq for Value {
                     false
                 }
             }
+            Value::Html(s) => {
+                if let Value::Html(s2) = other {
+                    s == s2
+                } else {
+                    false
+                }
+            }
             Value::List(list) => {
                 if let Value::List(l2) = other {
                     list == l2
@@ -122,7 +136,7 @@ impl Value {
         match self {
             Value::List(list) => list.borrow().len(),
             Value::Map(map) => map.borrow().len(),
-            Value::String(s) => s.len(),
+            Value::String(s) | Value::Html(s) => s.len(),
             _ => 0,
         }
     }
@@ -151,7 +165,7 @@ impl Value {
         use Value::*;
         match self {
             None => "",
-            String(s) => &s.to_str(),
+            String(s) | Html(s) => s.to_str(),
             Number(..) => "(number)",
             Fn(..) => "{function}",
             List(..) => "(list)",
@@ -174,6 +188,7 @@ impl Value {
             Bool(..) => "bool",
             Number(..) => "number",
             String(..) => "string",
+            Html(..) => "html",
             Fn(..) => "fn",
             List(..) => "list",
             Map(..) => "map",
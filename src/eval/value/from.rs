@@ -2,7 +2,7 @@ use {
     crate::{Args, Fn, OMap, Result, Stmt, Symbol, Value},
     std::{
         collections::{BTreeMap, HashMap},
-        rc::Rc,
+        sync::Arc,
     },
 };
 
@@ -168,10 +168,10 @@ impl From<Stmt> for Value {
 
 impl<F> From<F> for Value
 where
-    F: 'static + std::ops::Fn(Args) -> Result<Value>,
+    F: 'static + std::ops::Fn(Args) -> Result<Value> + Send + Sync,
 {
     fn from(f: F) -> Value {
-        Value::Fn(Fn::Native(rc!(f)))
+        Value::Fn(Fn::Native(arc!(f)))
     }
 }
 
@@ -2,25 +2,31 @@
 //! here.
 
 use {
-    crate::{Args, Env, OMap, Result, Scope, Stmt, Symbol, Value},
-    std::{cell::RefCell, ops::Deref, rc::Rc},
+    crate::{compile::bytecode::Unit, Args, Env, OMap, Result, Scope, Shared, Stmt, Symbol, Value},
+    std::{
+        ops::Deref,
+        sync::{Arc, OnceLock},
+    },
 };
 
 #[derive(Clone)]
-pub struct List(Rc<RefCell<Vec<Value>>>);
+pub struct List(Shared<Vec<Value>>);
 impl List {
     pub fn new(s: Vec<Value>) -> Self {
-        Self(rcell!(s))
+        Self(Shared::new(s))
     }
 }
 impl Deref for List {
-    type Target = Rc<RefCell<Vec<Value>>>;
+    type Target = Shared<Vec<Value>>;
     fn deref(&self) -> &Self::Target {
         &self.0
     }
 }
 impl PartialEq for List {
     fn eq(&self, other: &List) -> bool {
+        if self.ptr_eq(other) {
+            return true;
+        }
         let me = self.borrow();
         let you = other.borrow();
         me.len() == you.len() && me.iter().enumerate().all(|(i, v)| v == &you[i])
@@ -33,20 +39,23 @@ impl From<Vec<Value>> for List {
 }
 
 #[derive(Clone)]
-pub struct Map(Rc<RefCell<OMap>>);
+pub struct Map(Shared<OMap>);
 impl Map {
     pub fn new(m: OMap) -> Self {
-        Self(rcell!(m))
+        Self(Shared::new(m))
     }
 }
 impl Deref for Map {
-    type Target = Rc<RefCell<OMap>>;
+    type Target = Shared<OMap>;
     fn deref(&self) -> &Self::Target {
         &self.0
     }
 }
 impl PartialEq for Map {
     fn eq(&self, other: &Map) -> bool {
+        if self.ptr_eq(other) {
+            return true;
+        }
         let me = self.borrow();
         let you = other.borrow();
         me.len() == you.len() && me.iter().all(|(i, v)| Some(v) == you.get(i))
@@ -65,22 +74,54 @@ impl From<OMap> for Map {
 /// like a macro.
 #[derive(Clone)]
 pub enum Fn {
-    Fn(Vec<Symbol>, Vec<Stmt>, Scope),
-    Native(Rc<Native>),
-    Special(Rc<Special>),
+    Fn(Arc<Lambda>, Scope, Option<Symbol>), // code, scope, template
+    Native(Arc<Native>),
+    Special(Arc<Special>),
+}
+
+/// The code of a function defined in Hatter, shared by every closure
+/// made from it. The VM compiles it to bytecode the first time it's
+/// called, once as a function and once as a custom tag.
+pub struct Lambda {
+    pub params: Vec<Symbol>,
+    pub body: Vec<Stmt>,
+    pub(crate) code: OnceLock<Arc<Unit>>,
+    pub(crate) tag_code: OnceLock<Arc<Unit>>,
+}
+
+impl std::fmt::Debug for Lambda {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Lambda")
+            .field("params", &self.params)
+            .field("body", &self.body)
+            .finish()
+    }
+}
+
+impl Lambda {
+    /// Create a Lambda.
+    pub fn new(params: Vec<Symbol>, body: Vec<Stmt>) -> Lambda {
+        Lambda {
+            params,
+            body,
+            code: OnceLock::new(),
+            tag_code: OnceLock::new(),
+        }
+    }
 }
 
 /// Hatter function defined in Rust.
-pub type Native = dyn std::ops::Fn(Args) -> Result<Value>;
+pub type Native = dyn std::ops::Fn(Args) -> Result<Value> + Send + Sync;
 
 /// Hatter function defined in Rust whose arguments aren't evaluated,
 /// like a macro.
-pub type Special = dyn std::ops::Fn(&mut Env, &[Stmt]) -> Result<Value>;
+pub type Special = dyn std::ops::Fn(&mut Env, &[Stmt]) -> Result<Value> + Send + Sync;
 
 /// If you want to expose your own structs to Hatter code, this is the
-/// trait for you.
+/// trait for you. Objects need to be `Send + Sync` so templates and
+/// their values can be shared between threads.
 #[allow(unused_variables)]
-pub trait Object {
+pub trait Object: Send + Sync {
     /// String name of this type, like "string" or "Person".
     fn typename(&self) -> &str;
 
@@ -0,0 +1,384 @@
+//! The formatter reprints templates in one consistent style:
+//!
+//! - Two spaces of indentation per level, and for lines continuing
+//!   a `[list]`, `{map}`, `(call)`, `<tag>`, or operator.
+//! - One space around binary operators and after commas, none
+//!   inside brackets or around `=` in tags.
+//! - Tag shorthand in the order `<tag#id.class@name:type>`.
+//! - No trailing whitespace, at most one blank line in a row, and a
+//!   newline at the end.
+//!
+//! It works on tokens rather than the AST, so comments, `do`/`then`
+//! one-liners, strings, and text stay exactly as written. Only the
+//! whitespace between tokens changes, and the result is checked to
+//! compile to the same AST before it's returned.
+
+use crate::{
+    compile, scan_lossless, scan_with_comments, Error, ErrorKind, Result, Stmt, Syntax, Token,
+};
+
+/// Size of one level of indentation.
+const INDENT: &str = "  ";
+
+/// Format a template. Templates with syntax errors are returned as
+/// an error, untouched.
+pub fn format(source: &str) -> Result<String> {
+    let ast = without_spans(compile(source)?);
+    let (tokens, _) = scan_lossless(source);
+    let out = Formatter::new(source).format(&tokens);
+
+    if compile(&out).ok().map(without_spans) != Some(ast) || comments(&out) != comments(source) {
+        return Err(Error::new(
+            ErrorKind::RuntimeError,
+            "Formatting would change the meaning of this template, so it was left alone. \
+             Please report this as a bug."
+                .into(),
+            0,
+            0,
+        ));
+    }
+    Ok(out)
+}
+
+/// `stmts`, minus where they are in the source, which formatting
+/// changes.
+fn without_spans(mut stmts: Vec<Stmt>) -> Vec<Stmt> {
+    stmts.iter_mut().for_each(Stmt::clear_spans);
+    stmts
+}
+
+/// Every comment, in order.
+fn comments(source: &str) -> Vec<String> {
+    scan_with_comments(source)
+        .unwrap_or_default()
+        .iter()
+        .filter(|t| t.kind == Syntax::Comment)
+        .map(|t| t.literal().trim_end().to_string())
+        .collect()
+}
+
+/// A token with its exact text in the source, quotes and all.
+#[derive(Debug, Clone, Copy)]
+struct Piece<'s> {
+    kind: Syntax,
+    text: &'s str,
+    start: usize,
+    end: usize,
+    depth: usize, // block depth, from Indent/Dedent tokens
+}
+
+impl Piece<'_> {
+    fn is(&self, kind: Syntax) -> bool {
+        self.kind == kind
+    }
+
+    fn is_op(&self, op: &str) -> bool {
+        self.kind == Syntax::Op && self.text == op
+    }
+
+    /// Can this end an operand, making a following Op binary?
+    fn ends_operand(&self) -> bool {
+        matches!(
+            self.kind,
+            Syntax::Word
+                | Syntax::Number
+                | Syntax::String(..)
+                | Syntax::Bool(..)
+                | Syntax::RParen
+                | Syntax::RStaple
+                | Syntax::RCurly
+        )
+    }
+
+    fn opens(&self) -> bool {
+        matches!(
+            self.kind,
+            Syntax::LParen | Syntax::LStaple | Syntax::LCurly | Syntax::LCaret
+        )
+    }
+
+    fn closes(&self) -> bool {
+        matches!(
+            self.kind,
+            Syntax::RParen | Syntax::RStaple | Syntax::RCurly | Syntax::RCaret
+        )
+    }
+
+    /// `#`, `.`, `@`, and `:` at the start of a tag.
+    fn is_shorthand(&self) -> bool {
+        self.kind == Syntax::Op && matches!(self.text, "#" | "." | "@" | ":")
+    }
+}
+
+/// One line of output.
+struct Line<'s> {
+    pieces: Vec<Piece<'s>>,
+    indent: usize,
+    blank_before: bool,
+    column: usize, // indentation in the source
+}
+
+struct Formatter<'s> {
+    source: &'s str,
+    newlines: Vec<usize>, // where each \n is
+}
+
+impl<'s> Formatter<'s> {
+    fn new(source: &'s str) -> Formatter<'s> {
+        Formatter {
+            source,
+            newlines: source.match_indices('\n').map(|(i, _)| i).collect(),
+        }
+    }
+
+    /// Which line of the source is byte `pos` on?
+    fn line_of(&self, pos: usize) -> usize {
+        match self.newlines.binary_search(&pos) {
+            Ok(i) | Err(i) => i,
+        }
+    }
+
+    /// Is there whitespace in the source between two pieces?
+    fn spaced(&self, a: &Piece, b: &Piece) -> bool {
+        a.end < b.start
+    }
+
+    fn format(&self, tokens: &[Token<'s>]) -> String {
+        let lines = self.lines(&self.pieces(tokens));
+        let mut out = String::new();
+        let mut in_tag = false; // between < and >, which can span lines
+        for (i, line) in lines.iter().enumerate() {
+            if line.blank_before && i > 0 {
+                out.push('\n');
+            }
+            out.push_str(&INDENT.repeat(line.indent));
+            out.push_str(&self.print_line(&line.pieces, &mut in_tag));
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Turn lossless tokens into pieces of source, dropping the
+    /// whitespace and the empty Indents, Dedents, and Semis.
+    fn pieces(&self, tokens: &[Token<'s>]) -> Vec<Piece<'s>> {
+        let mut pieces = vec![];
+        let mut depth = 0;
+        for tok in tokens {
+            match tok.kind {
+                Syntax::Indent => depth += 1,
+                Syntax::Dedent => depth -= 1,
+                Syntax::Whitespace | Syntax::None => {}
+                _ if tok.text().is_empty() => {}
+                _ => pieces.push(Piece {
+                    kind: tok.kind,
+                    text: tok.text(),
+                    start: tok.pos,
+                    end: tok.pos + tok.text().len(),
+                    depth,
+                }),
+            }
+        }
+        pieces
+    }
+
+    /// Split pieces into lines, working out each one's indentation.
+    fn lines(&self, pieces: &[Piece<'s>]) -> Vec<Line<'s>> {
+        let mut lines: Vec<Line> = vec![];
+        let mut open = 0; // brackets and tags open at the start of the line
+        let mut last_end = None;
+        for piece in pieces {
+            let line = self.line_of(piece.start);
+            let new_line = match last_end {
+                None => true,
+                Some(end) => line > self.line_of(end),
+            };
+            if new_line {
+                let line_start = self.source[..piece.start]
+                    .rfind('\n')
+                    .map(|i| i + 1)
+                    .unwrap_or(0);
+                let continued = piece.is(Syntax::Op) && open == 0 && !lines.is_empty();
+                let mut extra = open + continued as usize;
+                if piece.closes() {
+                    extra = extra.saturating_sub(1);
+                }
+                lines.push(Line {
+                    pieces: vec![],
+                    indent: piece.depth + extra,
+                    blank_before: last_end.is_some_and(|end| line > self.line_of(end) + 1),
+                    column: self.source[line_start..piece.start].chars().count(),
+                });
+            }
+            if piece.opens() {
+                open += 1;
+            } else if piece.closes() {
+                open = open.saturating_sub(1);
+            }
+            lines.last_mut().unwrap().pieces.push(*piece);
+            last_end = Some(piece.end);
+        }
+
+        // Comments on their own line go with the code after them,
+        // unless they're indented further than it.
+        for i in 0..lines.len() {
+            if lines[i].pieces.len() != 1 || !lines[i].pieces[0].is(Syntax::Comment) {
+                continue;
+            }
+            let next = lines[i + 1..]
+                .iter()
+                .find(|l| !l.pieces[0].is(Syntax::Comment));
+            lines[i].indent = match next {
+                Some(next) if lines[i].column <= next.column => next.indent,
+                Some(next) => lines[i].indent.max(next.indent),
+                None if lines[i].column == 0 => 0,
+                None => lines[i].indent,
+            };
+        }
+        lines
+    }
+
+    /// Print the pieces on one line with normalized spacing.
+    fn print_line(&self, pieces: &[Piece], in_tag: &mut bool) -> String {
+        let pieces = normalize_tags(pieces);
+        let mut out = String::new();
+        let mut text = false; // in implicit tag text, like `<p> Hi there`
+        for (i, piece) in pieces.iter().enumerate() {
+            if i == 0 || pieces[i - 1].is(Syntax::RCaret) {
+                text = starts_text(&pieces[i..]);
+            }
+
+            if i > 0 {
+                let prev = &pieces[i - 1];
+                let space = if piece.is(Syntax::Comment) {
+                    true
+                } else if *in_tag {
+                    tag_space(prev, piece)
+                } else if text {
+                    self.spaced(prev, piece)
+                } else {
+                    self.code_space(&pieces[..i], piece)
+                };
+                if space {
+                    out.push(' ');
+                }
+            }
+            out.push_str(piece.text.trim_end());
+
+            if piece.is(Syntax::LCaret) {
+                text = false;
+                *in_tag = true;
+            } else if piece.is(Syntax::RCaret) {
+                *in_tag = false;
+            }
+        }
+        out
+    }
+
+    /// Should there be a space before `piece` in code?
+    fn code_space(&self, before: &[Piece], piece: &Piece) -> bool {
+        let prev = &before[before.len() - 1];
+        let binary = |i: usize| i > 0 && before[i - 1].ends_operand();
+
+        // around tags, as written
+        if piece.is(Syntax::LCaret) || prev.is(Syntax::RCaret) {
+            return self.spaced(prev, piece);
+        }
+
+        // brackets, commas, and ;
+        if matches!(
+            piece.kind,
+            Syntax::Comma | Syntax::Colon | Syntax::Semi | Syntax::RParen | Syntax::RStaple
+        ) || matches!(prev.kind, Syntax::LParen | Syntax::LStaple | Syntax::LCurly)
+            || piece.is(Syntax::RCurly)
+        {
+            return false;
+        }
+        if matches!(prev.kind, Syntax::Comma | Syntax::Colon | Syntax::Semi) {
+            return true;
+        }
+
+        // map.key
+        if piece.is_op(".") || prev.is_op(".") {
+            return false;
+        }
+
+        // binary operators, but not ranges
+        if piece.is(Syntax::Op) && !piece.text.starts_with("..") && prev.ends_operand() {
+            return true;
+        }
+        if prev.is(Syntax::Op) && !prev.text.starts_with("..") && binary(before.len() - 1) {
+            return true;
+        }
+
+        self.spaced(prev, piece)
+    }
+}
+
+/// Should there be a space between two pieces inside a `<tag>`?
+fn tag_space(prev: &Piece, piece: &Piece) -> bool {
+    if prev.is(Syntax::LCaret) || prev.is(Syntax::Slash) || piece.is(Syntax::RCaret) {
+        return false;
+    }
+    if prev.is(Syntax::Equal) || piece.is(Syntax::Equal) {
+        return false;
+    }
+    if piece.is(Syntax::Slash) {
+        return true;
+    }
+    // <tag#id.class> shorthand
+    if prev.is_shorthand() || (piece.is_shorthand() && !prev.is(Syntax::RParen)) {
+        return false;
+    }
+    true
+}
+
+/// Do these pieces start the implicit text the parser makes out of
+/// two words in a row inside a tag body?
+fn starts_text(pieces: &[Piece]) -> bool {
+    match (pieces.first(), pieces.get(1)) {
+        (Some(a), Some(b)) if a.is(Syntax::Word) => {
+            b.kind.is_word_or_keyword() || matches!(b.kind, Syntax::Comma | Syntax::Colon)
+        }
+        (Some(a), Some(b)) => a.is(Syntax::Number) && b.is(Syntax::Word),
+        _ => false,
+    }
+}
+
+/// Put the shorthand at the start of each tag in `#id.class@name:type`
+/// order. The parser keeps ids, classes, and other attributes apart,
+/// so this doesn't change what the tag means.
+fn normalize_tags<'s>(pieces: &[Piece<'s>]) -> Vec<Piece<'s>> {
+    let mut out = vec![];
+    let mut i = 0;
+    while i < pieces.len() {
+        out.push(pieces[i]);
+        i += 1;
+        if !pieces[i - 1].is(Syntax::LCaret) || pieces.get(i).is_some_and(|p| p.is(Syntax::Slash)) {
+            continue;
+        }
+        // tag name, if any
+        if pieces.get(i).is_some_and(|p| p.is(Syntax::Word)) {
+            out.push(pieces[i]);
+            i += 1;
+        }
+        // each shorthand is `. class` or `. class = condition`
+        let mut shorthands = vec![];
+        while i + 1 < pieces.len() && pieces[i].is_shorthand() {
+            let len = if pieces.get(i + 2).is_some_and(|p| p.is(Syntax::Equal)) {
+                4
+            } else {
+                2
+            };
+            let len = len.min(pieces.len() - i);
+            shorthands.push(&pieces[i..i + len]);
+            i += len;
+        }
+        shorthands.sort_by_key(|s| match s[0].text {
+            "#" => 0,
+            "." => 1,
+            _ => 2,
+        });
+        out.extend(shorthands.into_iter().flatten());
+    }
+    out
+}
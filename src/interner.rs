@@ -41,6 +41,11 @@ impl Interner {
         id
     }
 
+    /// Id of a string that's already been interned.
+    pub fn get(&self, name: &str) -> Option<u32> {
+        self.map.get(name).copied()
+    }
+
     pub fn lookup(&self, id: u32) -> &'static str {
         self.vec[id as usize]
     }
@@ -0,0 +1,576 @@
+//! JSON encoding and decoding for Values, without any dependencies.
+//!
+//! Encoded JSON is always safe to embed in a `<script>` tag: `<`,
+//! `>`, `&`, and the JavaScript line separators are `\u` escaped.
+
+use {
+    crate::{OMap, Result, Value},
+    std::{char, fmt::Write},
+};
+
+/// Plain JSON, with `String`s instead of `Symbol`s. `Value`'s strings
+/// are interned forever, which is fine for templates but not for
+/// something like the LSP, which gets a whole document on every
+/// keystroke.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    List(Vec<Json>),
+    Map(Vec<(String, Json)>),
+}
+
+#[cfg(feature = "lsp")]
+static NULL: Json = Json::Null;
+
+impl Json {
+    /// Decode JSON.
+    pub(crate) fn parse(json: &str) -> Result<Json> {
+        let mut parser = Parser {
+            json,
+            pos: 0,
+            depth: 0,
+        };
+        parser.skip_space();
+        let val = parser.value()?;
+        parser.skip_space();
+        if parser.pos < json.len() {
+            return parser.error("end of input");
+        }
+        Ok(val)
+    }
+}
+
+// only the LSP needs to pick JSON apart and write it back out
+#[cfg(feature = "lsp")]
+impl Json {
+    /// Encode as JSON.
+    pub(crate) fn to_json(&self) -> String {
+        let mut out = String::new();
+        encode_json(self, &mut out);
+        out
+    }
+
+    /// The value of `key`, if this is a map that has it, or null.
+    pub(crate) fn get(&self, key: &str) -> &Json {
+        match self {
+            Json::Map(pairs) => pairs
+                .iter()
+                .rev()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v)
+                .unwrap_or(&NULL),
+            _ => &NULL,
+        }
+    }
+
+    /// The string, or `""` if this isn't one.
+    pub(crate) fn as_str(&self) -> &str {
+        match self {
+            Json::String(s) => s,
+            _ => "",
+        }
+    }
+
+    /// The number, or `0.0` if this isn't one.
+    pub(crate) fn as_f64(&self) -> f64 {
+        match self {
+            Json::Number(n) => *n,
+            _ => 0.0,
+        }
+    }
+}
+
+impl From<Json> for Value {
+    fn from(json: Json) -> Value {
+        match json {
+            Json::Null => Value::None,
+            Json::Bool(b) => Value::Bool(b),
+            Json::Number(n) => Value::Number(n),
+            Json::String(s) => s.into(),
+            Json::List(list) => list.into_iter().map(Value::from).collect::<Vec<_>>().into(),
+            Json::Map(pairs) => {
+                let mut map = OMap::new();
+                for (k, v) in pairs {
+                    map.insert(k, Value::from(v));
+                }
+                Value::Map(map.into())
+            }
+        }
+    }
+}
+
+impl From<bool> for Json {
+    fn from(b: bool) -> Json {
+        Json::Bool(b)
+    }
+}
+
+impl From<usize> for Json {
+    fn from(n: usize) -> Json {
+        Json::Number(n as f64)
+    }
+}
+
+impl From<i32> for Json {
+    fn from(n: i32) -> Json {
+        Json::Number(n.into())
+    }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Json {
+        Json::String(s.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Json {
+        Json::String(s)
+    }
+}
+
+impl<T: Into<Json>> From<Vec<T>> for Json {
+    fn from(list: Vec<T>) -> Json {
+        Json::List(list.into_iter().map(Into::into).collect())
+    }
+}
+
+impl Value {
+    /// Encode as JSON. Maps keep their order. Functions, objects, and
+    /// numbers JSON can't represent (NaN, infinity) become `null`.
+    ///
+    /// `Value::from(vec![1, 2]).to_json() #=> "[1,2]"`
+    pub fn to_json(&self) -> String {
+        let mut out = String::new();
+        encode(self, &mut out);
+        out
+    }
+
+    /// Decode JSON into a Value.
+    pub fn from_json(json: &str) -> Result<Value> {
+        Json::parse(json).map(Value::from)
+    }
+}
+
+/// Write `val` to `out` as JSON.
+fn encode(val: &Value, out: &mut String) {
+    match val {
+        Value::None | Value::Fn(..) | Value::Object(..) => out.push_str("null"),
+        Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::Number(n) if !n.is_finite() => out.push_str("null"),
+        Value::Number(n) => {
+            let _ = write!(out, "{}", n);
+        }
+        Value::String(s) | Value::Html(s) => encode_str(s, out),
+        Value::List(list) => {
+            out.push('[');
+            for (i, v) in list.borrow().iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                encode(v, out);
+            }
+            out.push(']');
+        }
+        Value::Map(map) => {
+            out.push('{');
+            for (i, (k, v)) in map.borrow().iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                encode_str(k, out);
+                out.push(':');
+                encode(v, out);
+            }
+            out.push('}');
+        }
+    }
+}
+
+/// Write `json` to `out`.
+#[cfg(feature = "lsp")]
+fn encode_json(json: &Json, out: &mut String) {
+    match json {
+        Json::Null => out.push_str("null"),
+        Json::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        Json::Number(n) if !n.is_finite() => out.push_str("null"),
+        Json::Number(n) => {
+            let _ = write!(out, "{}", n);
+        }
+        Json::String(s) => encode_str(s, out),
+        Json::List(list) => {
+            out.push('[');
+            for (i, v) in list.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                encode_json(v, out);
+            }
+            out.push(']');
+        }
+        Json::Map(pairs) => {
+            out.push('{');
+            for (i, (k, v)) in pairs.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                encode_str(k, out);
+                out.push(':');
+                encode_json(v, out);
+            }
+            out.push('}');
+        }
+    }
+}
+
+/// Write a quoted, escaped JSON string.
+fn encode_str(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            // unsafe in <script>
+            '<' | '>' | '&' | '\u{2028}' | '\u{2029}' => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// How many lists and maps deep JSON can go, so hostile input can't
+/// overflow the stack.
+const MAX_DEPTH: usize = 128;
+
+/// Recursive descent JSON parser.
+struct Parser<'s> {
+    json: &'s str,
+    pos: usize,
+    depth: usize, // lists and maps we're inside
+}
+
+impl<'s> Parser<'s> {
+    fn error<T>(&self, expected: &str) -> Result<T> {
+        match self.peek() {
+            Some(c) => error!(
+                "invalid JSON at {}: expected {}, got {:?}",
+                self.pos, expected, c
+            ),
+            None => error!("invalid JSON: expected {}, got end of input", expected),
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.json[self.pos..].chars().next()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            self.error(&format!("{:?}", c))
+        }
+    }
+
+    fn skip_space(&mut self) {
+        while let Some(' ' | '\n' | '\r' | '\t') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    /// Parse `word` if it's next.
+    fn word(&mut self, word: &str, val: Json) -> Result<Json> {
+        if self.json[self.pos..].starts_with(word) {
+            self.pos += word.len();
+            Ok(val)
+        } else {
+            self.error(word)
+        }
+    }
+
+    fn value(&mut self) -> Result<Json> {
+        match self.peek() {
+            Some('n') => self.word("null", Json::Null),
+            Some('t') => self.word("true", Json::Bool(true)),
+            Some('f') => self.word("false", Json::Bool(false)),
+            Some('"') => Ok(Json::String(self.string()?)),
+            Some('[' | '{') if self.depth == MAX_DEPTH => {
+                error!("invalid JSON at {}: nested too deeply", self.pos)
+            }
+            Some('[') => self.nested(Parser::list),
+            Some('{') => self.nested(Parser::map),
+            Some('-' | '0'..='9') => self.number(),
+            _ => self.error("value"),
+        }
+    }
+
+    /// Parse a list or map, one level deeper.
+    fn nested(&mut self, f: fn(&mut Parser<'s>) -> Result<Json>) -> Result<Json> {
+        self.depth += 1;
+        let res = f(self);
+        self.depth -= 1;
+        res
+    }
+
+    /// `-? (0 | [1-9][0-9]*) (. [0-9]+)? ([eE] [+-]? [0-9]+)?`
+    fn number(&mut self) -> Result<Json> {
+        let start = self.pos;
+        if self.peek() == Some('-') {
+            self.pos += 1;
+        }
+        match self.peek() {
+            Some('0') => self.pos += 1,
+            Some('1'..='9') => self.digits()?,
+            _ => return self.error("digit"),
+        }
+        if self.peek() == Some('.') {
+            self.pos += 1;
+            self.digits()?;
+        }
+        if let Some('e' | 'E') = self.peek() {
+            self.pos += 1;
+            if let Some('+' | '-') = self.peek() {
+                self.pos += 1;
+            }
+            self.digits()?;
+        }
+        match self.json[start..self.pos].parse::<f64>() {
+            Ok(n) => Ok(Json::Number(n)),
+            Err(..) => {
+                self.pos = start;
+                self.error("number")
+            }
+        }
+    }
+
+    /// Skip one or more digits.
+    fn digits(&mut self) -> Result<()> {
+        if !matches!(self.peek(), Some('0'..='9')) {
+            return self.error("digit");
+        }
+        while let Some('0'..='9') = self.peek() {
+            self.pos += 1;
+        }
+        Ok(())
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            match self.next() {
+                Some('"') => return Ok(s),
+                Some('\\') => match self.next() {
+                    Some('"') => s.push('"'),
+                    Some('\\') => s.push('\\'),
+                    Some('/') => s.push('/'),
+                    Some('b') => s.push('\u{8}'),
+                    Some('f') => s.push('\u{c}'),
+                    Some('n') => s.push('\n'),
+                    Some('r') => s.push('\r'),
+                    Some('t') => s.push('\t'),
+                    Some('u') => s.push(self.unicode()?),
+                    _ => return self.error("escape sequence"),
+                },
+                Some(c) if (c as u32) < 0x20 => return self.error("escaped control character"),
+                Some(c) => s.push(c),
+                None => return self.error("closing \""),
+            }
+        }
+    }
+
+    /// Parse the XXXX in `\uXXXX`, including surrogate pairs.
+    fn unicode(&mut self) -> Result<char> {
+        let hi = self.hex()?;
+        if (0xD800..0xDC00).contains(&hi) {
+            if self.json[self.pos..].starts_with("\\u") {
+                self.pos += 2;
+                let lo = self.hex()?;
+                if (0xDC00..0xE000).contains(&lo) {
+                    let code = 0x10000 + ((hi - 0xD800) << 10) + (lo - 0xDC00);
+                    if let Some(c) = char::from_u32(code) {
+                        return Ok(c);
+                    }
+                }
+            }
+            return self.error("surrogate pair");
+        }
+        match char::from_u32(hi) {
+            Some(c) => Ok(c),
+            None => self.error("unicode escape"),
+        }
+    }
+
+    fn hex(&mut self) -> Result<u32> {
+        let hex = self.json.get(self.pos..self.pos + 4).unwrap_or("");
+        match u32::from_str_radix(hex, 16) {
+            Ok(n) if hex.len() == 4 && hex.bytes().all(|b| b.is_ascii_hexdigit()) => {
+                self.pos += 4;
+                Ok(n)
+            }
+            _ => self.error("4 hex digits"),
+        }
+    }
+
+    fn list(&mut self) -> Result<Json> {
+        self.expect('[')?;
+        let mut list = vec![];
+        self.skip_space();
+        if self.peek() == Some(']') {
+            self.pos += 1;
+            return Ok(Json::List(list));
+        }
+        loop {
+            self.skip_space();
+            list.push(self.value()?);
+            self.skip_space();
+            match self.next() {
+                Some(',') => {}
+                Some(']') => return Ok(Json::List(list)),
+                Some(c) => {
+                    self.pos -= c.len_utf8();
+                    return self.error(", or ]");
+                }
+                None => return self.error(", or ]"),
+            }
+        }
+    }
+
+    fn map(&mut self) -> Result<Json> {
+        self.expect('{')?;
+        let mut pairs = vec![];
+        self.skip_space();
+        if self.peek() == Some('}') {
+            self.pos += 1;
+            return Ok(Json::Map(pairs));
+        }
+        loop {
+            self.skip_space();
+            let key = self.string()?;
+            self.skip_space();
+            self.expect(':')?;
+            self.skip_space();
+            pairs.push((key, self.value()?));
+            self.skip_space();
+            match self.next() {
+                Some(',') => {}
+                Some('}') => return Ok(Json::Map(pairs)),
+                Some(c) => {
+                    self.pos -= c.len_utf8();
+                    return self.error(", or }");
+                }
+                None => return self.error(", or }"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_json() {
+        let mut map = OMap::new();
+        map.insert("z", Value::from(vec![1.5, -2.0]));
+        map.insert("a", Value::None);
+        map.insert("b", true.into());
+        assert_eq!(
+            r#"{"z":[1.5,-2],"a":null,"b":true}"#,
+            Value::Map(map.into()).to_json()
+        );
+        assert_eq!(
+            r#""\"hi\"\n\u003c/script\u003e \u0026 \u2028""#,
+            Value::from("\"hi\"\n</script> & \u{2028}").to_json()
+        );
+        assert_eq!("null", Value::Number(f64::NAN).to_json());
+    }
+
+    #[test]
+    fn test_from_json() {
+        let json =
+            r#" {"b": [1, 2.5e1, -3], "a": {"c": null, "d": false}, "e": "\u00e9\ud83d\ude00\n"} "#;
+        let val = Value::from_json(json).unwrap();
+        assert_eq!(
+            r#"{"b":[1,25,-3],"a":{"c":null,"d":false},"e":"é😀\n"}"#,
+            val.to_json()
+        );
+        assert_eq!(val, Value::from_json(&val.to_json()).unwrap());
+
+        for bad in &[
+            "",
+            "[1,]",
+            "{\"a\" 1}",
+            "tru",
+            "\"abc",
+            "[1] 2",
+            "01x",
+            "\"\\ud800\"",
+            "\"\\u+123\"",
+            "[1",
+            "01",
+            "1.",
+            ".5",
+            "+1",
+            "-",
+            "1e",
+            "1.e5",
+            "--1",
+        ] {
+            assert!(Value::from_json(bad).is_err(), "{}", bad);
+        }
+        for (good, n) in &[("0", 0.0), ("-0.5", -0.5), ("1E+2", 100.0), ("10e-1", 1.0)] {
+            assert_eq!(
+                Value::Number(*n),
+                Value::from_json(good).unwrap(),
+                "{}",
+                good
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "lsp")]
+    fn test_json() {
+        let json = Json::parse(r#"{"a": {"b": ["x", 1]}, "a": {"b": "y"}, "c": true}"#).unwrap();
+        assert_eq!("y", json.get("a").get("b").as_str());
+        assert_eq!(Json::Bool(true), *json.get("c"));
+        assert_eq!(Json::Null, *json.get("nope").get("b"));
+        assert_eq!(
+            r#"{"n":1.5,"s":"\u003c","l":[null]}"#,
+            Json::Map(vec![
+                ("n".into(), Json::Number(1.5)),
+                ("s".into(), "<".into()),
+                ("l".into(), Json::List(vec![Json::Null])),
+            ])
+            .to_json()
+        );
+    }
+
+    #[test]
+    fn test_from_json_depth() {
+        let ok = format!("{}{}", "[".repeat(MAX_DEPTH), "]".repeat(MAX_DEPTH));
+        assert!(Value::from_json(&ok).is_ok());
+        let deep = "[{\"a\":".repeat(100_000);
+        let err = Value::from_json(&deep).unwrap_err();
+        assert!(err.to_string().contains("nested too deeply"), "{}", err);
+    }
+}
@@ -17,44 +17,67 @@
 #![allow(clippy::should_implement_trait)]
 #![allow(clippy::new_without_default)]
 #![allow(clippy::inherent_to_string)]
+#![allow(clippy::wrong_self_convention)]
+#![allow(clippy::derived_hash_with_manual_eq)]
 
 #[macro_use]
 mod macros;
 #[macro_use]
 mod error;
+pub mod aot;
 mod compile;
+mod escape;
 mod eval;
+mod format;
 mod interner;
+mod json;
+mod lint;
+mod loader;
 mod omap;
 pub mod prelude;
+mod shared;
 mod symbol;
 mod template;
 
 #[cfg(feature = "repl")]
 pub mod repl;
 
+#[cfg(feature = "lsp")]
+pub mod lsp;
+
 pub use {
     compile::{
-        compile,
-        lexer::scan,
-        parser::parse,
-        stmt::Stmt,
+        bytecode::Code,
+        check, compile,
+        cst::{parse_cst, Element, Node, NodeKind},
+        lexer::{scan, scan_all, scan_lossless, scan_with_comments},
+        optimize::optimize,
+        parser::{parse, parse_all},
+        stmt::{Span, Stmt},
         syntax::{Syntax, SyntaxTrait},
-        tag::Tag,
+        tag::{Prerendered, Tag},
         token::Token,
     },
-    error::{line_and_col, print_error, Error, ErrorKind},
+    error::{error_page, line_and_col, print_error, print_error_with, Error, ErrorKind, Frame},
+    escape::{escape_attr, escape_html, escape_raw_text},
     eval::{
         args::Args,
         builtin,
-        env::{eval, render, Env, Jump, Scope},
-        value::{self, Fn, Native, Object, Special, Value},
+        env::{eval, render, Env, Scope},
+        value::{self, Fn, Lambda, Native, Object, Special, Value},
     },
+    format::format,
     interner::Interner,
+    lint::{lint, Lint, Linter, Warning},
+    loader::{FileLoader, Loader, MemoryLoader, StaticLoader},
     omap::OMap,
+    shared::Shared,
     symbol::Symbol,
-    template::Template,
+    template::{Template, Templates},
 };
 
+#[cfg(feature = "serde")]
+pub use eval::value::to_value;
+
 /// Alias for a generic Result type.
 pub type Result<T> = std::result::Result<T, Error>;
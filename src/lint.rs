@@ -0,0 +1,529 @@
+//! A static checker for templates, in the spirit of luacheck. It
+//! walks the compiled `Stmt`s looking for likely mistakes, without
+//! running anything:
+//!
+//! ```ignore
+//! let mut linter = Linter::new();
+//! linter.add_global("user");
+//! for warning in linter.lint(&hatter::compile(&source)?) {
+//!     println!("{}: {}", warning.lint.name(), warning.details);
+//! }
+//! ```
+
+use {
+    crate::{builtin, compile, Loader, Result, Span, Stmt, Symbol, Tag, Value},
+    std::{
+        collections::{HashMap, HashSet},
+        sync::Arc,
+    },
+};
+
+/// What kind of mistake did we find?
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lint {
+    UndefinedVariable,  // `x` that was never set
+    UnusedVariable,     // `x := 1` that's never used
+    UnusedFunction,     // `def f()` that's never called
+    Shadowing,          // `x := 1` when `x` already exists
+    UndeclaredAssign,   // `x = 1` without `x := ...`
+    Unreachable,        // code after `return`, `break`, or `continue`
+    ArityMismatch,      // `f(1, 2)` for `def f(a)`
+    DuplicateId,        // two tags with the same id
+    DuplicateAttribute, // one tag with the same attribute twice
+    MissingHref,        // `<a>` without `href`
+}
+
+impl Lint {
+    /// Name of the lint, like `unused-variable`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::UndefinedVariable => "undefined-variable",
+            Lint::UnusedVariable => "unused-variable",
+            Lint::UnusedFunction => "unused-function",
+            Lint::Shadowing => "shadowing",
+            Lint::UndeclaredAssign => "undeclared-assign",
+            Lint::Unreachable => "unreachable",
+            Lint::ArityMismatch => "arity-mismatch",
+            Lint::DuplicateId => "duplicate-id",
+            Lint::DuplicateAttribute => "duplicate-attribute",
+            Lint::MissingHref => "missing-href",
+        }
+    }
+}
+
+/// A likely mistake, and where it is in the template.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub lint: Lint,
+    pub details: String,
+    pub pos: usize,
+    pub len: usize,
+}
+
+/// Checks compiled templates for likely mistakes. Builtins are
+/// always known. Variables your Rust code sets on the `Env` need to
+/// be added with `add_global()`, or they'll be reported as undefined.
+pub struct Linter {
+    globals: HashSet<String>,
+    loader: Option<Arc<dyn Loader>>,
+    unused_globals: bool,
+}
+
+impl Default for Linter {
+    fn default() -> Linter {
+        let mut globals = HashSet::new();
+        globals.extend(builtin::natives().into_keys());
+        globals.extend(builtin::specials().into_keys());
+        Linter {
+            globals,
+            loader: None,
+            unused_globals: true,
+        }
+    }
+}
+
+impl Linter {
+    /// New Linter that knows about the builtins.
+    pub fn new() -> Linter {
+        Linter::default()
+    }
+
+    /// Tell the linter about a variable set from Rust.
+    pub fn add_global(&mut self, name: &str) {
+        self.globals.insert(name.to_string());
+    }
+
+    /// Set the Loader used to find templates for `import`, so the
+    /// names they define are known. Without one, templates that
+    /// `import` don't get undefined variable warnings.
+    pub fn set_loader<L: Loader + 'static>(&mut self, loader: L) {
+        self.loader = Some(Arc::new(loader));
+    }
+
+    /// Report unused top-level variables and functions? On by
+    /// default. Turn it off for templates that are `import`ed.
+    pub fn set_unused_globals(&mut self, check: bool) {
+        self.unused_globals = check;
+    }
+
+    /// Check a compiled template, returning warnings in the order
+    /// they appear in the source.
+    pub fn lint(&self, stmts: &[Stmt]) -> Vec<Warning> {
+        let mut checker = Checker {
+            linter: self,
+            scopes: vec![HashMap::new()],
+            pending: vec![],
+            fns: vec![],
+            ids: HashSet::new(),
+            imported: false,
+            warnings: vec![],
+        };
+        checker.printed_block(stmts);
+        checker.pop_scope();
+
+        if !checker.imported {
+            for (name, span, _) in std::mem::take(&mut checker.pending) {
+                checker.warn(
+                    Lint::UndefinedVariable,
+                    span,
+                    format!("undefined variable: {}", name),
+                );
+            }
+        }
+        let mut warnings = checker.warnings;
+        warnings.sort_by_key(|w| w.pos);
+        warnings
+    }
+}
+
+/// Lint source code with the default settings.
+pub fn lint(source: &str) -> Result<Vec<Warning>> {
+    Ok(Linter::new().lint(&compile(source)?))
+}
+
+/// Something a template defined.
+#[derive(Debug)]
+struct Var {
+    span: Span,
+    kind: VarKind,
+    arity: Option<usize>, // number of params, for functions
+    used: bool,
+}
+
+#[derive(Debug, PartialEq)]
+enum VarKind {
+    Var,   // x := 1
+    Fn,    // def f()
+    Param, // fn params, loop vars, and imported names
+}
+
+/// State for one run of the Linter.
+struct Checker<'l> {
+    linter: &'l Linter,
+    scopes: Vec<HashMap<String, Var>>,
+    pending: Vec<(String, Span, usize)>, // names used in fns before they're set, and the fn's scope
+    fns: Vec<usize>,                     // scopes the fns we're in were defined in
+    ids: HashSet<String>,                // ids of tags we've seen
+    imported: bool,                      // `import`ed something we couldn't load?
+    warnings: Vec<Warning>,
+}
+
+impl Checker<'_> {
+    fn warn(&mut self, lint: Lint, span: Span, details: String) {
+        self.warnings.push(Warning {
+            lint,
+            details,
+            pos: span.pos,
+            len: span.len,
+        });
+    }
+
+    fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    /// Pop a scope, resolving names functions used before they were
+    /// set and reporting what was never used.
+    fn pop_scope(&mut self) {
+        let depth = self.scopes.len() - 1;
+        let mut scope = self.scopes.pop().unwrap_or_default();
+        self.pending.retain(|(name, _, fn_depth)| {
+            if *fn_depth < depth {
+                return true;
+            }
+            match scope.get_mut(name) {
+                Some(var) => {
+                    var.used = true;
+                    false
+                }
+                None => true,
+            }
+        });
+
+        if depth == 0 && !self.linter.unused_globals {
+            return;
+        }
+        let mut unused = scope
+            .into_iter()
+            .filter(|(name, var)| !var.used && !name.starts_with('_'))
+            .collect::<Vec<_>>();
+        unused.sort_by_key(|(_, var)| var.span.pos);
+        for (name, var) in unused {
+            match var.kind {
+                VarKind::Var => self.warn(
+                    Lint::UnusedVariable,
+                    var.span,
+                    format!("{} is never used", name),
+                ),
+                VarKind::Fn => self.warn(
+                    Lint::UnusedFunction,
+                    var.span,
+                    format!("{} is never called", name),
+                ),
+                VarKind::Param => {}
+            }
+        }
+    }
+
+    /// Define a name in the current scope.
+    fn define(&mut self, name: &str, span: Span, kind: VarKind, arity: Option<usize>) {
+        let used = kind == VarKind::Param;
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(
+                name.to_string(),
+                Var {
+                    span,
+                    kind,
+                    arity,
+                    used,
+                },
+            );
+        }
+    }
+
+    /// Find a name, marking it used.
+    fn lookup(&mut self, name: &str) -> Option<&mut Var> {
+        if self.linter.globals.contains(name) {
+            return None;
+        }
+        let var = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name))?;
+        var.used = true;
+        Some(var)
+    }
+
+    /// Is `name` defined anywhere?
+    fn is_defined(&self, name: &str) -> bool {
+        self.linter.globals.contains(name) || self.scopes.iter().any(|s| s.contains_key(name))
+    }
+
+    /// Use a name. Inside a function it might be set later, before
+    /// the function is called, so we wait and see.
+    fn use_name(&mut self, name: &str, span: Span) {
+        if self.is_defined(name) {
+            self.lookup(name);
+        } else if let Some(depth) = self.fns.last() {
+            self.pending.push((name.to_string(), span, *depth));
+        } else if !self.imported {
+            self.warn(
+                Lint::UndefinedVariable,
+                span,
+                format!("undefined variable: {}", name),
+            );
+        }
+    }
+
+    /// Check a block whose last value is returned.
+    fn block(&mut self, stmts: &[Stmt]) {
+        self.stmts(stmts, false);
+    }
+
+    /// Check a block whose values are printed, where words that
+    /// aren't variables are just text.
+    fn printed_block(&mut self, stmts: &[Stmt]) {
+        self.stmts(stmts, true);
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt], printed: bool) {
+        let mut jumped = None;
+        for stmt in stmts {
+            if let Some(span) = jumped.take() {
+                let span = if stmt.span().len > 0 {
+                    stmt.span()
+                } else {
+                    span
+                };
+                self.warn(Lint::Unreachable, span, "unreachable code".to_string());
+            }
+            match stmt {
+                Stmt::Word(word, span) if is_jump(word) => jumped = Some(*span),
+                Stmt::Return(_, span) => jumped = Some(*span),
+                _ => {}
+            }
+            match stmt {
+                Stmt::Word(word, _) if printed && !self.is_defined(word) => {}
+                _ => self.stmt(stmt),
+            }
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::None | Stmt::Value(..) => {}
+            Stmt::Word(word, span) => {
+                if !is_jump(word) {
+                    self.use_name(word, *span);
+                }
+            }
+            Stmt::List(list) => list.iter().for_each(|s| self.stmt(s)),
+            Stmt::Map(pairs) | Stmt::Args(pairs) => pairs.iter().for_each(|(_, s)| self.stmt(s)),
+            Stmt::Call(target, args, span) => self.call(target, args, *span),
+            Stmt::Return(expr, _) | Stmt::Extends(expr) => self.stmt(expr),
+            Stmt::If(conds) => {
+                for (test, body) in conds {
+                    self.stmt(test);
+                    self.push_scope();
+                    self.block(body);
+                    self.pop_scope();
+                }
+            }
+            Stmt::For(key, val, expr, body) => {
+                self.stmt(expr);
+                self.push_scope();
+                if let Some(key) = key {
+                    self.define(key, Span::default(), VarKind::Param, None);
+                }
+                self.define(val, Span::default(), VarKind::Param, None);
+                self.block(body);
+                self.pop_scope();
+            }
+            Stmt::While(test, body) => {
+                self.push_scope();
+                self.stmt(test);
+                self.block(body);
+                self.pop_scope();
+            }
+            Stmt::Assign(name, expr, is_reassign, span) => {
+                self.assign(name, expr, *is_reassign, *span)
+            }
+            Stmt::Fn(params, body) => self.function(params, body, false),
+            Stmt::Tag(tag) => self.tag(tag),
+            Stmt::Block(_, body) => {
+                self.push_scope();
+                self.printed_block(body);
+                self.pop_scope();
+            }
+        }
+    }
+
+    fn call(&mut self, target: &Stmt, args: &[Stmt], span: Span) {
+        if let Stmt::Word(name, _) = target {
+            if name == "import" {
+                self.import(args);
+            }
+            let kw_args = matches!(args, [Stmt::Args(..)]);
+            if let Some(arity) = self.lookup(name).and_then(|var| var.arity) {
+                if !kw_args && arity != args.len() {
+                    self.warn(
+                        Lint::ArityMismatch,
+                        span,
+                        format!("{} takes {} args, got {}", name, arity, args.len()),
+                    );
+                }
+            }
+        }
+        self.stmt(target);
+        args.iter().for_each(|s| self.stmt(s));
+    }
+
+    /// Learn the names an `import`ed template defines, if we can.
+    fn import(&mut self, args: &[Stmt]) {
+        let stmts = match (&self.linter.loader, args) {
+            (Some(loader), [Stmt::Value(Value::String(name))]) => loader
+                .template(name)
+                .and_then(|t| t.stmts().map(|s| s.to_vec()))
+                .ok(),
+            _ => None,
+        };
+        match stmts {
+            Some(stmts) => {
+                for stmt in stmts {
+                    if let Stmt::Assign(name, _, false, _) = stmt {
+                        self.define(&name, Span::default(), VarKind::Param, None);
+                    }
+                }
+            }
+            None => self.imported = true,
+        }
+    }
+
+    fn assign(&mut self, name: &Symbol, expr: &Stmt, is_reassign: bool, span: Span) {
+        // assigning to a variable isn't using it
+        if is_reassign {
+            if !self.is_defined(name) && self.fns.is_empty() {
+                self.warn(
+                    Lint::UndeclaredAssign,
+                    span,
+                    format!("{} = without {} := first", name, name),
+                );
+            }
+            self.stmt(expr);
+            return;
+        }
+
+        if self.is_defined(name) {
+            self.warn(
+                Lint::Shadowing,
+                span,
+                format!("{} is already defined", name),
+            );
+        }
+        match expr {
+            // define functions first so they can call themselves
+            Stmt::Fn(params, body) => {
+                self.define(name, span, VarKind::Fn, Some(params.len()));
+                self.function(params, body, name.starts_with('<'));
+            }
+            _ => {
+                self.stmt(expr);
+                self.define(name, span, VarKind::Var, None);
+            }
+        }
+    }
+
+    /// Check a function body. Custom tags print their body and get
+    /// `children`.
+    fn function(&mut self, params: &[Symbol], body: &[Stmt], is_tag: bool) {
+        self.fns.push(self.scopes.len() - 1);
+        self.push_scope();
+        for param in params {
+            self.define(param, Span::default(), VarKind::Param, None);
+        }
+        if is_tag {
+            self.define("children", Span::default(), VarKind::Param, None);
+            self.printed_block(body);
+        } else {
+            self.block(body);
+        }
+        self.pop_scope();
+        self.fns.pop();
+    }
+
+    fn tag(&mut self, tag: &Tag) {
+        let name = match &*tag.tag {
+            Stmt::Value(Value::String(name)) => Some(name.to_string()),
+            stmt => {
+                self.stmt(stmt);
+                None
+            }
+        };
+        let custom = name
+            .as_ref()
+            .map(|name| format!("<{}>", name))
+            .filter(|name| self.is_defined(name));
+        if let Some(custom) = &custom {
+            self.lookup(custom);
+        }
+
+        // ids and attributes
+        let mut attrs: Vec<&str> = vec![];
+        let mut id = literal(&tag.id);
+        if tag.id.is_some() {
+            attrs.push("id");
+        }
+        self.stmt(&tag.id);
+        tag.classes.iter().for_each(|s| self.stmt(s));
+        for (attr, val) in &tag.attrs {
+            self.stmt(val);
+            match literal(attr) {
+                Some(attr) => {
+                    if attrs.contains(&attr) {
+                        self.warn(
+                            Lint::DuplicateAttribute,
+                            tag.span,
+                            format!("duplicate attribute: {}", attr),
+                        );
+                    }
+                    if attr == "id" {
+                        id = id.or_else(|| literal(val));
+                    }
+                    attrs.push(attr);
+                }
+                None => self.stmt(attr),
+            }
+        }
+        if let Some(id) = id {
+            if !self.ids.insert(id.to_string()) {
+                self.warn(Lint::DuplicateId, tag.span, format!("duplicate id: {}", id));
+            }
+        }
+        if custom.is_none() && name.as_deref() == Some("a") && !attrs.contains(&"href") {
+            self.warn(Lint::MissingHref, tag.span, "<a> without href".to_string());
+        }
+
+        // custom tags render their body in a new scope
+        if custom.is_some() {
+            self.push_scope();
+            self.printed_block(&tag.body);
+            self.pop_scope();
+        } else {
+            self.printed_block(&tag.body);
+        }
+    }
+}
+
+/// `break` or `continue`?
+fn is_jump(word: &str) -> bool {
+    matches!(word, "break" | "continue")
+}
+
+/// The string in a literal String stmt.
+fn literal(stmt: &Stmt) -> Option<&str> {
+    match stmt {
+        Stmt::Value(Value::String(s)) => Some(s.to_str()),
+        _ => None,
+    }
+}
@@ -0,0 +1,143 @@
+//! Loaders find the source code of other templates by name, for
+//! `extends`, `include()`, and `import`.
+//!
+//! Names are always relative to the loader, not to the template
+//! doing the loading, so `include("partials/nav.hat")` means the
+//! same thing everywhere.
+
+use {
+    crate::{Result, Template},
+    std::{
+        collections::HashMap,
+        fmt, fs, io,
+        path::{Component, Path, PathBuf},
+        sync::Arc,
+    },
+};
+
+/// Finds the source code for a template by name.
+pub trait Loader: Send + Sync {
+    /// Return the source code of the template named `name`.
+    fn load(&self, name: &str) -> Result<String>;
+
+    /// Return the template named `name`. Override this if you keep
+    /// compiled templates around, like `Templates` does.
+    fn template(&self, name: &str) -> Result<Arc<Template>> {
+        Ok(Arc::new(Template::new(self.load(name)?)))
+    }
+}
+
+impl<L: Loader + ?Sized> Loader for Arc<L> {
+    fn load(&self, name: &str) -> Result<String> {
+        (**self).load(name)
+    }
+
+    fn template(&self, name: &str) -> Result<Arc<Template>> {
+        (**self).template(name)
+    }
+}
+
+impl fmt::Debug for dyn Loader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Loader")
+    }
+}
+
+/// Loads templates from files in a directory. Names can't be
+/// absolute or use `..` to escape the directory.
+#[derive(Debug)]
+pub struct FileLoader {
+    root: PathBuf,
+}
+
+impl FileLoader {
+    /// Load templates from files in `root`.
+    pub fn new<P: AsRef<Path>>(root: P) -> FileLoader {
+        FileLoader {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl Loader for FileLoader {
+    fn load(&self, name: &str) -> Result<String> {
+        let path = Path::new(name);
+        if !path
+            .components()
+            .all(|c| matches!(c, Component::Normal(..)))
+        {
+            return error!("template names must be relative: {}", name);
+        }
+        match fs::read_to_string(self.root.join(path)) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Err(error_kind!(
+                TemplateNotFound {
+                    name: name.to_string()
+                },
+                "template not found: {}",
+                name
+            )),
+            res => Ok(res?),
+        }
+    }
+}
+
+/// Loads templates from a map of names to source code, for testing
+/// or for templates you build yourself.
+#[derive(Debug, Default)]
+pub struct MemoryLoader {
+    templates: HashMap<String, String>,
+}
+
+impl MemoryLoader {
+    /// Create an empty loader.
+    pub fn new() -> MemoryLoader {
+        MemoryLoader::default()
+    }
+
+    /// Add a template.
+    pub fn insert<N: Into<String>, S: Into<String>>(&mut self, name: N, source: S) {
+        self.templates.insert(name.into(), source.into());
+    }
+}
+
+impl Loader for MemoryLoader {
+    fn load(&self, name: &str) -> Result<String> {
+        match self.templates.get(name) {
+            Some(source) => Ok(source.clone()),
+            None => Err(error_kind!(
+                TemplateNotFound {
+                    name: name.to_string()
+                },
+                "template not found: {}",
+                name
+            )),
+        }
+    }
+}
+
+/// Loads templates embedded in your binary at compile time:
+///
+/// ```ignore
+/// static TEMPLATES: &[(&str, &str)] = &[
+///     ("layout.hat", include_str!("../templates/layout.hat")),
+///     ("index.hat", include_str!("../templates/index.hat")),
+/// ];
+/// env.set_loader(StaticLoader(TEMPLATES));
+/// ```
+#[derive(Debug)]
+pub struct StaticLoader(pub &'static [(&'static str, &'static str)]);
+
+impl Loader for StaticLoader {
+    fn load(&self, name: &str) -> Result<String> {
+        match self.0.iter().find(|(n, _)| *n == name) {
+            Some((_, source)) => Ok(source.to_string()),
+            None => Err(error_kind!(
+                TemplateNotFound {
+                    name: name.to_string()
+                },
+                "template not found: {}",
+                name
+            )),
+        }
+    }
+}
@@ -0,0 +1,627 @@
+//! The Hatter Language Server is an optional feature that speaks the
+//! Language Server Protocol over stdin/stdout, so editors can show
+//! errors, hover docs, definitions, completions, and an outline for
+//! `.hat` files while you type. Start it with `hatter lsp`.
+//!
+//! It has no dependencies: JSON-RPC messages are read and written
+//! with the same JSON parser as `Value::from_json()`, but into plain
+//! `String`s, so documents aren't interned.
+
+use {
+    crate::{
+        builtin, json::Json, parse_all, scan_all, Error, FileLoader, Linter, Span, Stmt, Symbol,
+        SyntaxTrait,
+    },
+    std::{
+        collections::HashMap,
+        io::{self, BufRead, Write},
+        path,
+    },
+};
+
+/// Build a JSON object out of `"key" => value` pairs.
+macro_rules! obj {
+    ($($key:expr => $val:expr),* $(,)?) => {
+        Json::Map(vec![$(($key.to_string(), Json::from($val))),*])
+    };
+}
+
+// JSON-RPC error codes.
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+
+// LSP enums.
+const SEVERITY_ERROR: usize = 1;
+const SEVERITY_WARNING: usize = 2;
+const COMPLETION_FUNCTION: usize = 3;
+const COMPLETION_VARIABLE: usize = 6;
+const SYMBOL_FUNCTION: usize = 12;
+const SYMBOL_VARIABLE: usize = 13;
+
+/// Start the Language Server on stdin/stdout.
+pub fn run() -> io::Result<()> {
+    let stdin = io::stdin();
+    Server::new().serve(stdin.lock(), io::stdout())
+}
+
+/// A Language Server that keeps track of the documents an editor
+/// has open.
+pub struct Server {
+    docs: HashMap<String, String>,
+    builtins: HashMap<String, String>,
+    shutdown: bool,
+}
+
+/// A name defined in a document, and where it can be seen.
+struct Def {
+    name: String,
+    span: Span,
+    detail: String,
+    is_fn: bool,
+    scope: (usize, usize),
+}
+
+impl Server {
+    /// Create a Server with no open documents.
+    pub fn new() -> Server {
+        Server {
+            docs: HashMap::new(),
+            builtins: builtin_docs(),
+            shutdown: false,
+        }
+    }
+
+    /// Handle messages from `input` until the client sends `exit` or
+    /// hangs up, writing responses and notifications to `output`.
+    pub fn serve<R: BufRead, W: Write>(&mut self, mut input: R, mut output: W) -> io::Result<()> {
+        while let Some(body) = read_message(&mut input)? {
+            let msg = match Json::parse(&body) {
+                Ok(msg) => msg,
+                Err(e) => {
+                    let err = obj!("code" => PARSE_ERROR, "message" => e.details);
+                    send(
+                        &mut output,
+                        obj!("jsonrpc" => "2.0", "id" => Json::Null, "error" => err),
+                    )?;
+                    continue;
+                }
+            };
+            let method = msg.get("method").as_str().to_string();
+            if method == "exit" {
+                break;
+            }
+            let id = match &msg {
+                Json::Map(pairs) => pairs
+                    .iter()
+                    .find(|(k, _)| k == "id")
+                    .map(|(_, v)| v.clone()),
+                _ => None,
+            };
+            let result = self.handle(&method, msg.get("params"), &mut output, id.is_some());
+            if let Some(id) = id {
+                send(
+                    &mut output,
+                    match result? {
+                        Ok(result) => obj!("jsonrpc" => "2.0", "id" => id, "result" => result),
+                        Err((code, message)) => {
+                            let err = obj!("code" => code, "message" => message);
+                            obj!("jsonrpc" => "2.0", "id" => id, "error" => err)
+                        }
+                    },
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Run a request or notification. Notifications may send their
+    /// own messages, like diagnostics, to `output`.
+    fn handle<W: Write>(
+        &mut self,
+        method: &str,
+        params: &Json,
+        output: &mut W,
+        is_request: bool,
+    ) -> io::Result<std::result::Result<Json, (i32, String)>> {
+        if self.shutdown && is_request {
+            return Ok(Err((INVALID_REQUEST, "server is shutting down".into())));
+        }
+
+        let uri = params.get("textDocument").get("uri").as_str().to_string();
+        Ok(Ok(match method {
+            "initialize" => obj!(
+                "capabilities" => obj!(
+                    "textDocumentSync" => 1, // full
+                    "hoverProvider" => true,
+                    "definitionProvider" => true,
+                    "completionProvider" => obj!("triggerCharacters" => vec!["<"]),
+                    "documentSymbolProvider" => true,
+                ),
+                "serverInfo" => obj!("name" => "hatter", "version" => env!("CARGO_PKG_VERSION")),
+            ),
+            "shutdown" => {
+                self.shutdown = true;
+                Json::Null
+            }
+            "textDocument/didOpen" => {
+                let text = params.get("textDocument").get("text").as_str();
+                self.docs.insert(uri.clone(), text.to_string());
+                send(output, self.diagnostics(&uri))?;
+                Json::Null
+            }
+            "textDocument/didChange" => {
+                if let Json::List(changes) = params.get("contentChanges") {
+                    if let Some(change) = changes.last() {
+                        let text = change.get("text").as_str();
+                        self.docs.insert(uri.clone(), text.to_string());
+                    }
+                }
+                send(output, self.diagnostics(&uri))?;
+                Json::Null
+            }
+            "textDocument/didClose" => {
+                self.docs.remove(&uri);
+                let params = obj!("uri" => uri.as_str(), "diagnostics" => Vec::<Json>::new());
+                send(
+                    output,
+                    notification("textDocument/publishDiagnostics", params),
+                )?;
+                Json::Null
+            }
+            "textDocument/hover" => self.hover(&uri, params.get("position")),
+            "textDocument/definition" => self.definition(&uri, params.get("position")),
+            "textDocument/completion" => self.completion(&uri, params.get("position")),
+            "textDocument/documentSymbol" => self.symbols(&uri),
+            _ if is_request => {
+                return Ok(Err((
+                    METHOD_NOT_FOUND,
+                    format!("unknown method: {}", method),
+                )));
+            }
+            _ => Json::Null,
+        }))
+    }
+
+    /// Syntax and parse errors, or lint warnings if there aren't any,
+    /// as a `publishDiagnostics` notification.
+    fn diagnostics(&self, uri: &str) -> Json {
+        let src = self.docs.get(uri).map(|s| s.as_str()).unwrap_or("");
+        let (stmts, errors) = analyze(src);
+        let mut diags = vec![];
+        for err in &errors {
+            diags.push(obj!(
+                "range" => range(src, err.pos, err.len),
+                "severity" => SEVERITY_ERROR,
+                "code" => err.kind.name(),
+                "source" => "hatter",
+                "message" => err.details.as_str(),
+            ));
+        }
+        if errors.is_empty() {
+            let mut linter = Linter::new();
+            if let Some(dir) = uri_dir(uri) {
+                linter.set_loader(FileLoader::new(dir));
+            }
+            for w in linter.lint(&stmts) {
+                diags.push(obj!(
+                    "range" => range(src, w.pos, w.len),
+                    "severity" => SEVERITY_WARNING,
+                    "code" => w.lint.name(),
+                    "source" => "hatter",
+                    "message" => w.details,
+                ));
+            }
+        }
+        notification(
+            "textDocument/publishDiagnostics",
+            obj!("uri" => uri, "diagnostics" => diags),
+        )
+    }
+
+    /// Builtin docs, or what we know about a variable or function.
+    fn hover(&self, uri: &str, pos: &Json) -> Json {
+        let src = self.docs.get(uri).map(|s| s.as_str()).unwrap_or("");
+        let (start, end, is_tag) = match word_at(src, offset(src, pos)) {
+            Some(word) => word,
+            None => return Json::Null,
+        };
+        let text = match self.lookup(src, start, end, is_tag) {
+            Some(def) => format!("```hatter\n{}\n```", def.detail),
+            None => match self.builtins.get(&src[start..end]) {
+                Some(doc) if !is_tag => format!("```hatter\n{}\n```\n\n{}", &src[start..end], doc),
+                _ => return Json::Null,
+            },
+        };
+        obj!(
+            "contents" => obj!("kind" => "markdown", "value" => text),
+            "range" => range(src, start, end - start),
+        )
+    }
+
+    /// Where the word under the cursor was defined.
+    fn definition(&self, uri: &str, pos: &Json) -> Json {
+        let src = self.docs.get(uri).map(|s| s.as_str()).unwrap_or("");
+        match word_at(src, offset(src, pos)).and_then(|(s, e, t)| self.lookup(src, s, e, t)) {
+            Some(def) => obj!("uri" => uri, "range" => range(src, def.span.pos, def.span.len)),
+            None => Json::Null,
+        }
+    }
+
+    /// Builtins and the names that can be seen from the cursor. After
+    /// a `<`, only tags defined with `def <tag>` are suggested.
+    fn completion(&self, uri: &str, pos: &Json) -> Json {
+        let src = self.docs.get(uri).map(|s| s.as_str()).unwrap_or("");
+        let at = offset(src, pos);
+        let is_tag = match word_at(src, at) {
+            Some((_, _, is_tag)) => is_tag,
+            None => src[..at].ends_with('<'),
+        };
+
+        let mut items: Vec<(String, Json)> = vec![];
+        for def in defs(&analyze(src).0) {
+            if def.name.starts_with('<') != is_tag || at < def.scope.0 || at > def.scope.1 {
+                continue;
+            }
+            let label = def.name.trim_start_matches('<').trim_end_matches('>');
+            if items.iter().any(|(name, _)| name == label) {
+                continue;
+            }
+            let kind = if def.is_fn {
+                COMPLETION_FUNCTION
+            } else {
+                COMPLETION_VARIABLE
+            };
+            let item = obj!("label" => label, "kind" => kind, "detail" => def.detail);
+            items.push((label.into(), item));
+        }
+        if !is_tag {
+            let mut names = self
+                .builtins
+                .keys()
+                .filter(|name| name.starts_with(char::is_alphabetic))
+                .collect::<Vec<_>>();
+            names.sort();
+            for name in names {
+                if !items.iter().any(|(label, _)| label == name) {
+                    let item = obj!(
+                        "label" => name.as_str(),
+                        "kind" => COMPLETION_FUNCTION,
+                        "detail" => "builtin",
+                        "documentation" => self.builtins[name].as_str(),
+                    );
+                    items.push((name.clone(), item));
+                }
+            }
+        }
+        Json::List(items.into_iter().map(|(_, i)| i).collect())
+    }
+
+    /// Functions, tags, and variables defined at the top level.
+    fn symbols(&self, uri: &str) -> Json {
+        let src = self.docs.get(uri).map(|s| s.as_str()).unwrap_or("");
+        let mut symbols = vec![];
+        for stmt in &analyze(src).0 {
+            if let Stmt::Assign(name, val, false, span) = stmt {
+                let (kind, end) = match &**val {
+                    Stmt::Fn(_, body) => (SYMBOL_FUNCTION, extent(body).map(|e| e.1)),
+                    _ => (SYMBOL_VARIABLE, extent(&[(**val).clone()]).map(|e| e.1)),
+                };
+                let end = end.unwrap_or(0).max(span.pos + span.len);
+                symbols.push(obj!(
+                    "name" => name.to_str(),
+                    "kind" => kind,
+                    "range" => range(src, span.pos, end - span.pos),
+                    "selectionRange" => range(src, span.pos, span.len),
+                ));
+            }
+        }
+        Json::List(symbols)
+    }
+
+    /// Find the innermost definition of the word at `start..end`
+    /// that can be seen from where it is.
+    fn lookup(&self, src: &str, start: usize, end: usize, is_tag: bool) -> Option<Def> {
+        let name = if is_tag {
+            format!("<{}>", &src[start..end])
+        } else {
+            src[start..end].to_string()
+        };
+        defs(&analyze(src).0)
+            .into_iter()
+            .filter(|d| d.name == name && d.scope.0 <= start && start <= d.scope.1)
+            .min_by_key(|d| (d.scope.1 - d.scope.0, d.span.pos))
+    }
+}
+
+/// Scan and parse as much of `src` as we can, since it's usually
+/// half-typed code.
+fn analyze(src: &str) -> (Vec<Stmt>, Vec<Error>) {
+    let (tokens, mut errors) = scan_all(src);
+    let (stmts, parse_errors) = parse_all(&tokens);
+    errors.extend(parse_errors);
+    errors.sort_by_key(|e| e.pos);
+    (stmts, errors)
+}
+
+/// Every name defined with `:=`, `def`, `for`, or as a function
+/// parameter, along with the part of the source it's visible in.
+fn defs(stmts: &[Stmt]) -> Vec<Def> {
+    let mut defs = vec![];
+    collect_defs(stmts, (0, usize::MAX), &mut defs);
+    defs
+}
+
+fn collect_defs(stmts: &[Stmt], scope: (usize, usize), defs: &mut Vec<Def>) {
+    for stmt in stmts {
+        match stmt {
+            Stmt::Assign(name, val, reassign, span) => {
+                if !reassign {
+                    defs.push(Def {
+                        name: name.to_string(),
+                        span: *span,
+                        detail: describe(name.to_str(), val),
+                        is_fn: matches!(**val, Stmt::Fn(..)),
+                        scope,
+                    });
+                }
+                if let Stmt::Fn(args, body) = &**val {
+                    let end = extent(body).map(|e| e.1).unwrap_or(0);
+                    let inner = (span.pos, end.max(span.pos + span.len));
+                    for arg in args {
+                        defs.push(Def {
+                            name: arg.to_string(),
+                            span: *span,
+                            detail: format!("{}: parameter of {}", arg, name),
+                            is_fn: false,
+                            scope: inner,
+                        });
+                    }
+                    collect_defs(body, inner, defs);
+                } else {
+                    collect_defs(&[(**val).clone()], scope, defs);
+                }
+            }
+            Stmt::Fn(args, body) => collect_params(args, body, defs),
+            Stmt::For(key, val, iter, body) => {
+                collect_defs(&[(**iter).clone()], scope, defs);
+                let names = key.iter().chain(Some(val)).cloned().collect::<Vec<_>>();
+                collect_params(&names, body, defs);
+            }
+            _ => collect_defs(&children(stmt), scope, defs),
+        }
+    }
+}
+
+/// Names only visible inside `body`, like `fn(x)` or `for x in`.
+fn collect_params(names: &[Symbol], body: &[Stmt], defs: &mut Vec<Def>) {
+    let inner = extent(body).unwrap_or((0, 0));
+    for name in names {
+        defs.push(Def {
+            name: name.to_string(),
+            span: Span::new(inner.0, 0),
+            detail: name.to_string(),
+            is_fn: false,
+            scope: inner,
+        });
+    }
+    collect_defs(body, inner, defs);
+}
+
+/// What to show when hovering over `name := val` or `def name()`.
+fn describe(name: &str, val: &Stmt) -> String {
+    match val {
+        Stmt::Fn(args, _) => {
+            let args = args.iter().map(|a| a.to_str()).collect::<Vec<_>>();
+            match name.strip_prefix('<').and_then(|n| n.strip_suffix('>')) {
+                Some(tag) if args.is_empty() => format!("def <{}>", tag),
+                Some(tag) => format!("def <{}({})>", tag, args.join(", ")),
+                None => format!("def {}({})", name, args.join(", ")),
+            }
+        }
+        Stmt::Value(v) => format!("{}: {}", name, v.typename()),
+        Stmt::List(..) => format!("{}: list", name),
+        Stmt::Map(..) => format!("{}: map", name),
+        Stmt::Tag(..) => format!("{}: html", name),
+        _ => name.to_string(),
+    }
+}
+
+/// Stmts directly inside a Stmt.
+fn children(stmt: &Stmt) -> Vec<Stmt> {
+    match stmt {
+        Stmt::List(list) => list.clone(),
+        Stmt::Map(pairs) | Stmt::Args(pairs) => pairs.iter().map(|(_, v)| v.clone()).collect(),
+        Stmt::Call(f, args, _) => Some(&**f).into_iter().chain(args).cloned().collect(),
+        Stmt::Return(val, _) | Stmt::Assign(_, val, _, _) | Stmt::Extends(val) => {
+            vec![(**val).clone()]
+        }
+        Stmt::If(conds) => conds
+            .iter()
+            .flat_map(|(test, body)| Some(test).into_iter().chain(body))
+            .cloned()
+            .collect(),
+        Stmt::For(_, _, iter, body) | Stmt::While(iter, body) => {
+            Some(&**iter).into_iter().chain(body).cloned().collect()
+        }
+        Stmt::Tag(tag) => vec![(*tag.tag).clone(), (*tag.id).clone()]
+            .into_iter()
+            .chain(tag.classes.iter().cloned())
+            .chain(
+                tag.attrs
+                    .iter()
+                    .flat_map(|(k, v)| vec![k.clone(), v.clone()]),
+            )
+            .chain(tag.body.iter().cloned())
+            .collect(),
+        Stmt::Fn(_, body) | Stmt::Block(_, body) => body.clone(),
+        Stmt::None | Stmt::Value(..) | Stmt::Word(..) => vec![],
+    }
+}
+
+/// The first and last byte covered by some Stmts, going by the
+/// Spans we know about.
+fn extent(stmts: &[Stmt]) -> Option<(usize, usize)> {
+    let mut found: Option<(usize, usize)> = None;
+    for stmt in stmts {
+        let span = stmt.span();
+        if span.pos > 0 || span.len > 0 {
+            let (start, end) = found.unwrap_or((span.pos, span.pos + span.len));
+            found = Some((start.min(span.pos), end.max(span.pos + span.len)));
+        }
+        if let Some((s, e)) = extent(&children(stmt)) {
+            let (start, end) = found.unwrap_or((s, e));
+            found = Some((start.min(s), end.max(e)));
+        }
+    }
+    found
+}
+
+/// The word touching byte `at`, and whether it's a `<tag>` name.
+fn word_at(src: &str, at: usize) -> Option<(usize, usize, bool)> {
+    let at = at.min(src.len());
+    let is_word = |c: char| c.is_word_char() || c == '?';
+    let start = src[..at]
+        .char_indices()
+        .rev()
+        .take_while(|(_, c)| is_word(*c))
+        .last()
+        .map(|(i, _)| i)
+        .unwrap_or(at);
+    let end = src[at..]
+        .char_indices()
+        .find(|(_, c)| !is_word(*c))
+        .map(|(i, _)| at + i)
+        .unwrap_or(src.len());
+    if start == end {
+        return None;
+    }
+    let before = src[..start].trim_end_matches('/');
+    Some((start, end, before.ends_with('<')))
+}
+
+/// Convert a byte offset to an LSP position, which counts lines from
+/// 0 and columns in UTF-16 code units.
+fn position(src: &str, pos: usize) -> Json {
+    let pos = char_boundary(src, pos);
+    let line_start = src[..pos].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let line = src[..line_start].matches('\n').count();
+    let character: usize = src[line_start..pos].chars().map(char::len_utf16).sum();
+    obj!("line" => line, "character" => character)
+}
+
+/// `pos`, moved back to the start of the char it's in. Error spans
+/// in half-typed code can end in the middle of one.
+fn char_boundary(src: &str, pos: usize) -> usize {
+    let mut pos = pos.min(src.len());
+    while !src.is_char_boundary(pos) {
+        pos -= 1;
+    }
+    pos
+}
+
+/// Convert an LSP position to a byte offset.
+fn offset(src: &str, pos: &Json) -> usize {
+    let line = pos.get("line").as_f64() as usize;
+    let mut character = pos.get("character").as_f64() as usize;
+    let start = match line {
+        0 => 0,
+        _ => match src.match_indices('\n').nth(line - 1) {
+            Some((i, _)) => i + 1,
+            None => return src.len(),
+        },
+    };
+    for (i, c) in src[start..].char_indices() {
+        if c == '\n' || character < c.len_utf16() {
+            return start + i;
+        }
+        character -= c.len_utf16();
+    }
+    src.len()
+}
+
+/// LSP range for `len` bytes starting at `pos`.
+fn range(src: &str, pos: usize, len: usize) -> Json {
+    obj!("start" => position(src, pos), "end" => position(src, pos + len))
+}
+
+/// Directory of a `file://` URI, so `import` and `include()` work.
+fn uri_dir(uri: &str) -> Option<path::PathBuf> {
+    let path = uri.strip_prefix("file://")?;
+    let mut bytes = vec![];
+    let mut iter = path.bytes();
+    while let Some(b) = iter.next() {
+        if b == b'%' {
+            let hex = [iter.next()?, iter.next()?];
+            bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+        } else {
+            bytes.push(b);
+        }
+    }
+    let path = path::PathBuf::from(String::from_utf8(bytes).ok()?);
+    path.parent().map(|p| p.to_path_buf())
+}
+
+/// Docs for the builtin functions, from their `///` comments in
+/// `builtin.rs`.
+fn builtin_docs() -> HashMap<String, String> {
+    let mut by_fn = HashMap::new();
+    let mut doc = vec![];
+    for line in include_str!("eval/builtin.rs").lines() {
+        let line = line.trim();
+        if let Some(text) = line.strip_prefix("///") {
+            doc.push(text.strip_prefix(' ').unwrap_or(text));
+            continue;
+        }
+        if let Some(name) = line
+            .strip_prefix("pub fn ")
+            .and_then(|l| l.split('(').next())
+        {
+            by_fn.insert(name, doc.join("\n").replace("```ignore", "```hatter"));
+        }
+        doc.clear();
+    }
+    builtin::NATIVES
+        .iter()
+        .filter_map(|(name, _, f)| Some((name.to_string(), by_fn.get(f)?.clone())))
+        .collect()
+}
+
+/// Read one `Content-Length` framed message, or `None` at EOF.
+fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<String>> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            if len.is_some() {
+                break;
+            }
+            continue;
+        }
+        if let Some((key, val)) = line.split_once(':') {
+            if key.eq_ignore_ascii_case("content-length") {
+                len = val.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let mut body = vec![0; len.unwrap_or(0)];
+    input.read_exact(&mut body)?;
+    String::from_utf8(body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Write one `Content-Length` framed message.
+fn send<W: Write>(output: &mut W, msg: Json) -> io::Result<()> {
+    let body = msg.to_json();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+fn notification(method: &str, params: Json) -> Json {
+    obj!("jsonrpc" => "2.0", "method" => method, "params" => params)
+}
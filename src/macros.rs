@@ -5,23 +5,9 @@ macro_rules! bx {
     };
 }
 
-/// Rc an object.
-macro_rules! rc {
+/// Arc an object.
+macro_rules! arc {
     ($ex:expr) => {
-        Rc::new($ex)
-    };
-}
-
-/// RefCell an object.
-macro_rules! cell {
-    ($ex:expr) => {
-        RefCell::new($ex)
-    };
-}
-
-/// Rc::new(RefCell::new(X))
-macro_rules! rcell {
-    ($ex:expr) => {
-        rc!(cell!($ex))
+        Arc::new($ex)
     };
 }
@@ -1,5 +1,7 @@
 use {
-    hatter::{parse, render, scan, Stmt, Syntax, Token},
+    hatter::{
+        check, compile, line_and_col, parse, scan, Env, FileLoader, Linter, Stmt, Syntax, Token,
+    },
     std::{
         env, fs,
         io::{self, Write},
@@ -32,11 +34,16 @@ fn main() -> io::Result<()> {
         return hatter::repl::run();
     }
 
-    if !matches!(command, "scan" | "parse" | "check" | "compile" | "print") {
-        return Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!("unknown command: {}", command),
-        ));
+    #[cfg(feature = "lsp")]
+    if command == "lsp" || path == "lsp" {
+        return hatter::lsp::run();
+    }
+
+    if !matches!(
+        command,
+        "scan" | "parse" | "check" | "lint" | "fmt" | "compile" | "print"
+    ) {
+        return Err(io::Error::other(format!("unknown command: {}", command)));
     }
 
     match path.as_ref() {
@@ -60,28 +67,53 @@ fn main() -> io::Result<()> {
 
     if command == "scan" {
         let tokens = scan(&source)
-            .map_err(|e| print_error(&path, &source, e))
+            .map_err(|e| print_error(path, &source, e))
             .unwrap();
         print_tokens(tokens);
         return Ok(());
     }
 
-    if command == "parse" || command == "check" {
+    if command == "check" {
+        let errors = check(&source);
+        if errors.is_empty() {
+            return Ok(());
+        }
+        let count = errors.len();
+        for (i, err) in errors.into_iter().enumerate() {
+            if i > 0 {
+                println!();
+            }
+            show_error(path, &source, err);
+        }
+        println!("\n{} error{}", count, if count == 1 { "" } else { "s" });
+        std::process::exit(1);
+    }
+
+    if command == "fmt" {
+        return fmt(path, &source, &args[2..]);
+    }
+
+    if command == "parse" {
         let ast = scan(&source)
             .and_then(|tokens| parse(&tokens))
-            .map_err(|e| print_error(&path, &source, e))
+            .map_err(|e| print_error(path, &source, e))
             .unwrap();
-        if command == "parse" {
-            print_ast(&ast);
-        }
+        print_ast(&ast);
         return Ok(());
     }
 
+    let dir = dir_of(path);
+
+    if command == "lint" {
+        return lint(path, &source, dir, &args[2..]);
+    }
+    let mut env = Env::new();
+    env.set_loader(FileLoader::new(dir));
     write!(
         io::stdout(),
         "{}",
-        render(&source)
-            .map_err(|e| print_error(&path, &source, e))
+        env.render(&source)
+            .map_err(|e| print_error(path, &source, e))
             .unwrap()
     )
 }
@@ -91,20 +123,96 @@ fn print_usage() -> io::Result<()> {
     let repl = "";
     #[cfg(feature = "repl")]
     let repl = "  repl        Start REPL. (default without args)\n";
+    #[cfg(not(feature = "lsp"))]
+    let lsp = "";
+    #[cfg(feature = "lsp")]
+    let lsp = "  lsp         Start Language Server on stdio.\n";
     print!(
         r#"Usage: hatter [COMMAND] <file.hat>
 
 Commands:
   scan        Print tokens.
   parse       Print AST.
-  check       Check for syntax/parse errors only.
+  check       Print all syntax/parse errors, without running.
+  lint        Print likely mistakes, like unused variables.
+              --globals a,b  Variables set from Rust.
+              --lib          Don't report unused top-level names.
+  fmt         Reformat the file in place.
+              --check        Exit with an error instead, if the file
+                             isn't formatted.
   print       Print HTML. (default)
-{}"#,
-        repl
+{}{}"#,
+        repl, lsp
     );
     Ok(())
 }
 
+/// `hatter fmt FILE [--check]`
+fn fmt(path: &str, source: &str, opts: &[String]) -> io::Result<()> {
+    let mut check = false;
+    for opt in opts {
+        match opt.as_ref() {
+            "--check" => check = true,
+            _ => return Err(io::Error::other(format!("unknown option: {}", opt))),
+        }
+    }
+
+    let formatted = hatter::format(source)
+        .map_err(|e| print_error(path, source, e))
+        .unwrap();
+    if formatted == source {
+        return Ok(());
+    }
+    if check {
+        println!("{}: not formatted", path);
+        std::process::exit(1);
+    }
+    fs::write(path, formatted)
+}
+
+/// `hatter lint FILE [--globals a,b,c] [--lib]`
+fn lint(path: &str, source: &str, dir: &path::Path, opts: &[String]) -> io::Result<()> {
+    let mut linter = Linter::new();
+    linter.set_loader(FileLoader::new(dir));
+    let mut opts = opts.iter();
+    while let Some(opt) = opts.next() {
+        match opt.as_ref() {
+            "--globals" | "-g" => {
+                for name in opts.next().map(|s| s.split(',')).into_iter().flatten() {
+                    linter.add_global(name.trim());
+                }
+            }
+            "--lib" => linter.set_unused_globals(false),
+            _ => return Err(io::Error::other(format!("unknown option: {}", opt))),
+        }
+    }
+
+    let stmts = compile(source)
+        .map_err(|e| print_error(path, source, e))
+        .unwrap();
+    let warnings = linter.lint(&stmts);
+    if warnings.is_empty() {
+        return Ok(());
+    }
+    for w in &warnings {
+        let (line, col) = line_and_col(source, w.pos);
+        println!(
+            "{}:{}:{}: {} [{}]",
+            path,
+            line,
+            col,
+            w.details,
+            w.lint.name()
+        );
+    }
+    println!(
+        "\n{} warning{}",
+        warnings.len(),
+        if warnings.len() == 1 { "" } else { "s" }
+    );
+    std::process::exit(1)
+}
+
 fn print_tokens(mut tokens: Vec<Token>) {
     let mut indent = 0;
     while !tokens.is_empty() {
@@ -131,8 +239,21 @@ fn print_ast(ast: &[Stmt]) {
     }
 }
 
-fn print_error<P: AsRef<std::path::Path>, S: AsRef<str>>(path: P, source: S, err: hatter::Error) {
-    hatter::print_error(path, source, err);
+/// `extends`, `include()`, and `import` are relative to the file.
+fn dir_of(path: &str) -> &path::Path {
+    path::Path::new(path)
+        .parent()
+        .unwrap_or_else(|| path::Path::new("."))
+}
+
+/// Print an error, loading the other templates it might have
+/// happened in the same way rendering `path` did.
+fn show_error(path: &str, source: &str, err: hatter::Error) {
+    hatter::print_error_with(path, source, err, &FileLoader::new(dir_of(path)));
+}
+
+fn print_error(path: &str, source: &str, err: hatter::Error) {
+    show_error(path, source, err);
     std::process::exit(1)
 }
 
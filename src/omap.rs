@@ -24,6 +24,10 @@ impl OMap {
         self.map.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
     pub fn get<S: Into<Symbol>>(&self, key: S) -> Option<&Value> {
         self.map.get(&key.into())
     }
@@ -58,9 +62,9 @@ mod tests {
     fn test_insert() {
         let mut map = OMap::new();
         map.insert("name", "Cyndi L.".into());
-        assert_eq!("Cyndi L.", map.get(&Symbol::from("name")).unwrap().to_str());
+        assert_eq!("Cyndi L.", map.get(Symbol::from("name")).unwrap().to_str());
         map.remove("name");
-        assert_eq!(None, map.get(&Symbol::from("name")));
+        assert_eq!(None, map.get(Symbol::from("name")));
     }
 
     #[test]
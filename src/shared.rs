@@ -0,0 +1,290 @@
+//! Shared is how Hatter shares mutable data, like lists, maps, and
+//! scopes. It's an `Arc<RwLock<T>>`, so compiled templates and their
+//! values can be sent between threads, and borrowing something
+//! another thread is using waits for it.
+//!
+//! On one thread it borrows like a `RefCell`: borrowing something the
+//! thread has mutably borrowed panics instead of deadlocking. So does
+//! mutably borrowing something it's reading, in debug builds, which
+//! keep track of that. Hatter itself never holds a borrow while it
+//! runs template code, so a loop can change the list it's looping
+//! over.
+//!
+//! If a thread panics while it has something mutably borrowed, the
+//! data might be half-changed, so borrowing it again panics too.
+
+use std::{
+    fmt,
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicUsize, Ordering::SeqCst},
+        Arc, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError,
+    },
+};
+
+#[cfg(debug_assertions)]
+use std::cell::RefCell;
+
+/// Thread-safe, reference counted, mutable data.
+pub struct Shared<T>(Arc<Lock<T>>);
+
+struct Lock<T> {
+    data: RwLock<T>,
+    writer: AtomicUsize, // `thread_no()` of the writer, or 0
+    #[cfg(debug_assertions)]
+    id: usize, // for `READING`, since addresses get reused
+}
+
+thread_local! {
+    static THREAD_NO: usize = {
+        static NEXT: AtomicUsize = AtomicUsize::new(1);
+        NEXT.fetch_add(1, SeqCst)
+    };
+}
+
+#[cfg(debug_assertions)]
+thread_local! {
+    // ids of the locks this thread has `Ref`s to, once per `Ref`
+    static READING: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A number for this thread that's never 0.
+fn thread_no() -> usize {
+    THREAD_NO.with(|no| *no)
+}
+
+fn poisoned() -> ! {
+    panic!("a thread panicked while this was mutably borrowed")
+}
+
+impl<T> Shared<T> {
+    /// Share `val`.
+    pub fn new(val: T) -> Shared<T> {
+        #[cfg(debug_assertions)]
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+        Shared(Arc::new(Lock {
+            data: RwLock::new(val),
+            writer: AtomicUsize::new(0),
+            #[cfg(debug_assertions)]
+            id: NEXT_ID.fetch_add(1, SeqCst),
+        }))
+    }
+
+    /// Immutably borrow the data, waiting if another thread has it
+    /// mutably borrowed.
+    pub fn borrow(&self) -> Ref<'_, T> {
+        let lock = &*self.0;
+        let guard = match lock.data.try_read() {
+            Ok(guard) => guard,
+            Err(TryLockError::WouldBlock) => {
+                if lock.writer.load(SeqCst) == thread_no() {
+                    panic!("already mutably borrowed");
+                }
+                lock.data.read().unwrap_or_else(|_| poisoned())
+            }
+            Err(TryLockError::Poisoned(..)) => poisoned(),
+        };
+        #[cfg(debug_assertions)]
+        READING.with(|r| r.borrow_mut().push(lock.id));
+        Ref {
+            guard,
+            #[cfg(debug_assertions)]
+            id: lock.id,
+        }
+    }
+
+    /// Mutably borrow the data, waiting if another thread has it
+    /// borrowed.
+    pub fn borrow_mut(&self) -> RefMut<'_, T> {
+        let lock = &*self.0;
+        let me = thread_no();
+        let guard = match lock.data.try_write() {
+            Ok(guard) => guard,
+            Err(TryLockError::WouldBlock) => {
+                if lock.writer.load(SeqCst) == me || lock.is_reading() {
+                    panic!("already borrowed");
+                }
+                lock.data.write().unwrap_or_else(|_| poisoned())
+            }
+            Err(TryLockError::Poisoned(..)) => poisoned(),
+        };
+        lock.writer.store(me, SeqCst);
+        RefMut {
+            guard,
+            writer: &lock.writer,
+        }
+    }
+
+    /// Do these point to the same data?
+    pub fn ptr_eq(&self, other: &Shared<T>) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<T> Lock<T> {
+    /// Does this thread have a `Ref` to us? Only debug builds know.
+    #[cfg(debug_assertions)]
+    fn is_reading(&self) -> bool {
+        READING.with(|r| r.borrow().contains(&self.id))
+    }
+
+    #[cfg(not(debug_assertions))]
+    fn is_reading(&self) -> bool {
+        false
+    }
+}
+
+/// An immutable borrow, from `Shared::borrow()`.
+pub struct Ref<'a, T> {
+    guard: RwLockReadGuard<'a, T>,
+    #[cfg(debug_assertions)]
+    id: usize,
+}
+
+impl<T> Deref for Ref<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+#[cfg(debug_assertions)]
+impl<T> Drop for Ref<'_, T> {
+    fn drop(&mut self) {
+        // `try_with` in case we're dropped after thread locals are
+        let _ = READING.try_with(|r| {
+            let mut r = r.borrow_mut();
+            if let Some(i) = r.iter().rposition(|id| *id == self.id) {
+                r.swap_remove(i);
+            }
+        });
+    }
+}
+
+/// A mutable borrow, from `Shared::borrow_mut()`.
+pub struct RefMut<'a, T> {
+    guard: RwLockWriteGuard<'a, T>,
+    writer: &'a AtomicUsize,
+}
+
+impl<T> Deref for RefMut<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for RefMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for RefMut<'_, T> {
+    fn drop(&mut self) {
+        // before `guard` lets anyone else in
+        self.writer.store(0, SeqCst);
+    }
+}
+
+impl<T> Clone for Shared<T> {
+    fn clone(&self) -> Shared<T> {
+        Shared(self.0.clone())
+    }
+}
+
+impl<T: Default> Default for Shared<T> {
+    fn default() -> Shared<T> {
+        Shared::new(T::default())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Shared<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let lock = &*self.0;
+        if lock.writer.load(SeqCst) == thread_no() || lock.data.is_poisoned() {
+            return write!(f, "Shared(<borrowed>)");
+        }
+        f.debug_tuple("Shared").field(&*self.borrow()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::thread};
+
+    #[test]
+    fn test_borrow() {
+        let list = Shared::new(vec![1, 2]);
+        let other = list.clone();
+        other.borrow_mut().push(3);
+        assert_eq!(3, list.borrow().len());
+        assert!(list.ptr_eq(&other));
+        assert!(!list.ptr_eq(&Shared::new(vec![])));
+
+        // borrowing twice on one thread is fine
+        let a = list.borrow();
+        let b = list.borrow();
+        assert_eq!(a.len(), b.len());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "already borrowed")]
+    fn test_borrow_mut_while_borrowed() {
+        let list = Shared::new(vec![1]);
+        let _read = list.borrow();
+        list.borrow_mut().push(2);
+    }
+
+    #[test]
+    #[should_panic(expected = "already mutably borrowed")]
+    fn test_borrow_while_borrowed_mut() {
+        let list = Shared::new(vec![1]);
+        let _write = list.borrow_mut();
+        list.borrow();
+    }
+
+    #[test]
+    fn test_forgotten_borrow() {
+        // a leaked borrow only affects its own data
+        let list = Shared::new(vec![1]);
+        std::mem::forget(list.borrow());
+        drop(list);
+        let other = Shared::new(vec![2]);
+        other.borrow_mut().push(3);
+        assert_eq!(2, other.borrow().len());
+    }
+
+    #[test]
+    fn test_borrow_across_threads() {
+        let list = Shared::new(vec![0]);
+        let writer = {
+            let list = list.clone();
+            thread::spawn(move || {
+                for i in 0..1000 {
+                    list.borrow_mut().push(i);
+                }
+            })
+        };
+        for _ in 0..1000 {
+            let read = list.borrow();
+            assert!(!read.is_empty());
+        }
+        writer.join().unwrap();
+        assert_eq!(1001, list.borrow().len());
+    }
+
+    #[test]
+    fn test_poisoned() {
+        let list = Shared::new(vec![1]);
+        let other = list.clone();
+        let _ = thread::spawn(move || {
+            let _write = other.borrow_mut();
+            panic!("oops");
+        })
+        .join();
+        let res = std::panic::catch_unwind(|| list.borrow().len());
+        assert!(res.is_err());
+    }
+}
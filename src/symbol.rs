@@ -13,10 +13,12 @@ static mut CACHE: Option<RwLock<Interner>> = None;
 static SETUP: Once = Once::new();
 
 /// A Symbol is an interned String, created and managed by
-/// `Interner` and stored in CACHE.
-#[derive(Clone, Ord, PartialOrd, Eq, Hash)]
+/// `Interner` and stored in CACHE. Every string is only interned
+/// once, so Symbols are compared by id.
+#[derive(Clone, PartialEq, Ord, PartialOrd, Eq, Hash)]
 pub struct Symbol(u32);
 
+#[allow(static_mut_refs)]
 fn cache() -> Option<&'static RwLock<Interner>> {
     unsafe {
         SETUP.call_once(|| CACHE = Some(RwLock::new(Interner::new())));
@@ -40,6 +42,12 @@ impl Symbol {
             ""
         }
     }
+    /// The Symbol for `s`, if it's been interned, without interning
+    /// it. Nothing can be named by a string that was never interned.
+    pub fn find(s: &str) -> Option<Symbol> {
+        cache()?.read().unwrap().get(s).map(Symbol)
+    }
+
     pub fn from<S: AsRef<str>>(s: S) -> Symbol {
         if let Some(cache) = cache() {
             Symbol(cache.write().unwrap().intern(s.as_ref()))
@@ -68,12 +76,6 @@ impl ops::Deref for Symbol {
     }
 }
 
-impl PartialEq for Symbol {
-    fn eq(&self, other: &Self) -> bool {
-        self.to_str() == other.to_str()
-    }
-}
-
 impl PartialEq<&str> for Symbol {
     fn eq(&self, other: &&str) -> bool {
         self.to_str() == *other
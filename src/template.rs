@@ -2,36 +2,81 @@
 //! you're not reloading templates on each page view.
 
 use {
-    crate::{compile, Result, Stmt},
-    std::{fs::File, io::Read, path::Path},
+    crate::{compile, optimize, Env, Loader, Result, Stmt},
+    std::{
+        collections::HashMap,
+        fs::{self, File},
+        io::Read,
+        path::{Component, Path, PathBuf},
+        sync::{Arc, Mutex, OnceLock},
+        time::SystemTime,
+    },
 };
 
 /// Compiled HTML template.
+#[derive(Debug)]
 pub struct Template {
     source: String,
-    compiled: Option<Vec<Stmt>>,
+    optimize: bool,
+    compiled: OnceLock<Vec<Stmt>>,
 }
 
 impl Template {
     pub fn new(source: String) -> Template {
         Template {
             source,
-            compiled: None,
+            optimize: false,
+            compiled: OnceLock::new(),
         }
     }
 
-    pub fn stmts(&mut self) -> Result<&[Stmt]> {
+    /// Read a template from a file.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Template> {
+        Ok(Template::new(fs::read_to_string(path)?))
+    }
+
+    /// Run the template through `optimize()` when it's compiled? Off
+    /// by default, since folding `2 + 2` assumes nobody has replaced
+    /// `+` with `Env::set()`.
+    pub fn set_optimize(&mut self, optimize: bool) {
+        if self.optimize != optimize {
+            self.optimize = optimize;
+            self.compiled = OnceLock::new();
+        }
+    }
+
+    /// The template's source code.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn stmts(&self) -> Result<&[Stmt]> {
         self.compile()?;
-        if let Some(stmts) = &self.compiled {
+        if let Some(stmts) = self.compiled.get() {
             Ok(stmts)
         } else {
             Ok(&[])
         }
     }
 
-    pub fn compile(&mut self) -> Result<()> {
-        if self.compiled.is_none() {
-            self.compiled = Some(compile(&self.source)?);
+    /// Render this template with `env`, compiling it first if it
+    /// hasn't been yet.
+    pub fn render(&self, env: &mut Env) -> Result<String> {
+        env.render_stmts(self.stmts()?)
+    }
+
+    /// Compile the template, and `optimize()` it if that's on, if
+    /// that hasn't been done yet.
+    pub fn compile(&self) -> Result<()> {
+        if self.compiled.get().is_none() {
+            let stmts = compile(&self.source)?;
+            let stmts = if self.optimize {
+                optimize(stmts)
+            } else {
+                stmts
+            };
+            // if two threads race here, both compile and one wins
+            let _ = self.compiled.set(stmts);
         }
         Ok(())
     }
@@ -49,12 +94,16 @@ impl From<&str> for Template {
     }
 }
 
+/// Panics if the file can't be read. Use `Template::open()` to get
+/// an error instead.
 impl From<&Path> for Template {
     fn from(p: &Path) -> Template {
         File::open(p).unwrap().into()
     }
 }
 
+/// Panics if the file can't be read. Use `Template::open()` to get
+/// an error instead.
 impl From<File> for Template {
     fn from(mut f: File) -> Template {
         let mut s = String::new();
@@ -62,3 +111,168 @@ impl From<File> for Template {
         Template::new(s)
     }
 }
+
+/// All the templates in a directory, loaded by name and compiled the
+/// first time they're used. In debug builds templates are reloaded
+/// when their file changes, so you don't have to restart your app.
+///
+/// Names are paths relative to the directory, with or without the
+/// `.hat`, so `templates.get("posts/show")` loads
+/// `DIR/posts/show.hat`.
+///
+/// `Templates` is also a `Loader`, so `extends`, `include()`, and
+/// `import` use its cache:
+///
+/// ```ignore
+/// let templates = Arc::new(Templates::new("templates")?);
+/// env.set_loader(templates.clone());
+/// ```
+#[derive(Debug)]
+pub struct Templates {
+    root: PathBuf,
+    reload: bool,
+    optimize: bool,
+    cache: Mutex<HashMap<String, Cached>>,
+}
+
+/// A loaded template and the mtime of its file when it was loaded.
+#[derive(Debug)]
+struct Cached {
+    template: Arc<Template>,
+    modified: Option<SystemTime>,
+}
+
+impl Templates {
+    /// Find all the `.hat` files in `root` and its subdirectories.
+    pub fn new<P: AsRef<Path>>(root: P) -> Result<Templates> {
+        let root = root.as_ref().to_path_buf();
+        let mut cache = HashMap::new();
+        let mut dirs = vec![root.clone()];
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else if path.extension().filter(|ext| *ext == "hat").is_some() {
+                    let template = Template::open(&path)?;
+                    let name = path.strip_prefix(&root).unwrap_or(&path);
+                    cache.insert(
+                        name_of(name),
+                        Cached {
+                            template: Arc::new(template),
+                            modified: modified(&path),
+                        },
+                    );
+                }
+            }
+        }
+        Ok(Templates {
+            root,
+            reload: cfg!(debug_assertions),
+            optimize: false,
+            cache: Mutex::new(cache),
+        })
+    }
+
+    /// Check files for changes on every `get()`? On by default in
+    /// debug builds, off in release builds.
+    pub fn set_reload(&mut self, reload: bool) {
+        self.reload = reload;
+    }
+
+    /// `optimize()` templates when they're compiled? Off by default.
+    /// See `Template::set_optimize()`.
+    pub fn set_optimize(&mut self, optimize: bool) {
+        self.optimize = optimize;
+        let cache = self.cache.get_mut().unwrap_or_else(|err| err.into_inner());
+        for cached in cache.values_mut() {
+            let mut template = Template::new(cached.template.source().to_string());
+            template.set_optimize(optimize);
+            cached.template = Arc::new(template);
+        }
+    }
+
+    /// Names of all the templates we know about, sorted.
+    pub fn names(&self) -> Vec<String> {
+        let mut names = self.cache().keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    /// Get a template by name, reloading it first if its file changed
+    /// and reloading is on.
+    pub fn get(&self, name: &str) -> Result<Arc<Template>> {
+        let name = if name.ends_with(".hat") {
+            name.to_string()
+        } else {
+            format!("{}.hat", name)
+        };
+        let path = Path::new(&name);
+        if !path
+            .components()
+            .all(|c| matches!(c, Component::Normal(..)))
+        {
+            return error!("template names must be relative: {}", name);
+        }
+
+        // don't touch the disk while holding the lock
+        let path = self.root.join(path);
+        let modified = if self.reload { modified(&path) } else { None };
+        if let Some(cached) = self.cache().get(&name) {
+            if !self.reload || cached.modified == modified {
+                return Ok(cached.template.clone());
+            }
+        } else if !self.reload {
+            return Err(error_kind!(
+                TemplateNotFound { name: name.clone() },
+                "template not found: {}",
+                name
+            ));
+        }
+
+        let mut template = Template::open(&path)?;
+        template.set_optimize(self.optimize);
+        let template = Arc::new(template);
+        self.cache().insert(
+            name,
+            Cached {
+                template: template.clone(),
+                modified,
+            },
+        );
+        Ok(template)
+    }
+
+    /// Render the template `name` with `env`.
+    pub fn render(&self, name: &str, env: &mut Env) -> Result<String> {
+        self.get(name)?.render(env)
+    }
+
+    /// Lock the cache, ignoring panics in other threads.
+    fn cache(&self) -> std::sync::MutexGuard<'_, HashMap<String, Cached>> {
+        self.cache.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl Loader for Templates {
+    fn load(&self, name: &str) -> Result<String> {
+        Ok(self.get(name)?.source().to_string())
+    }
+
+    fn template(&self, name: &str) -> Result<Arc<Template>> {
+        self.get(name)
+    }
+}
+
+/// Name of a template: its relative path with `/` separators.
+fn name_of(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// When a file was last changed, if we can tell.
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
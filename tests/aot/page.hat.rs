@@ -0,0 +1,384 @@
+// compiled by hatter. don't edit!
+{
+    const SYMS: &[&str] = &["<card>", "title", "div", "card", "h2", "children", "shout", "s", "+", "to-uppercase", "!", "items", "apples", "pears", "plums", "<ul>", "ul", "fruit", "list", "i", "item", "==", "continue", "first", "li", "when", "data-i", "concat", ": ", "<li>", "Hi", "p", "hello", "<p>", "n", "<", "<b>", "b", "<form>", "form", "GET", "/search", "input", "name", "q", "type", "text", "<input>", "<a>", "a", "home"];
+    static PARTS: ::std::sync::OnceLock<::hatter::aot::rt::Parts> = ::std::sync::OnceLock::new();
+
+    #[allow(unused, clippy::all)]
+    fn build(p: &::hatter::aot::rt::Parts, i: usize) -> ::hatter::Stmt {
+        use {
+            ::hatter::{Prerendered, Span, Stmt, Tag, Value},
+            ::std::sync::Arc,
+        };
+        match i {
+            0 => Stmt::Fn(vec![p.syms[1].clone()], vec![
+                Stmt::Tag({
+                    let mut tag = Tag::new(Stmt::Value(Value::String(p.syms[2].clone())));
+                    tag.classes = vec![
+                        Stmt::Value(Value::String(p.syms[3].clone())),
+                    ];
+                    tag.body = vec![
+                        Stmt::Tag({
+                            let mut tag = Tag::new(Stmt::Value(Value::String(p.syms[4].clone())));
+                            tag.body = vec![
+                                Stmt::Word(p.syms[1].clone(), Span::new(40, 5)),
+                            ];
+                            tag.span = Span::new(36, 2);
+                            tag
+                        }),
+                        Stmt::Word(p.syms[5].clone(), Span::new(50, 8)),
+                    ];
+                    tag.span = Span::new(21, 3);
+                    tag
+                }),
+            ]),
+            1 => Stmt::Fn(vec![p.syms[7].clone()], vec![
+                Stmt::Call(
+                    Box::new(Stmt::Word(p.syms[8].clone(), Span::new(91, 1))),
+                    vec![
+                        Stmt::Call(
+                            Box::new(Stmt::Word(p.syms[9].clone(), Span::new(75, 12))),
+                            vec![
+                                Stmt::Word(p.syms[7].clone(), Span::new(88, 1)),
+                            ],
+                            Span::new(75, 12),
+                        ),
+                        Stmt::Value(Value::String(p.syms[10].clone())),
+                    ],
+                    Span::new(91, 1),
+                ),
+            ]),
+            2 => Stmt::List(vec![
+                Stmt::Value(Value::String(p.syms[12].clone())),
+                Stmt::Value(Value::String(p.syms[13].clone())),
+                Stmt::Value(Value::String(p.syms[14].clone())),
+            ]),
+            3 => Stmt::Tag({
+                let mut tag = Tag::new(Stmt::Value(Value::String(p.syms[16].clone())));
+                tag.id = Box::new(Stmt::Value(Value::String(p.syms[17].clone())));
+                tag.classes = vec![
+                    Stmt::Value(Value::String(p.syms[18].clone())),
+                ];
+                tag.body = vec![
+                    Stmt::For(
+                        Some(p.syms[19].clone()),
+                        p.syms[20].clone(),
+                        Box::new(Stmt::Word(p.syms[11].clone(), Span::new(169, 5))),
+                        vec![
+                            Stmt::If(vec![
+                                (Stmt::Call(
+                                    Box::new(Stmt::Word(p.syms[21].clone(), Span::new(187, 2))),
+                                    vec![
+                                        Stmt::Word(p.syms[20].clone(), Span::new(182, 4)),
+                                        Stmt::Value(Value::String(p.syms[13].clone())),
+                                    ],
+                                    Span::new(187, 2),
+                                ), vec![
+                                    Stmt::Word(p.syms[22].clone(), Span::new(204, 8)),
+                                ]),
+                            ]),
+                            Stmt::Assign(
+                                p.syms[23].clone(),
+                                Box::new(Stmt::Call(
+                                    Box::new(Stmt::Word(p.syms[21].clone(), Span::new(228, 2))),
+                                    vec![
+                                        Stmt::Word(p.syms[19].clone(), Span::new(226, 1)),
+                                        Stmt::Value(Value::Number(0.0)),
+                                    ],
+                                    Span::new(228, 2),
+                                )),
+                                false,
+                                Span::new(217, 5),
+                            ),
+                            Stmt::Tag({
+                                let mut tag = Tag::new(Stmt::Value(Value::String(p.syms[24].clone())));
+                                tag.classes = vec![
+                                    Stmt::Value(Value::String(p.syms[20].clone())),
+                                    Stmt::Call(
+                                        Box::new(Stmt::Word(p.syms[25].clone(), Span::new(245, 1))),
+                                        vec![
+                                            Stmt::Word(p.syms[23].clone(), Span::new(252, 5)),
+                                            Stmt::Value(Value::String(p.syms[23].clone())),
+                                        ],
+                                        Span::new(245, 1),
+                                    ),
+                                ];
+                                tag.attrs = vec![
+                                    (Stmt::Value(Value::String(p.syms[26].clone())), Stmt::Word(p.syms[19].clone(), Span::new(265, 1))),
+                                ];
+                                tag.body = vec![
+                                    Stmt::Call(
+                                        Box::new(Stmt::Word(p.syms[27].clone(), Span::new(269, 10))),
+                                        vec![
+                                            Stmt::Word(p.syms[19].clone(), Span::new(270, 1)),
+                                            Stmt::Value(Value::String(p.syms[28].clone())),
+                                            Stmt::Word(p.syms[20].clone(), Span::new(275, 4)),
+                                        ],
+                                        Span::new(269, 10),
+                                    ),
+                                ];
+                                tag.span = Span::new(238, 2);
+                                tag
+                            }),
+                        ],
+                    ),
+                ];
+                tag.span = Span::new(137, 2);
+                tag
+            }),
+            4 => Stmt::Call(
+                Box::new(Stmt::Word(p.syms[21].clone(), Span::new(187, 2))),
+                vec![
+                    Stmt::Word(p.syms[20].clone(), Span::new(182, 4)),
+                    Stmt::Value(Value::String(p.syms[13].clone())),
+                ],
+                Span::new(187, 2),
+            ),
+            5 => Stmt::Call(
+                Box::new(Stmt::Word(p.syms[21].clone(), Span::new(228, 2))),
+                vec![
+                    Stmt::Word(p.syms[19].clone(), Span::new(226, 1)),
+                    Stmt::Value(Value::Number(0.0)),
+                ],
+                Span::new(228, 2),
+            ),
+            6 => Stmt::Call(
+                Box::new(Stmt::Word(p.syms[25].clone(), Span::new(245, 1))),
+                vec![
+                    Stmt::Word(p.syms[23].clone(), Span::new(252, 5)),
+                    Stmt::Value(Value::String(p.syms[23].clone())),
+                ],
+                Span::new(245, 1),
+            ),
+            7 => Stmt::Call(
+                Box::new(Stmt::Word(p.syms[27].clone(), Span::new(269, 10))),
+                vec![
+                    Stmt::Word(p.syms[19].clone(), Span::new(270, 1)),
+                    Stmt::Value(Value::String(p.syms[28].clone())),
+                    Stmt::Word(p.syms[20].clone(), Span::new(275, 4)),
+                ],
+                Span::new(269, 10),
+            ),
+            8 => Stmt::Tag({
+                let mut tag = Tag::new(Stmt::Value(Value::String(p.syms[3].clone())));
+                tag.attrs = vec![
+                    (Stmt::Value(Value::String(p.syms[1].clone())), Stmt::Value(Value::String(p.syms[30].clone()))),
+                ];
+                tag.body = vec![
+                    Stmt::Tag({
+                        let mut tag = Tag::new(Stmt::Value(Value::String(p.syms[31].clone())));
+                        tag.body = vec![
+                            Stmt::Call(
+                                Box::new(Stmt::Word(p.syms[6].clone(), Span::new(307, 5))),
+                                vec![
+                                    Stmt::Value(Value::String(p.syms[32].clone())),
+                                ],
+                                Span::new(307, 5),
+                            ),
+                        ];
+                        tag.span = Span::new(304, 1);
+                        tag
+                    }),
+                ];
+                tag.span = Span::new(284, 4);
+                tag
+            }),
+            9 => Stmt::Tag({
+                let mut tag = Tag::new(Stmt::Value(Value::String(p.syms[31].clone())));
+                tag.body = vec![
+                    Stmt::Call(
+                        Box::new(Stmt::Word(p.syms[6].clone(), Span::new(307, 5))),
+                        vec![
+                            Stmt::Value(Value::String(p.syms[32].clone())),
+                        ],
+                        Span::new(307, 5),
+                    ),
+                ];
+                tag.span = Span::new(304, 1);
+                tag
+            }),
+            10 => Stmt::Call(
+                Box::new(Stmt::Word(p.syms[6].clone(), Span::new(307, 5))),
+                vec![
+                    Stmt::Value(Value::String(p.syms[32].clone())),
+                ],
+                Span::new(307, 5),
+            ),
+            11 => Stmt::Call(
+                Box::new(Stmt::Word(p.syms[35].clone(), Span::new(338, 1))),
+                vec![
+                    Stmt::Word(p.syms[34].clone(), Span::new(336, 1)),
+                    Stmt::Value(Value::Number(3.0)),
+                ],
+                Span::new(338, 1),
+            ),
+            12 => Stmt::Call(
+                Box::new(Stmt::Word(p.syms[8].clone(), Span::new(346, 2))),
+                vec![
+                    Stmt::Word(p.syms[34].clone(), Span::new(344, 1)),
+                    Stmt::Value(Value::Number(1.0)),
+                ],
+                Span::new(346, 2),
+            ),
+            13 => Stmt::Tag({
+                let mut tag = Tag::new(Stmt::Value(Value::String(p.syms[37].clone())));
+                tag.body = vec![
+                    Stmt::Word(p.syms[34].clone(), Span::new(357, 1)),
+                ];
+                tag.span = Span::new(354, 1);
+                tag
+            }),
+            14 => Stmt::Tag({
+                let mut tag = Tag::new(Stmt::Value(Value::String(p.syms[39].clone())));
+                tag.attrs = vec![
+                    (Stmt::Value(Value::String(p.syms[40].clone())), Stmt::Value(Value::String(p.syms[41].clone()))),
+                ];
+                tag.body = vec![
+                    Stmt::Tag({
+                        let mut tag = Tag::new(Stmt::Value(Value::String(p.syms[42].clone())));
+                        tag.attrs = vec![
+                            (Stmt::Value(Value::String(p.syms[43].clone())), Stmt::Value(Value::String(p.syms[44].clone()))),
+                            (Stmt::Value(Value::String(p.syms[45].clone())), Stmt::Value(Value::String(p.syms[46].clone()))),
+                        ];
+                        tag.closed = true;
+                        tag.span = Span::new(384, 5);
+                        tag
+                    }),
+                ];
+                tag.span = Span::new(361, 4);
+                tag
+            }),
+            15 => Stmt::Tag({
+                let mut tag = Tag::new(Stmt::Value(Value::String(p.syms[42].clone())));
+                tag.attrs = vec![
+                    (Stmt::Value(Value::String(p.syms[43].clone())), Stmt::Value(Value::String(p.syms[44].clone()))),
+                    (Stmt::Value(Value::String(p.syms[45].clone())), Stmt::Value(Value::String(p.syms[46].clone()))),
+                ];
+                tag.closed = true;
+                tag.span = Span::new(384, 5);
+                tag
+            }),
+            16 => Stmt::Tag({
+                let mut tag = Tag::new(Stmt::Value(Value::String(p.syms[49].clone())));
+                tag.body = vec![
+                    Stmt::Word(p.syms[50].clone(), Span::new(403, 4)),
+                ];
+                tag.span = Span::new(400, 1);
+                tag
+            }),
+            _ => Stmt::None,
+        }
+    }
+
+    #[allow(unused, clippy::all)]
+    fn render(env: &mut ::hatter::Env) -> ::hatter::Result<::std::string::String> {
+        use ::hatter::{aot::rt::{self, Flow}, Env, Span, Value};
+        let p = PARTS.get_or_init(|| rt::Parts::new(SYMS, 17, build));
+        rt::render(env, false, |env: &mut Env| -> Flow<()> {
+            rt::assign(env, &p.syms[0], false, Span::new(5, 4), |env: &mut Env| -> Flow<Value> { rt::run(env, p.node(0)) })?;
+            rt::assign(env, &p.syms[6], false, Span::new(64, 5), |env: &mut Env| -> Flow<Value> { rt::run(env, p.node(1)) })?;
+            rt::assign(env, &p.syms[11], false, Span::new(98, 5), |env: &mut Env| -> Flow<Value> { rt::run(env, p.node(2)) })?;
+            rt::static_tag(env, &p.syms[15], p.node(3), "<ul id='fruit' class='list'>", Some("</ul>\n"), |env: &mut Env| -> Flow<()> {
+                {
+                    let iter = rt::word(env, &p.syms[11], Span::new(169, 5))?;
+                    rt::for_each(env, Some(&p.syms[19]), &p.syms[20], iter, |env: &mut Env| -> Flow<()> {
+                        if {
+                            let f = rt::word(env, &p.syms[21], Span::new(187, 2))?;
+                            rt::call(env, f, p.node(4), |env: &mut Env| -> Flow<Vec<Value>> {
+                                Ok(vec![rt::word(env, &p.syms[20], Span::new(182, 4))?, Value::String(p.syms[13].clone())])
+                            })?
+                        }.to_bool() {
+                            rt::scoped(env, |env: &mut Env| -> Flow<()> {
+                                rt::word(env, &p.syms[22], Span::new(204, 8))?;
+                                Ok(())
+                            })?;
+                        }
+                        rt::assign(env, &p.syms[23], false, Span::new(217, 5), |env: &mut Env| -> Flow<Value> { Ok({
+                            let f = rt::word(env, &p.syms[21], Span::new(228, 2))?;
+                            rt::call(env, f, p.node(5), |env: &mut Env| -> Flow<Vec<Value>> {
+                                Ok(vec![rt::word(env, &p.syms[19], Span::new(226, 1))?, Value::Number(0.0)])
+                            })?
+                        }) })?;
+                        rt::tag(
+                            env,
+                            &rt::Tag {
+                                span: Span::new(238, 2),
+                                name: &|env: &mut Env| -> Flow<Value> { Ok(Value::String(p.syms[24].clone())) },
+                                custom: Some(&p.syms[29]),
+                                id: None,
+                                classes: &[&|env: &mut Env| -> Flow<Value> { Ok(Value::String(p.syms[20].clone())) }, &|env: &mut Env| -> Flow<Value> { Ok({
+                                    let f = rt::word(env, &p.syms[25], Span::new(245, 1))?;
+                                    rt::call(env, f, p.node(6), |env: &mut Env| -> Flow<Vec<Value>> {
+                                        Ok(vec![rt::word(env, &p.syms[23], Span::new(252, 5))?, Value::String(p.syms[23].clone())])
+                                    })?
+                                }) }],
+                                attrs: &[(&|env: &mut Env| -> Flow<Value> { Ok(Value::String(p.syms[26].clone())) }, &|env: &mut Env| -> Flow<Value> { rt::word(env, &p.syms[19], Span::new(265, 1)) })],
+                                closed: false,
+                                body: &|env: &mut Env| -> Flow<()> {
+                                    let val = {
+                                        let f = rt::word(env, &p.syms[27], Span::new(269, 10))?;
+                                        rt::call(env, f, p.node(7), |env: &mut Env| -> Flow<Vec<Value>> {
+                                            Ok(vec![rt::word(env, &p.syms[19], Span::new(270, 1))?, Value::String(p.syms[28].clone()), rt::word(env, &p.syms[20], Span::new(275, 4))?])
+                                        })?
+                                    };
+                                    rt::print(env, val);
+                                    Ok(())
+                                },
+                            },
+                        )?;
+                        Ok(())
+                    })?;
+                }
+                Ok(())
+            })?;
+            rt::static_tag(env, &p.syms[0], p.node(8), "<card title='Hi'>", Some("</card>\n"), |env: &mut Env| -> Flow<()> {
+                rt::static_tag(env, &p.syms[33], p.node(9), "<p>", Some("</p>\n"), |env: &mut Env| -> Flow<()> {
+                    let val = {
+                        let f = rt::word(env, &p.syms[6], Span::new(307, 5))?;
+                        rt::call(env, f, p.node(10), |env: &mut Env| -> Flow<Vec<Value>> {
+                            Ok(vec![Value::String(p.syms[32].clone())])
+                        })?
+                    };
+                    rt::print(env, val);
+                    Ok(())
+                })?;
+                Ok(())
+            })?;
+            rt::assign(env, &p.syms[34], false, Span::new(323, 1), |env: &mut Env| -> Flow<Value> { Ok(Value::Number(0.0)) })?;
+            rt::while_loop(
+                env,
+                |env: &mut Env| -> Flow<Value> { Ok({
+                    let f = rt::word(env, &p.syms[35], Span::new(338, 1))?;
+                    rt::call(env, f, p.node(11), |env: &mut Env| -> Flow<Vec<Value>> {
+                        Ok(vec![rt::word(env, &p.syms[34], Span::new(336, 1))?, Value::Number(3.0)])
+                    })?
+                }) },
+                |env: &mut Env| -> Flow<()> {
+                    rt::assign(env, &p.syms[34], true, Span::new(344, 1), |env: &mut Env| -> Flow<Value> { Ok({
+                        let f = rt::word(env, &p.syms[8], Span::new(346, 2))?;
+                        rt::call(env, f, p.node(12), |env: &mut Env| -> Flow<Vec<Value>> {
+                            Ok(vec![rt::word(env, &p.syms[34], Span::new(344, 1))?, Value::Number(1.0)])
+                        })?
+                    }) })?;
+                    rt::static_tag(env, &p.syms[36], p.node(13), "<b>", Some("</b>\n"), |env: &mut Env| -> Flow<()> {
+                        rt::print_word(env, &p.syms[34], Span::new(357, 1))?;
+                        Ok(())
+                    })?;
+                    Ok(())
+                },
+            )?;
+            rt::static_tag(env, &p.syms[38], p.node(14), "<form method='GET' action='/search'>", Some("</form>\n"), |env: &mut Env| -> Flow<()> {
+                rt::static_tag(env, &p.syms[47], p.node(15), "<input name='q' type='text' />\n", None, |env: &mut Env| -> Flow<()> {
+                    Ok(())
+                })?;
+                Ok(())
+            })?;
+            rt::static_tag(env, &p.syms[48], p.node(16), "<a href='#'>", Some("</a>\n"), |env: &mut Env| -> Flow<()> {
+                rt::print_word(env, &p.syms[50], Span::new(403, 4))?;
+                Ok(())
+            })?;
+            Ok(())
+        })
+    }
+
+    render
+}
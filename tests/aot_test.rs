@@ -0,0 +1,110 @@
+use {
+    hatter::{
+        aot::{to_rust, Compiler},
+        Args, Env, Result, Value,
+    },
+    std::{env, fs},
+};
+
+/// `tests/aot/page.hat`, compiled. Regenerate it after changing the
+/// compiler with `UPDATE_AOT=1 cargo test --test aot_test`.
+fn page(env: &mut Env) -> Result<String> {
+    let page: fn(&mut Env) -> Result<String> = include!("aot/page.hat.rs");
+    page(env)
+}
+
+#[test]
+fn test_aot_up_to_date() {
+    let source = fs::read_to_string("tests/aot/page.hat").unwrap();
+    let code = to_rust(&source).unwrap();
+    if env::var_os("UPDATE_AOT").is_some() {
+        fs::write("tests/aot/page.hat.rs", &code).unwrap();
+    }
+    let old = fs::read_to_string("tests/aot/page.hat.rs").unwrap();
+    assert!(old == code, "tests/aot/page.hat.rs is out of date");
+}
+
+#[test]
+fn test_aot_render() {
+    let source = fs::read_to_string("tests/aot/page.hat").unwrap();
+    let html = page(&mut Env::new()).unwrap();
+    assert_eq!(Env::new().render(&source).unwrap(), html);
+    assert!(html.contains("<li class='item first' data-i=0>0: apples</li>"));
+    assert!(html.contains("<p>HELLO!</p>"));
+
+    // tags can be turned into custom tags after they're compiled
+    let setup = "def <b()>\n  <strong> children";
+    let mut env = Env::new();
+    env.eval_src(setup).unwrap();
+    let html = page(&mut env).unwrap();
+    let mut tree = Env::new();
+    tree.eval_src(setup).unwrap();
+    assert_eq!(tree.render(&source).unwrap(), html);
+    assert!(html.contains("<strong>1</strong>"));
+
+    // and errors are the same too
+    let mut env = Env::new();
+    env.set("items", 1);
+    let err = page(&mut env).unwrap_err();
+    assert_eq!(err.details, "items already set");
+    assert!(err.pos > 0);
+}
+
+#[test]
+fn test_aot_static_tags() {
+    let code = to_rust("<p.a#b title='hi'> <b> x + 1").unwrap();
+    assert!(code.contains(r#""<p id='b' class='a' title='hi'>""#));
+    assert!(code.contains(r#""<b>""#));
+
+    // and so are whole tags, if nothing in them is dynamic, once
+    // they're optimized
+    let code = optimized("<p.a> <b> 2 + 2");
+    assert!(code.contains("rt::html("));
+    assert!(!code.contains("rt::static_tag"));
+
+    // dynamic attributes, and tags that need the Env, aren't
+    let code = to_rust("x := 1\n<p title=x> hi").unwrap();
+    assert!(code.contains("rt::Tag"));
+    let code = to_rust("<script> 1 < 2").unwrap();
+    assert!(!code.contains("rt::static_tag"));
+}
+
+fn optimized(source: &str) -> String {
+    let mut compiler = Compiler::new();
+    compiler.set_optimize(true);
+    compiler.to_rust(source).unwrap()
+}
+
+#[test]
+fn test_aot_optimize() {
+    // templates aren't optimized unless you ask
+    let code = to_rust("<b> 2 + 2").unwrap();
+    assert!(!code.contains("rt::html("));
+    assert!(optimized("<b> 2 + 2").contains(r#""<b>4</b>\n", p.node(0)"#));
+
+    // so builtin operators can be replaced, like with the tree-walker
+    let source = fs::read_to_string("tests/aot/page.hat").unwrap();
+    let mut env = Env::new();
+    env.set("==", |_: Args| Ok(Value::from(false)));
+    let html = page(&mut env).unwrap();
+    let mut tree = Env::new();
+    tree.set("==", |_: Args| Ok(Value::from(false)));
+    assert_eq!(tree.render(&source).unwrap(), html);
+    assert!(html.contains("1: pears"));
+}
+
+#[test]
+fn test_aot_no_source() {
+    // what the tree-walker runs is built, not parsed
+    let code = to_rust("def f(x)\n  x + 1\n<p> f(2)").unwrap();
+    assert!(code.contains("Stmt::Fn("));
+    assert!(!code.contains("def f"));
+    assert!(!code.contains("expect("));
+}
+
+#[test]
+fn test_aot_errors() {
+    let err = to_rust("<p\n  <b").unwrap_err();
+    assert!(err.pos > 0);
+    assert!(to_rust("x := ").is_err());
+}
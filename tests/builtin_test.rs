@@ -15,6 +15,8 @@ fn test_internal_functions() {
     assert_eval!(r#"type(false)"#, "bool");
     assert_eval!(r#"type(123)"#, "number");
     assert_eval!(r#"type('heyo')"#, "string");
+    assert_eval!(r#"b := <b> "hi"; type(b)"#, "html");
+    assert_eval!(r#"type(raw("<b>hi</b>"))"#, "html");
 }
 
 #[test]
@@ -110,3 +112,47 @@ fn test_range() {
     assert_eval!("1..5", vec![1, 2, 3, 4]);
     assert_eval!("1..=5", vec![1, 2, 3, 4, 5]);
 }
+
+#[test]
+fn test_json() {
+    assert_eval!(r#"to-json([1, "a<b", true])"#, r#"[1,"a\u003cb",true]"#);
+    assert_eval!(r#"to-json({b: 1, a: false})"#, r#"{"b":1,"a":false}"#);
+    assert_eval!(r#"parse-json("[1, 2]")"#, vec![1, 2]);
+    assert_eval!(r#"parse-json('{"a": {"b": "c"}}').a.b"#, "c");
+    assert_error!(r#"parse-json("[1,")"#);
+    assert_error!(r#"parse-json("01")"#);
+    assert_render!(
+        r#"
+tag := "</script>"
+<script> "var x = {to-json(tag)};"
+"#,
+        "<script>var x = \"\\u003c/script\\u003e\";</script>\n"
+    );
+}
+
+#[test]
+fn test_error_try() {
+    assert_error!(r#"error("Panic!")"#);
+    assert_eval!(r#"try(error, "Panic!").err"#, "Panic!");
+    assert_eval!(r#"try(fn(a, b) a + b, 1, 2).ok"#, 3);
+    assert_eval!(r#"try(fn(a, b) a + b, 1, 2).err"#, none!());
+    assert_eval!(r#"try(fn(a) a, 1, 2).err"#, "expected 1 args, got 2");
+    assert_eval!(r#"try(parse-json, "[1,").ok"#, none!());
+    assert_render!(
+        r#"
+def combine(a, b)
+    error("Panic!")
+    concat(a, b)
+def risky()
+    <b> "half done"
+    error("oops")
+ret := try(combine, "Mr.", "Robinson Crusoe")
+if ret.err
+    <p> ret.err
+r := try(risky)
+x := 1
+<p> x
+"#,
+        "<p>Panic!</p>\n<p>1</p>\n"
+    );
+}
@@ -0,0 +1,170 @@
+use {
+    hatter::{parse_cst, scan, scan_lossless, Element, Node, NodeKind, Syntax},
+    std::{fs, path::Path},
+};
+
+/// The kind and source code of every node in the tree.
+fn nodes(source: &str) -> Vec<(NodeKind, String)> {
+    let (root, errors) = parse_cst(source);
+    assert!(errors.is_empty(), "{:?}", errors);
+    root.nodes()
+        .iter()
+        .map(|n| (n.kind, n.text().trim().to_string()))
+        .collect()
+}
+
+fn has(source: &str, kind: NodeKind, text: &str) -> bool {
+    nodes(source).contains(&(kind, text.to_string()))
+}
+
+/// Every child lines up with the one before it.
+fn assert_contiguous(node: &Node) {
+    let mut pos = node.pos;
+    for child in &node.children {
+        let span = child.span();
+        assert_eq!(pos, span.pos, "gap in {:?}", node.kind);
+        pos += span.len;
+        if let Element::Node(child) = child {
+            assert_contiguous(child);
+        }
+    }
+    assert_eq!(node.pos + node.len, pos);
+}
+
+#[test]
+fn test_scan_lossless() {
+    let src = "x := 'hi' # greet\n<p>  x;y\n";
+    let (tokens, errors) = scan_lossless(src);
+    assert!(errors.is_empty());
+    assert_eq!(
+        src,
+        tokens.iter().map(|t| t.text()).collect::<Vec<_>>().concat()
+    );
+
+    let kinds = tokens
+        .iter()
+        .filter(|t| !t.text().is_empty())
+        .map(|t| t.kind)
+        .collect::<Vec<_>>();
+    use Syntax::*;
+    assert_eq!(
+        vec![
+            Word,
+            Whitespace,
+            Op,
+            Whitespace,
+            String(false),
+            Whitespace,
+            Comment,
+            Whitespace,
+            LCaret,
+            Word,
+            RCaret,
+            Whitespace,
+            Word,
+            Semi,
+            Word,
+            Whitespace
+        ],
+        kinds
+    );
+    assert_eq!(
+        "'hi'",
+        tokens
+            .iter()
+            .find(|t| t.kind == String(false))
+            .unwrap()
+            .text()
+    );
+
+    // same tokens as scan(), minus the trivia
+    let code = tokens
+        .iter()
+        .filter(|t| !matches!(t.kind, Whitespace | Comment))
+        .map(|t| t.kind)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        scan(src)
+            .unwrap()
+            .iter()
+            .map(|t| t.kind)
+            .collect::<Vec<_>>(),
+        code
+    );
+}
+
+#[test]
+fn test_cst_nodes() {
+    let src = "def greet(name)\n  # say hi\n  \"Hi, {name}!\"\nx := [1, 2][0] + -y\n<p.big> greet('you')\n";
+    assert!(has(
+        src,
+        NodeKind::Def,
+        "def greet(name)\n  # say hi\n  \"Hi, {name}!\""
+    ));
+    assert!(has(src, NodeKind::Body, "\"Hi, {name}!\""));
+    assert!(has(src, NodeKind::Assign, "x := [1, 2][0] + -y"));
+    assert!(has(src, NodeKind::Binary, "[1, 2][0] + -y"));
+    assert!(has(src, NodeKind::Index, "[1, 2][0]"));
+    assert!(has(src, NodeKind::List, "[1, 2]"));
+    assert!(has(src, NodeKind::Unary, "-y"));
+    assert!(has(src, NodeKind::Tag, "<p.big> greet('you')"));
+    assert!(has(src, NodeKind::OpenTag, "<p.big>"));
+    assert!(has(src, NodeKind::Call, "greet('you')"));
+    assert!(has(src, NodeKind::Args, "('you')"));
+
+    let src = "<ul>\n  <li> one two </li>\n  for x in xs\n    <li> x\n";
+    assert!(has(src, NodeKind::Text, "one two"));
+    assert!(has(src, NodeKind::For, "for x in xs\n    <li> x"));
+    assert!(has(src, NodeKind::CloseTag, "</li>"));
+
+    let (root, _) = parse_cst(src);
+    assert_eq!(NodeKind::File, root.kind);
+    assert_eq!(src.len(), root.len);
+    let pos = src.find("xs").unwrap();
+    let tok = root.tokens().find(|t| t.pos == pos).unwrap();
+    assert_eq!((Syntax::Word, "xs"), (tok.kind, tok.text()));
+}
+
+#[test]
+fn test_cst_node_at() {
+    let src = "if a\n  <b> c + 1\n";
+    let (root, _) = parse_cst(src);
+    let pos = src.find('+').unwrap();
+    let node = root.node_at(pos).unwrap();
+    assert_eq!(NodeKind::Binary, node.kind);
+    assert_eq!("c + 1", node.text());
+    assert_eq!(NodeKind::If, root.node_at(1).unwrap().kind);
+    assert!(root.node_at(src.len()).is_none());
+}
+
+#[test]
+fn test_cst_errors() {
+    let src = "x := (1 +\n<p> \"unclosed\ny := 2 # ok\n";
+    let (root, errors) = parse_cst(src);
+    assert_eq!(src, root.text());
+    assert_contiguous(&root);
+    assert!(!errors.is_empty());
+    assert!(root
+        .nodes()
+        .iter()
+        .any(|n| n.kind == NodeKind::Assign && n.text() == "y := 2"));
+}
+
+#[test]
+fn test_cst_examples() {
+    fn visit(dir: &Path) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                visit(&path);
+            } else if path.extension().is_some_and(|e| e == "hat") {
+                let source = fs::read_to_string(&path).unwrap();
+                let (root, _) = parse_cst(&source);
+                assert_eq!(source, root.text(), "{:?}", path);
+                assert_contiguous(&root);
+            }
+        }
+    }
+    visit(Path::new("./examples"));
+    visit(Path::new("./tests/examples"));
+}
@@ -0,0 +1,272 @@
+use {
+    hatter::{
+        check, compile, error_page, line_and_col, parse_all, scan, Code, Env, Error, ErrorKind,
+        MemoryLoader,
+    },
+    std::sync::Arc,
+};
+
+#[test]
+fn test_line_and_col() {
+    let src = "one\ntwo\nthree";
+    assert_eq!((1, 1), line_and_col(src, 0));
+    assert_eq!((2, 2), line_and_col(src, 5));
+    assert_eq!((3, 5), line_and_col(src, 12));
+}
+
+#[test]
+fn test_error_page() {
+    let src = "<p> 1\n<p> 2\n<b> x <& y\n<p> 3\n<p> 4\n<p> 5\n";
+    let err = Error::new(ErrorKind::RuntimeError, "bad <thing>".into(), 16, 2);
+    let html = error_page("page.hat", src, &err, &MemoryLoader::new());
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("<h1>RuntimeError</h1>"));
+    assert!(html.contains("bad &lt;thing&gt;"));
+    assert!(html.contains("page.hat:3:5"));
+    assert!(html.contains("<th>1</th><td>&lt;p&gt; 1</td>"));
+    assert!(html.contains("<td>&lt;b&gt; <mark>x </mark>&lt;&amp; y</td>"));
+    assert!(html.contains("<th>5</th>"));
+    assert!(!html.contains("<th>6</th>"));
+}
+
+#[test]
+fn test_error_page_included() {
+    let mut loader = MemoryLoader::new();
+    loader.insert("partials/nav.hat", "<nav>\n  <a> oops()\n");
+    let loader = Arc::new(loader);
+    let source = "def oops()\n  1 + nope\n<div> include(\"partials/nav.hat\")\n";
+
+    let mut env = Env::new();
+    env.set_loader(loader.clone());
+    let err = env.render(source).unwrap_err();
+    let html = error_page("site/index.hat", source, &err, &*loader);
+    assert!(html.contains("can't find var: nope"));
+    assert!(html.contains("site/index.hat:2:7"));
+    assert!(html.contains("<mark>nope</mark>"));
+    assert!(html.contains("<li>in <code>oops</code>, called at site/partials/nav.hat:2:7</li>"));
+    assert!(html.contains("<li>in <code>include</code>, called at site/index.hat:3:7</li>"));
+
+    // without the loader, other templates are just named
+    let html = error_page("site/index.hat", source, &err, &MemoryLoader::new());
+    assert!(html.contains("<li>in <code>oops</code>, called at site/partials/nav.hat</li>"));
+}
+
+#[test]
+fn test_runtime_error_pos() {
+    let check = |src: &str, code: &str, details: &str| {
+        let err = Env::new().render(src).unwrap_err();
+        assert_eq!(details, err.details);
+        assert_eq!(code, &src[err.pos..err.pos + err.len], "{}", src);
+    };
+    check("x := 1\n<p> x + y", "y", "can't find var: y");
+    check("x := 1\nx := 2", "x", "x already set");
+    check(
+        "first := 1\n<p> \"Hi {first + nope}!\"",
+        "nope",
+        "can't find var: nope",
+    );
+    check("f := fn(a) a\n<b> f(1, 2)", "f", "expected 1 args, got 2");
+    check("x := [1]\n<p> \"{len()}\"", "len", "Argument Not Found: 0");
+}
+
+#[test]
+fn test_stack() {
+    let src = r#"
+def inner(x)
+  x + nope
+def outer()
+  inner(1)
+def <card>
+  <b> outer()
+<card>
+"#;
+    let err = Env::new().render(src).unwrap_err();
+    assert_eq!("nope", &src[err.pos..err.pos + err.len]);
+    let stack = err
+        .stack()
+        .iter()
+        .map(|f| (f.name.as_str(), &src[f.pos..f.pos + f.len]))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![("inner", "inner"), ("outer", "outer"), ("<card>", "card")],
+        stack
+    );
+
+    // functions imported from another template
+    let mut loader = MemoryLoader::new();
+    loader.insert(
+        "lib.hat",
+        "def inner()\n  nope + 1\ndef outer()\n  inner()\n",
+    );
+    let loader = Arc::new(loader);
+    let src = "import 'lib.hat'\n<p> outer()\n";
+    let mut env = Env::new();
+    env.set_loader(loader.clone());
+    let err = env.render(src).unwrap_err();
+    assert_eq!(Some("lib.hat"), err.path());
+    let stack = err
+        .stack()
+        .iter()
+        .map(|f| (f.name.as_str(), f.path.as_deref(), f.pos))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![("inner", Some("lib.hat"), 37), ("outer", None, 21)],
+        stack
+    );
+    let mut env = Env::new();
+    env.set_loader(loader);
+    let code = Code::new(compile(src).unwrap());
+    assert_eq!(err, env.render_code(&code).unwrap_err());
+
+    // errors raised by the call itself don't get a frame
+    let err = Env::new().render("def f(a) a\nf()").unwrap_err();
+    assert!(err.stack().is_empty());
+}
+
+#[test]
+fn test_error_kinds() {
+    use ErrorKind::*;
+    fn kind(src: &str) -> ErrorKind {
+        Env::new().render(src).unwrap_err().kind
+    }
+    let s = |s: &str| s.to_string();
+
+    assert_eq!(IndentationError, kind("<p>\n  <b> hi\n\t<i> oops"));
+    assert_eq!(UnclosedString, kind("x := 'nope"));
+    assert_eq!(UnclosedTag { tag: s("div") }, kind("<div id=x"));
+    assert_eq!(UnclosedTag { tag: s("div") }, kind("<div> hi </b>"));
+    assert_eq!(
+        UnexpectedToken {
+            expected: s("Open Tag"),
+            actual: s("LCaret")
+        },
+        kind("</div>")
+    );
+    assert_eq!(UndefinedVariable { name: s("nope") }, kind("nope + 1"));
+    assert_eq!(UndefinedVariable { name: s("x") }, kind("x = 1"));
+    assert_eq!(AlreadyDefined { name: s("x") }, kind("x := 1\nx := 2"));
+    assert_eq!(
+        TypeMismatch {
+            expected: s("number"),
+            actual: s("string")
+        },
+        kind("x := 1\nx = 'one'")
+    );
+    assert_eq!(
+        TypeMismatch {
+            expected: s("number"),
+            actual: s("bool")
+        },
+        kind("2 * true")
+    );
+    assert_eq!(NotCallable { name: s("x") }, kind("x := 1\nx()"));
+    assert_eq!(
+        ArityMismatch {
+            expected: 1,
+            actual: 2
+        },
+        kind("def f(a)\n  a\nf(1, 2)")
+    );
+    assert_eq!(ArgNotFound { index: 0 }, kind("len()"));
+    assert_eq!(
+        UnknownAttribute {
+            tag: s("card"),
+            attr: s("href")
+        },
+        kind("def <card(title)>\n  <b> title\n<card href='x'>")
+    );
+    assert_eq!(
+        TemplateNotFound {
+            name: s("nope.hat")
+        },
+        kind("include('nope.hat')")
+    );
+}
+
+#[test]
+fn test_stray_jumps() {
+    // jumps only leave loops and functions, and aren't errors there
+    let mut env = Env::new();
+    assert_eq!(
+        "3",
+        env.render("def f()\n  for x in [1, 2]\n    return 3\nprint(f())")
+            .unwrap()
+    );
+
+    for (src, keyword) in &[
+        ("break", "break"),
+        ("if true\n  continue", "continue"),
+        ("return 1", "return"),
+        ("def f()\n  break\nfor x in [1]\n  f()", "break"),
+    ] {
+        let err = Env::new().render(src).unwrap_err();
+        assert_eq!(
+            ErrorKind::UnexpectedJump {
+                keyword: keyword.to_string()
+            },
+            err.kind,
+            "{}",
+            src
+        );
+        assert_eq!(*keyword, &src[err.pos..err.pos + err.len]);
+    }
+}
+
+#[test]
+fn test_check() {
+    let src = r#"
+<div>
+  if
+    3
+  <p> "fine"
+x := 'oops
+<ul> hi </ol>
+z := )
+<p> "fine"
+	<b> tabs
+"#;
+    let errors = check(src)
+        .into_iter()
+        .map(|e| (e.kind.name(), line_and_col(src, e.pos).0))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            ("UnexpectedToken", 3),
+            ("UnclosedString", 6),
+            ("UnclosedTag", 7),
+            ("UnexpectedToken", 8),
+            ("IndentationError", 9),
+        ],
+        errors
+    );
+
+    // compile() still stops at the first one, scanning first
+    assert_eq!(ErrorKind::UnclosedString, compile(src).unwrap_err().kind);
+
+    // the good parts are still parsed
+    let (ast, errors) = parse_all(&scan("a := )\nb := 1\nc := (\nd := 2").unwrap());
+    assert_eq!(2, errors.len());
+    assert_eq!(2, ast.len());
+    // every unclosed string is reported, each on its own line
+    let src = "<p> 'a\n<p> \"b\n<p> `c {1}\n<p> ok";
+    let lines = check(src)
+        .iter()
+        .map(|e| line_and_col(src, e.pos).0)
+        .collect::<Vec<_>>();
+    assert_eq!(vec![1, 2, 3], lines);
+    assert!(check("<p> \"ok\"").is_empty());
+}
+
+#[test]
+fn test_malformed_errors() {
+    // these used to panic instead
+    let err = compile("<p> \"Hi {name\"").unwrap_err();
+    assert_eq!("unclosed { in string", err.details);
+    assert_eq!(8, err.pos);
+    assert!(compile("x := \"{\"").is_err());
+    assert_eq!(
+        "tag is already closed",
+        compile("<br//>").unwrap_err().details
+    );
+    assert_eq!(2, check("<p> \"a{\"\n<br//>").len());
+}
@@ -1,6 +1,7 @@
 #![allow(unused)]
+#![allow(clippy::approx_constant)]
 
-use hatter::{compile, eval, render, Value};
+use hatter::{compile, eval, render, Env, MemoryLoader, Value};
 
 #[macro_use]
 mod helpers;
@@ -49,6 +50,189 @@ fn test_attributes() {
     assert_render!(r#"<div data-id=123/>"#, "<div data-id=123 />\n");
 }
 
+#[test]
+fn test_escaping() {
+    assert_render!(
+        r#"name := "<script>alert('hi')</script>"
+<b> name"#,
+        "<b>&lt;script&gt;alert('hi')&lt;/script&gt;</b>\n"
+    );
+    assert_render!(
+        r#"title := "it's <b>"
+<div title=title>"#,
+        "<div title='it&#39;s &lt;b&gt;'></div>\n"
+    );
+    assert_render!(
+        r#"<a href="/?a=1&b=2"> "Tom & Jerry""#,
+        "<a href='/?a=1&amp;b=2'>Tom &amp; Jerry</a>\n"
+    );
+    assert_render!(r#"print("<&>")"#, "&lt;&amp;&gt;");
+    assert_render!(r#"<p> raw("<i>hi</i>")"#, "<p><i>hi</i></p>\n");
+    assert_render!(
+        r#"<script> "if (1 < 2) alert('hi')""#,
+        "<script>if (1 < 2) alert('hi')</script>\n"
+    );
+    // values can't close a <script> or <style> early
+    assert_render!(
+        r#"x := "</script><img src=x onerror=alert(1)>"
+<script> x"#,
+        "<script><\\/script><img src=x onerror=alert(1)></script>\n"
+    );
+    assert_render!(
+        r#"x := "</STYLE><!--"
+<style> x"#,
+        "<style><\\/STYLE><\\!--</style>\n"
+    );
+    // or a quoted attribute, even if they're HTML
+    assert_render!(
+        r#"<p title=raw("<b class='x' onclick='y'>")>"#,
+        "<p title='&lt;b class=&#39;x&#39; onclick=&#39;y&#39;&gt;'></p>\n"
+    );
+    assert_render!(
+        r#"link := fn(url) return <a href=url> url
+<p> link("/")"#,
+        "<p><a href='/'>/</a>\n</p>\n"
+    );
+    assert_render!(
+        r#"b := <b> "&"
+<p> "1 < 2 {b}""#,
+        "<p>1 &lt; 2 <b>&amp;</b>\n</p>\n"
+    );
+}
+
+#[test]
+fn test_layout() {
+    assert_render!(
+        r#"<div> block body do <p> "Default""#,
+        "<div><p>Default</p>\n</div>\n"
+    );
+    assert_render!(
+        r#"
+extends "tests/templates/layout.hat"
+name := "Alice"
+block title do "Hi {name}"
+block body
+    <h1> "Welcome, {name}"
+"#,
+        "<!DOCTYPE html>
+<html><head><title>Hi Alice</title>
+</head>
+<body><div id='main'><h1>Welcome, Alice</h1>
+</div>
+</body>
+
+</html>
+"
+    );
+    assert_render!(
+        r#"extends "tests/templates/layout.hat""#,
+        "<!DOCTYPE html>
+<html><head><title>Default Title</title>
+</head>
+<body><div id='main'><p>Nothing here</p>
+</div>
+</body>
+
+</html>
+"
+    );
+
+    // `block`, `extends`, and `import` still work as names
+    assert_render!("block := 1\n<p block=block> block", "<p block=1>1</p>\n");
+    assert_render!(
+        "def extends(a)\n  a + 1\nx := {import: 2}\n<p> extends(x.import)",
+        "<p>3</p>\n"
+    );
+}
+
+#[test]
+fn test_def_tag() {
+    assert_render!(
+        r#"
+def <item(item)> do <li.item data-id=item.id> item.text
+<ul>
+    for x in [{id: 1, text: "one"}, {id: 2, text: "two"}]
+        <item item=x />
+"#,
+        "<ul><li class='item' data-id=1>one</li>
+<li class='item' data-id=2>two</li>
+</ul>
+"
+    );
+    assert_render!(
+        r#"
+def <card(title, class)>
+    <div.card.{class}>
+        <h2> title
+        children
+<card.wide title="Hi">
+    <p> There
+<card title="Empty" />
+"#,
+        "<div class='card wide'><h2>Hi</h2>
+<p>There</p>
+</div>
+<div class='card'><h2>Empty</h2>
+</div>
+"
+    );
+    assert_error!("def <x(a)> do <b> a\n<x b=1 />");
+}
+
+fn loader() -> MemoryLoader {
+    let mut loader = MemoryLoader::new();
+    loader.insert("nav.hat", "<nav> for link in links\n  <a href=link> link");
+    loader.insert(
+        "helpers.hat",
+        "def shout(s)\n  return to-uppercase(s)\nsecret := 42",
+    );
+    loader.insert("loop.hat", "include('loop.hat')");
+    loader
+}
+
+#[test]
+fn test_include() {
+    let mut env = Env::new();
+    env.set_loader(loader());
+    assert_eq!(
+        "<div><nav><a href='/a'>/a</a>\n<a href='/b'>/b</a>\n</nav>\n</div>\n",
+        env.render(
+            r#"
+links := ["/a", "/b"]
+<div> include("nav.hat")
+"#
+        )
+        .unwrap()
+    );
+    let err = env.render("include('loop.hat')").unwrap_err();
+    assert_eq!("include cycle: loop.hat -> loop.hat", err.details);
+    assert_eq!(Some("loop.hat"), err.path());
+    assert!(env.render("include('missing.hat')").is_err());
+}
+
+#[test]
+fn test_import() {
+    let mut env = Env::new();
+    env.set_loader(loader());
+    assert_eq!(
+        "<p>HEY 42</p>\n",
+        env.render(
+            r#"
+import "helpers.hat"
+<p> "{shout('hey')} {secret}"
+"#
+        )
+        .unwrap()
+    );
+    let mut env = Env::new();
+    env.set_loader(loader());
+    assert_eq!(
+        "<p>YO</p>\n",
+        env.render("import('helpers.hat')\n<p> shout('yo')")
+            .unwrap()
+    );
+}
+
 #[test]
 fn test_index() {
     assert_eval!(
@@ -239,7 +423,7 @@ def total(x)
 total(5)
         "#,
         15
-    )
+    );
 }
 
 #[test]
@@ -312,7 +496,7 @@ fn test_math() {
     assert_eval!("2.2 + 3.3", num!(5.5));
 }
 
-////
+//
 // Hatter in a Hurry
 
 #[test]
@@ -432,7 +616,7 @@ else if i > 0
     print("Positive")
 else if i < 0
     print("Negative")
-"#;
+"#
         };
     }
 
@@ -686,3 +870,70 @@ greet(name: "Marley", title: "Dr")
 "
     );
 }
+
+#[test]
+fn test_fn_args_scope() {
+    // args are evaluated by the caller, even if they shadow params.
+    // they used to be evaluated after the params were set, so `f(1,
+    // a)` passed 1 as `b`.
+    assert_render!(
+        r#"
+a := 5
+def f(a, b)
+    return b
+<p> f(1, a)
+"#,
+        "<p>5</p>\n"
+    );
+    assert_eval!(
+        r#"
+def f(a, b)
+    concat(a, b)
+def g(a, b)
+    f(b, a)
+g("1", "2")
+"#,
+        "21"
+    );
+
+    // returning from inside an `if` in a loop doesn't leak scopes
+    assert_render!(
+        r#"
+def first-even(list)
+    for n in list
+        if n % 2 == 0
+            return n
+<p> "{first-even([1, 2, 3])} {first-even([5, 6])}"
+"#,
+        "<p>2 6</p>\n"
+    );
+}
+
+#[test]
+fn test_fn_scope_popped() {
+    // calls used to leave the scope the function was defined in on
+    // the stack, so its variables leaked into the caller
+    assert_render!(
+        r#"
+def make()
+    x := 1
+    return fn() return x
+g := make()
+y := g()
+<p> x
+"#,
+        "<p>x</p>\n"
+    );
+
+    // and a loop clearing its scope would clear the leaked one
+    assert_render!(
+        r#"
+def f()
+    return 1
+for i in 0..3
+    f()
+<p> f()
+"#,
+        "<p>1</p>\n"
+    );
+}
@@ -0,0 +1,118 @@
+use {
+    hatter::{compile, format, Stmt},
+    std::{fs, path::Path},
+};
+
+macro_rules! format_test {
+    ($name:ident, $src:expr, $expected:expr) => {
+        #[test]
+        fn $name() {
+            let formatted = format($src).unwrap();
+            assert_eq!($expected, formatted);
+            assert_eq!(formatted, format(&formatted).unwrap(), "not idempotent");
+        }
+    };
+}
+
+format_test!(
+    indentation,
+    "def f(a)\n    if a\n            <p> a\n    else\n            <p> 0\n",
+    "def f(a)\n  if a\n    <p> a\n  else\n    <p> 0\n"
+);
+
+format_test!(
+    tabs,
+    "<ul>\n\t<li> one\n\t<li>\n\t\ttwo\n",
+    "<ul>\n  <li> one\n  <li>\n    two\n"
+);
+
+format_test!(
+    operators,
+    "x:=1+2*  3\ny := -x\nz = x>=y && !y\nr := 1..10\n",
+    "x := 1 + 2 * 3\ny := -x\nz = x >= y && !y\nr := 1..10\n"
+);
+
+format_test!(
+    brackets_and_commas,
+    "f( 1 ,2 )\nl := [ 1,2 ]\nm := { a:1 , b :2 }\nm.a\ng(a: 1 ,b: 2)\n",
+    "f(1, 2)\nl := [1, 2]\nm := {a: 1, b: 2}\nm.a\ng(a: 1, b: 2)\n"
+);
+
+format_test!(
+    continued_lines,
+    "nums := [\n1,\n      2,\n]\ntotal := 1\n+ 2\n",
+    "nums := [\n  1,\n  2,\n]\ntotal := 1\n  + 2\n"
+);
+
+format_test!(
+    comments,
+    "# top\nx := 1   # one\ndef f()\n# about f\n  x\n    # still in f\ny := 2\n# bye\n",
+    "# top\nx := 1 # one\ndef f()\n  # about f\n  x\n  # still in f\ny := 2\n# bye\n"
+);
+
+format_test!(
+    blank_lines,
+    "\n\nx := 1\n\n\n\ny := 2   \n\n",
+    "x := 1\n\ny := 2\n"
+);
+
+format_test!(
+    one_liners_stay,
+    "if x  then  <b> yes else  <i> no\nfor v in list  do <li> v\n",
+    "if x then <b> yes else <i> no\nfor v in list do <li> v\n"
+);
+
+format_test!(
+    tag_shorthand,
+    "<div .card#main   >\n<input@q:text#box.wide  placeholder = \"Search\"/>\n<li.item.active=on#x>",
+    "<div#main.card>\n<input#box.wide@q:text placeholder=\"Search\" />\n<li#x.item.active=on>\n"
+);
+
+format_test!(
+    text_is_left_alone,
+    "<p>   Hello , world!  <b>Bold</>  text\n",
+    "<p> Hello , world! <b>Bold</> text\n"
+);
+
+format_test!(
+    strings_are_left_alone,
+    "x := \"a   b\" + '{c}'\ny := \"\"\"\n  keep\n    this\n\"\"\"\n<!--   html   -->\n",
+    "x := \"a   b\" + '{c}'\ny := \"\"\"\n  keep\n    this\n\"\"\"\n<!--   html   -->\n"
+);
+
+#[test]
+fn test_format_errors() {
+    assert!(format("<p> \"unclosed").is_err());
+    assert!(format("def f(\n").is_err());
+}
+
+/// Every example formats to the same AST, and formatting twice
+/// changes nothing.
+#[test]
+fn test_format_examples() {
+    fn visit(dir: &Path) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                visit(&path);
+            } else if path.extension().is_some_and(|e| e == "hat") {
+                let source = fs::read_to_string(&path).unwrap();
+                let ast = match compile(&source) {
+                    Ok(ast) => without_spans(ast),
+                    Err(..) => continue,
+                };
+                let formatted = format(&source).unwrap();
+                let formatted_ast = without_spans(compile(&formatted).unwrap());
+                assert_eq!(ast, formatted_ast, "{:?}", path);
+                assert_eq!(formatted, format(&formatted).unwrap(), "{:?}", path);
+            }
+        }
+    }
+    visit(Path::new("./examples"));
+    visit(Path::new("./tests/examples"));
+}
+
+fn without_spans(mut stmts: Vec<Stmt>) -> Vec<Stmt> {
+    stmts.iter_mut().for_each(Stmt::clear_spans);
+    stmts
+}
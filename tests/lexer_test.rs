@@ -591,3 +591,28 @@ if true # this should work
     );
 
 }
+
+#[test]
+fn test_scan_with_comments() {
+    let src = "# top\nx := 1 # one\nif x\n    # inside\n    x\n";
+    let tokens = hatter::scan_with_comments(src).unwrap();
+    let comments = tokens
+        .iter()
+        .filter(|t| t.kind == Comment)
+        .map(|t| (t.pos, t.literal()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![(0, "# top"), (13, "# one"), (28, "# inside")],
+        comments
+    );
+
+    // same tokens otherwise
+    let kinds = |tokens: Vec<Token>| {
+        tokens
+            .into_iter()
+            .filter(|t| t.kind != Comment)
+            .map(|t| t.kind)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(kinds(scan!(src)), kinds(tokens));
+}
@@ -0,0 +1,161 @@
+use hatter::{compile, lint, Lint, Linter, MemoryLoader};
+
+/// Lint `src` and return each warning's lint and the code it's for.
+fn check(linter: &Linter, src: &str) -> Vec<(Lint, String)> {
+    linter
+        .lint(&compile(src).unwrap())
+        .into_iter()
+        .map(|w| (w.lint, src[w.pos..w.pos + w.len].to_string()))
+        .collect()
+}
+
+macro_rules! lint_test {
+    ($name:ident, $src:expr) => {
+        lint_test!($name, $src,);
+    };
+    ($name:ident, $src:expr, $($lint:ident $code:expr),* $(,)?) => {
+        #[test]
+        fn $name() {
+            let expected: Vec<(Lint, String)> = vec![$((Lint::$lint, $code.to_string())),*];
+            assert_eq!(expected, check(&Linter::new(), $src));
+        }
+    };
+}
+
+lint_test!(
+    clean,
+    r#"
+def greet(name)
+  "Hi, {name}!"
+names := ["Alice", "Bob"]
+<ul> for name in names
+  <li> greet(name)
+<p> Just some text.
+"#
+);
+
+lint_test!(
+    undefined_variable,
+    "x := nope + 1\n<p> x",
+    UndefinedVariable "nope"
+);
+
+lint_test!(
+    undefined_in_fn_set_later,
+    r#"
+def f()
+  later + missing
+later := 1
+<p> f()
+"#,
+    UndefinedVariable "missing"
+);
+
+lint_test!(
+    unused,
+    r#"
+x := 1
+def f()
+  y := 2
+  3
+_ignored := 4
+<p> "hi"
+"#,
+    UnusedVariable "x",
+    UnusedFunction "f",
+    UnusedVariable "y"
+);
+
+lint_test!(
+    shadowing,
+    r#"
+x := 1
+if x
+  x := 2
+  <p> x
+"#,
+    Shadowing "x"
+);
+
+lint_test!(
+    undeclared_assign,
+    "x = 1",
+    UndeclaredAssign "x"
+);
+
+lint_test!(
+    unreachable,
+    r#"
+def f(a)
+  return a
+  a + 1
+for x in [1, 2]
+  break
+  print(x)
+<p> f(1)
+"#,
+    Unreachable "+",
+    Unreachable "print"
+);
+
+lint_test!(
+    arity_mismatch,
+    r#"
+def f(a, b)
+  a + b
+<p> f(1)
+<p> f(a: 1, b: 2)
+"#,
+    ArityMismatch "f"
+);
+
+lint_test!(
+    tags,
+    r#"
+<div#main>
+  <a> "Nowhere"
+  <a href="/"> "Home"
+  <p#main class="a" class="b"> "Hi"
+"#,
+    MissingHref "a",
+    DuplicateAttribute "p",
+    DuplicateId "p"
+);
+
+lint_test!(
+    custom_tags,
+    r#"
+def <card(title)>
+  <b> title
+  children
+def <unused>
+  <i> "nope"
+<card title="x"> "body"
+"#,
+    UnusedFunction "unused"
+);
+
+#[test]
+fn test_globals_and_imports() {
+    let src = "import 'helpers.hat'\n<p> helper(user)";
+    let mut linter = Linter::new();
+    linter.add_global("user");
+    // without a loader, we can't know what's imported
+    assert!(check(&linter, src).is_empty());
+
+    let mut loader = MemoryLoader::new();
+    loader.insert("helpers.hat", "def helper(x)\n  x");
+    linter.set_loader(loader);
+    assert!(check(&linter, src).is_empty());
+    assert_eq!(
+        vec![(Lint::UndefinedVariable, "nope".to_string())],
+        check(&linter, "import 'helpers.hat'\n<p> nope(user)")
+    );
+
+    // imported templates define things for someone else
+    let mut linter = Linter::new();
+    linter.set_unused_globals(false);
+    assert!(check(&linter, "def helper(x)\n  x").is_empty());
+
+    assert_eq!(1, lint("x = 1").unwrap().len());
+}
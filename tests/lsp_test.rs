@@ -0,0 +1,358 @@
+#![cfg(feature = "lsp")]
+
+use {
+    hatter::{lsp::Server, Symbol, Value},
+    std::io::{BufRead, Cursor, Read},
+};
+
+const URI: &str = "file:///tmp/test.hat";
+
+const SRC: &str = r#"
+title := "Hello"
+def <card(heading)>
+  <div.card> heading
+def greet(name)
+  "Hi, {name}!"
+<h1> greet(title)
+<card heading=title>
+"#;
+
+/// Send a script of JSON messages to a fresh Server, and return
+/// everything it sent back.
+fn run(script: &[String]) -> Vec<Value> {
+    let mut input = String::new();
+    for msg in script {
+        input.push_str(&format!("Content-Length: {}\r\n\r\n{}", msg.len(), msg));
+    }
+    let mut output = vec![];
+    Server::new()
+        .serve(Cursor::new(input), &mut output)
+        .unwrap();
+
+    let mut output = Cursor::new(output);
+    let mut msgs = vec![];
+    loop {
+        let mut header = String::new();
+        if output.read_line(&mut header).unwrap() == 0 {
+            break;
+        }
+        let len: usize = header["Content-Length: ".len()..].trim().parse().unwrap();
+        output.read_line(&mut header).unwrap();
+        let mut body = vec![0; len];
+        output.read_exact(&mut body).unwrap();
+        msgs.push(Value::from_json(&String::from_utf8(body).unwrap()).unwrap());
+    }
+    msgs
+}
+
+fn request(id: usize, method: &str, params: &str) -> String {
+    format!(
+        r#"{{"jsonrpc":"2.0","id":{},"method":"{}","params":{}}}"#,
+        id, method, params
+    )
+}
+
+fn open(src: &str) -> String {
+    format!(
+        r#"{{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{{"textDocument":{{"uri":"{}","languageId":"hatter","version":1,"text":{}}}}}}}"#,
+        URI,
+        Value::from(src).to_json()
+    )
+}
+
+fn at(line: usize, character: usize) -> String {
+    format!(
+        r#"{{"textDocument":{{"uri":"{}"}},"position":{{"line":{},"character":{}}}}}"#,
+        URI, line, character
+    )
+}
+
+fn get(val: &Value, path: &[&str]) -> Value {
+    let mut val = val.clone();
+    for key in path {
+        val = match &val {
+            Value::Map(map) => map.borrow().get(*key).cloned().unwrap_or(Value::None),
+            Value::List(list) => list.borrow()[key.parse::<usize>().unwrap()].clone(),
+            _ => Value::None,
+        };
+    }
+    val
+}
+
+/// The response to the request with `id`.
+fn response(msgs: &[Value], id: usize) -> Value {
+    msgs.iter()
+        .find(|m| get(m, &["id"]) == Value::from(id))
+        .cloned()
+        .unwrap()
+}
+
+#[test]
+fn test_initialize() {
+    let msgs = run(&[
+        request(1, "initialize", r#"{"capabilities":{}}"#),
+        request(2, "shutdown", "null"),
+        request(3, "textDocument/hover", &at(0, 0)),
+        r#"{"jsonrpc":"2.0","method":"exit"}"#.into(),
+        request(4, "shutdown", "null"),
+    ]);
+    assert_eq!(3, msgs.len());
+    let caps = get(&msgs[0], &["result", "capabilities"]);
+    assert_eq!(Value::from(1), get(&caps, &["textDocumentSync"]));
+    assert_eq!(Value::from(true), get(&caps, &["hoverProvider"]));
+    assert_eq!(Value::from(true), get(&caps, &["definitionProvider"]));
+    assert_eq!(Value::from(true), get(&caps, &["documentSymbolProvider"]));
+    assert_eq!(Value::None, get(&msgs[1], &["result"]));
+    assert_eq!(Value::from(-32600), get(&msgs[2], &["error", "code"]));
+}
+
+#[test]
+fn test_unknown_method() {
+    let msgs = run(&[
+        r#"{"jsonrpc":"2.0","method":"$/unknownNotification"}"#.into(),
+        request(1, "workspace/unknown", "{}"),
+        "{not json".into(),
+    ]);
+    assert_eq!(2, msgs.len());
+    assert_eq!(Value::from(-32601), get(&msgs[0], &["error", "code"]));
+    assert_eq!(Value::from(-32700), get(&msgs[1], &["error", "code"]));
+    assert_eq!(Value::None, get(&msgs[1], &["id"]));
+}
+
+#[test]
+fn test_diagnostics() {
+    let change = format!(
+        r#"{{"jsonrpc":"2.0","method":"textDocument/didChange","params":{{"textDocument":{{"uri":"{}","version":2}},"contentChanges":[{{"text":"x := 1\n<p> x"}}]}}}}"#,
+        URI
+    );
+    let msgs = run(&[
+        open("<p> ok\n<div> \"unclosed\n</span>"),
+        change,
+        open("x := 1\n<p> y + 1"),
+    ]);
+    assert_eq!(3, msgs.len());
+    for msg in &msgs {
+        assert_eq!(
+            Value::from("textDocument/publishDiagnostics"),
+            get(msg, &["method"])
+        );
+        assert_eq!(Value::from(URI), get(msg, &["params", "uri"]));
+    }
+
+    // syntax errors
+    let diags = get(&msgs[0], &["params", "diagnostics"]);
+    assert_eq!(2, diags.len());
+    assert_eq!(Value::from("UnclosedString"), get(&diags, &["0", "code"]));
+    assert_eq!(Value::from(1), get(&diags, &["0", "severity"]));
+    assert_eq!(
+        Value::from(1),
+        get(&diags, &["0", "range", "start", "line"])
+    );
+    assert_eq!(
+        Value::from(7),
+        get(&diags, &["0", "range", "start", "character"])
+    );
+    assert_eq!(
+        Value::from(2),
+        get(&diags, &["1", "range", "start", "line"])
+    );
+
+    // fixed
+    assert_eq!(0, get(&msgs[1], &["params", "diagnostics"]).len());
+
+    // lint warnings
+    let diags = get(&msgs[2], &["params", "diagnostics"]);
+    assert_eq!(2, diags.len());
+    assert_eq!(Value::from("unused-variable"), get(&diags, &["0", "code"]));
+    assert_eq!(Value::from(2), get(&diags, &["0", "severity"]));
+    assert_eq!(
+        Value::from("undefined-variable"),
+        get(&diags, &["1", "code"])
+    );
+    assert_eq!(
+        Value::from(4),
+        get(&diags, &["1", "range", "start", "character"])
+    );
+}
+
+#[test]
+fn test_hover() {
+    let msgs = run(&[
+        open(SRC),
+        request(1, "textDocument/hover", &at(6, 6)), // greet
+        request(2, "textDocument/hover", &at(6, 12)), // title
+        request(3, "textDocument/hover", &at(7, 2)), // <card>
+        request(4, "textDocument/hover", &at(5, 8)), // name
+        request(5, "textDocument/hover", &at(2, 3)), // def
+    ]);
+    let hover = |id| get(&response(&msgs, id), &["result", "contents", "value"]);
+    assert_eq!(Value::from("```hatter\ndef greet(name)\n```"), hover(1));
+    assert_eq!(Value::from("```hatter\ntitle: string\n```"), hover(2));
+    assert_eq!(Value::from("```hatter\ndef <card(heading)>\n```"), hover(3));
+    assert_eq!(
+        Value::from("```hatter\nname: parameter of greet\n```"),
+        hover(4)
+    );
+    assert_eq!(Value::None, get(&response(&msgs, 5), &["result"]));
+
+    let msgs = run(&[
+        open("<p> len([1, 2])"),
+        request(1, "textDocument/hover", &at(0, 5)),
+    ]);
+    let hover = get(&response(&msgs, 1), &["result", "contents", "value"]);
+    assert!(hover.to_str().starts_with("```hatter\nlen\n```\n\n"));
+    assert!(hover.to_str().contains("length"), "{}", hover);
+
+    // builtin docs come from their Rust doc comments
+    let msgs = run(&[
+        open("<p> type(1)"),
+        request(1, "textDocument/hover", &at(0, 5)),
+    ]);
+    let hover = get(&response(&msgs, 1), &["result", "contents", "value"]);
+    assert!(
+        hover.to_str().contains("type(123)     #=> \"number\""),
+        "{}",
+        hover
+    );
+    assert!(
+        hover.to_str().contains("```hatter\ntype('heyo')"),
+        "{}",
+        hover
+    );
+}
+
+#[test]
+fn test_definition() {
+    let msgs = run(&[
+        open(SRC),
+        request(1, "textDocument/definition", &at(6, 5)),
+        request(2, "textDocument/definition", &at(7, 3)),
+        request(3, "textDocument/definition", &at(6, 14)),
+        request(4, "textDocument/definition", &at(6, 1)),
+    ]);
+    let range = |id| get(&response(&msgs, id), &["result", "range"]);
+    let line_col = |id| {
+        (
+            get(&range(id), &["start", "line"]),
+            get(&range(id), &["start", "character"]),
+            get(&range(id), &["end", "character"]),
+        )
+    };
+    assert_eq!(
+        Value::from(URI),
+        get(&response(&msgs, 1), &["result", "uri"])
+    );
+    assert_eq!((4.into(), 4.into(), 9.into()), line_col(1));
+    assert_eq!((2.into(), 5.into(), 9.into()), line_col(2));
+    assert_eq!((1.into(), 0.into(), 5.into()), line_col(3));
+    assert_eq!(Value::None, get(&response(&msgs, 4), &["result"]));
+}
+
+#[test]
+fn test_completion() {
+    let labels = |msgs: &[Value], id| match get(&response(msgs, id), &["result"]) {
+        Value::List(items) => items
+            .borrow()
+            .iter()
+            .map(|i| get(i, &["label"]).to_str().to_string())
+            .collect::<Vec<_>>(),
+        _ => panic!("expected a list"),
+    };
+
+    let msgs = run(&[
+        open(SRC),
+        request(1, "textDocument/completion", &at(5, 7)),
+        request(2, "textDocument/completion", &at(7, 1)),
+        request(3, "textDocument/completion", &at(7, 0)),
+    ]);
+    let items = labels(&msgs, 1);
+    for name in &["title", "greet", "name", "len", "to-uppercase", "empty?"] {
+        assert!(items.contains(&name.to_string()), "missing {}", name);
+    }
+    for name in &["card", "heading", "==", "+"] {
+        assert!(!items.contains(&name.to_string()), "has {}", name);
+    }
+    assert_eq!(vec!["card"], labels(&msgs, 2));
+    let items = labels(&msgs, 3);
+    assert!(!items.contains(&"name".to_string()));
+    assert!(items.contains(&"greet".to_string()));
+}
+
+#[test]
+fn test_document_symbols() {
+    let msgs = run(&[
+        open(SRC),
+        request(1, "textDocument/documentSymbol", &at(0, 0)),
+    ]);
+    let symbols = get(&response(&msgs, 1), &["result"]);
+    assert_eq!(3, symbols.len());
+    let symbol = |i: &str, key: &str| get(&symbols, &[i, key]);
+    assert_eq!(Value::from("title"), symbol("0", "name"));
+    assert_eq!(Value::from(13), symbol("0", "kind"));
+    assert_eq!(Value::from("<card>"), symbol("1", "name"));
+    assert_eq!(Value::from(12), symbol("1", "kind"));
+    assert_eq!(Value::from("greet"), symbol("2", "name"));
+    assert_eq!(
+        Value::from(5),
+        get(&symbols, &["2", "range", "end", "line"])
+    );
+}
+
+#[test]
+fn test_non_ascii() {
+    // half-typed documents with spans ending inside a char
+    for src in [
+        "<p> \"é",
+        "<p.é",
+        "é é é(",
+        "<a href=é>",
+        "\"\"\"\né",
+        "x := 'ü\n<p> x",
+    ] {
+        let msgs = run(&[open(src), request(1, "textDocument/hover", &at(0, 2))]);
+        assert_eq!(2, msgs.len(), "{}", src);
+        assert_eq!(
+            Value::from("textDocument/publishDiagnostics"),
+            get(&msgs[0], &["method"])
+        );
+    }
+}
+
+#[test]
+fn test_documents_not_interned() {
+    // every keystroke sends the whole document, so it can't be interned
+    let src = "<p> 1 # a document nobody has interned";
+    let msg = format!(
+        r#"{{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{{"textDocument":{{"uri":"{}","text":"{}"}}}}}}"#,
+        URI, src
+    );
+    assert_eq!(1, run(&[msg]).len());
+    assert!(Symbol::find(src).is_none());
+}
+
+#[test]
+fn test_utf16_positions() {
+    // 😀 is two UTF-16 code units but four bytes
+    let msgs = run(&[
+        open("t := [\"😀\", nope]"),
+        request(1, "textDocument/hover", &at(0, 1)),
+    ]);
+    let diags = get(&msgs[0], &["params", "diagnostics"]);
+    let undefined = match &diags {
+        Value::List(list) => list
+            .borrow()
+            .iter()
+            .find(|d| get(d, &["code"]) == Value::from("undefined-variable"))
+            .cloned()
+            .unwrap(),
+        _ => panic!("expected a list"),
+    };
+    assert_eq!(
+        Value::from(12),
+        get(&undefined, &["range", "start", "character"])
+    );
+    assert_eq!(
+        Value::from("```hatter\nt: list\n```"),
+        get(&response(&msgs, 1), &["result", "contents", "value"])
+    );
+}
@@ -0,0 +1,113 @@
+use {
+    hatter::{compile, optimize, Code, Env, Result, Stmt},
+    std::{fs, path::Path},
+};
+
+/// Render `source` as-is, then optimized with the tree-walker and
+/// the VM, each in a new `Env` that's run `setup` first. All three
+/// should agree, errors included.
+fn render(setup: &str, source: &str) -> Result<String> {
+    let env = || {
+        let mut env = Env::new();
+        env.eval_src(setup).unwrap();
+        env
+    };
+    let plain = compile(source).and_then(|stmts| env().render_stmts(&stmts));
+    let stmts = compile(source).map(optimize);
+    let tree = stmts.clone().and_then(|stmts| env().render_stmts(&stmts));
+    let vm = stmts.and_then(|stmts| env().render_code(&Code::new(stmts)));
+    assert_eq!(plain, tree, "{}", source);
+    assert_eq!(plain, vm, "{}", source);
+    tree
+}
+
+/// The `Stmt` `val := EXPR` sets `val` to.
+fn folded(expr: &str) -> Stmt {
+    let source = format!("val := {}", expr);
+    match optimize(compile(source).unwrap()).remove(0) {
+        Stmt::Assign(_, val, ..) => *val,
+        other => panic!("expected assign, got {:?}", other),
+    }
+}
+
+fn prerendered(source: &str) -> Option<String> {
+    match optimize(compile(source).unwrap()).remove(0) {
+        Stmt::Tag(tag) => tag.html.map(|html| html.html.clone()),
+        other => panic!("expected tag, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_fold() {
+    assert_eq!(Stmt::Value(4.into()), folded("2 + 2"));
+    assert_eq!(Stmt::Value("ab".into()), folded(r#""a" + "b""#));
+    assert_eq!(Stmt::Value("a2b".into()), folded(r#""a{1 + 1}b""#));
+    assert_eq!(Stmt::Value(true.into()), folded("1 < 2 && !false"));
+    assert_eq!(Stmt::Value(7.into()), folded("1 + 2 * 3"));
+
+    // only literals
+    assert!(matches!(folded("x + 2"), Stmt::Call(..)));
+    assert!(matches!(folded("to-uppercase('hi')"), Stmt::Call(..)));
+    // errors are left for runtime
+    assert!(matches!(folded("'a' - 1"), Stmt::Call(..)));
+    // builtins the template redefines aren't folded
+    let stmts = optimize(compile("concat := fn(a, b) return a\nconcat(1, 2)").unwrap());
+    assert!(matches!(stmts[1], Stmt::Call(..)));
+}
+
+#[test]
+fn test_prerender() {
+    assert_eq!(
+        Some("<p class='a'><b>4</b>\n</p>\n".to_string()),
+        prerendered("<p.a> <b> 2 + 2")
+    );
+    assert_eq!(
+        Some("<div><em>hello</em>\n</div>\n".to_string()),
+        prerendered("<div>\n  <em> hello")
+    );
+    assert!(prerendered("<p title=x> hi").is_none());
+    assert!(prerendered("<p> x + 1").is_none());
+    assert!(prerendered("<script> 1 < 2").is_none());
+    assert!(prerendered("<div>\n  <debug> 1").is_none());
+}
+
+#[test]
+fn test_prerender_fallback() {
+    // custom tags and vars defined after the template was optimized
+    let source = "<div>\n  <em> hello";
+    assert_eq!("<div><em>hello</em>\n</div>\n", render("", source).unwrap());
+    assert_eq!(
+        "<div><em>1</em>\n</div>\n",
+        render("hello := 1", source).unwrap()
+    );
+    let custom = "def <em()> do return '!'";
+    assert_eq!("<div>!</div>\n", render(custom, source).unwrap());
+
+    // in the template itself
+    render("", "def <b()> do return '!'\n<p> <b> bold").unwrap();
+    render("", "<p> <b> x\nx := 1\n<p> <b> x").unwrap();
+    render("", "for i in [1, 2]\n  <b> i\n  <i> 2 + 2").unwrap();
+
+    render("", "<script>\n  <b> \"a & b\"").unwrap();
+}
+
+#[test]
+fn test_optimize_examples() {
+    let mut files = vec![];
+    hat_files(Path::new("./examples"), &mut files);
+    assert!(!files.is_empty());
+    for source in files {
+        let _ = render("", &source);
+    }
+}
+
+fn hat_files(dir: &Path, files: &mut Vec<String>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            hat_files(&path, files);
+        } else if path.extension().is_some_and(|e| e == "hat") {
+            files.push(fs::read_to_string(&path).unwrap());
+        }
+    }
+}
@@ -1,4 +1,6 @@
-use hatter::{compile, Stmt, Symbol, Tag};
+#![allow(clippy::approx_constant)]
+
+use hatter::{compile, Span, Stmt, Symbol, Tag, Value};
 
 trait Intern {
     fn to_sym(&self) -> Symbol;
@@ -30,7 +32,7 @@ macro_rules! boo {
 
 macro_rules! word {
     ($code:expr) => {
-        Stmt::Word(Symbol::from($code))
+        Stmt::Word(Symbol::from($code), Span::default())
     };
 }
 
@@ -40,6 +42,12 @@ macro_rules! string {
     };
 }
 
+macro_rules! html {
+    ($code:expr) => {
+        Stmt::Value(Value::Html($code.into()))
+    };
+}
+
 macro_rules! num {
     ($num:expr) => {
         Stmt::Value($num.into())
@@ -48,19 +56,19 @@ macro_rules! num {
 
 macro_rules! call {
     ($name:expr, $($arg:expr),+) => {
-        Stmt::Call(Box::new(Stmt::Word(Symbol::from($name))), vec![$($arg),+])
+        Stmt::Call(Box::new(word!($name)), vec![$($arg),+], Span::default())
     };
     ($name:expr) => {
-        Stmt::Call(Box::new(Stmt::Word(Symbol::from($name))), vec![])
+        Stmt::Call(Box::new(word!($name)), vec![], Span::default())
     };
 }
 
 macro_rules! call_expr {
     ($ex:expr, $($arg:expr),+) => {
-        Stmt::Call(Box::new($ex), vec![$($arg),+])
+        Stmt::Call(Box::new($ex), vec![$($arg),+], Span::default())
     };
     ($ex:expr) => {
-        Stmt::Call(Box::new($ex), vec![])
+        Stmt::Call(Box::new($ex), vec![], Span::default())
     };
 }
 
@@ -91,7 +99,9 @@ macro_rules! parse_test {
     ($name:ident, $code:expr, $($stmt:expr,)+) => {
         #[test]
         fn $name() {
-            let nodes = compile($code).unwrap();
+            // the expected Stmts don't say where they are
+            let mut nodes = compile($code).unwrap();
+            nodes.iter_mut().for_each(Stmt::clear_spans);
             let mut i = 0;
             $(
                 let node = nodes.get(i).unwrap();
@@ -121,7 +131,7 @@ parse_test!(
 <!-- okay -->
 true
 ",
-    string!("<!-- okay -->"),
+    html!("<!-- okay -->"),
     boo!(true)
 );
 
@@ -134,7 +144,7 @@ parse_test!(
 -->
 true
 ",
-    string!(
+    html!(
         "<!--
     oooo
     kkkkay
@@ -617,7 +627,8 @@ def greet()
     Stmt::Assign(
         "greet".into(),
         bx!(Stmt::Fn(vec![], vec![call!("print", string!("HEY!!"))])),
-        false
+        false,
+        Span::default()
     )
 );
 
@@ -636,7 +647,8 @@ def greet(name)
                 call!("concat", string!("Hi there, "), word!("name"), string!("!"))
             )]
         )),
-        false
+        false,
+        Span::default()
     )
 );
 
@@ -662,7 +674,8 @@ def greet(title, name)
                 )
             )]
         )),
-        false
+        false,
+        Span::default()
     )
 );
 
@@ -691,7 +704,8 @@ def greet(
                 )
             )]
         )),
-        false
+        false,
+        Span::default()
     )
 );
 
@@ -712,13 +726,43 @@ def <<(a, b)
                     call!("==", call!("type", word!("a")), string!("list")),
                     vec![call!("append", word!("a"), word!("b"))]
                 )]),
-                Stmt::Return(bx!(word!("a")))
+                Stmt::Return(bx!(word!("a")), Span::default())
             ]
         )),
-        false
+        false,
+        Span::default()
     )
 );
 
+parse_test!(def_tag, "def <shout(name)> do <b> name", {
+    let mut b = tag!("b");
+    b.set_body(vec![word!("name")]);
+    Stmt::Assign(
+        "<shout>".into(),
+        bx!(Stmt::Fn(vec!["name".into()], vec![b.into()])),
+        false,
+        Span::default(),
+    )
+});
+
+parse_test!(
+    def_tag_no_params,
+    r#"
+def <footer>
+    <p> children
+"#,
+    {
+        let mut p = tag!("p");
+        p.set_body(vec![word!("children")]);
+        Stmt::Assign(
+            "<footer>".into(),
+            bx!(Stmt::Fn(vec![], vec![p.into()])),
+            false,
+            Span::default(),
+        )
+    }
+);
+
 parse_test!(
     fn_literal_oneline,
     "z := fn(x) do return x + 1\nz(2)",
@@ -726,9 +770,13 @@ parse_test!(
         "z".into(),
         bx!(Stmt::Fn(
             vec!["x".into()],
-            vec![Stmt::Return(bx!(call!("+", word!("x"), num!(1))))]
+            vec![Stmt::Return(
+                bx!(call!("+", word!("x"), num!(1))),
+                Span::default()
+            )]
         )),
-        false
+        false,
+        Span::default()
     ),
     call!("z", num!(2))
 );
@@ -743,20 +791,57 @@ z(2)"#,
         "z".into(),
         bx!(Stmt::Fn(
             vec!["x".into()],
-            vec![Stmt::Return(bx!(call!("+", word!("x"), num!(1))))]
+            vec![Stmt::Return(
+                bx!(call!("+", word!("x"), num!(1))),
+                Span::default()
+            )]
         )),
-        false
+        false,
+        Span::default()
     ),
     call!("z", num!(2))
 );
 
+////
+// block
+
+parse_test!(
+    block_oneline,
+    r#"block title do "Hello""#,
+    Stmt::Block("title".into(), vec![string!("Hello")])
+);
+
+parse_test!(
+    block_empty,
+    "block scripts",
+    Stmt::Block("scripts".into(), vec![])
+);
+
+parse_test!(
+    block_with_extends,
+    r#"
+extends "layout.hat"
+block body
+    <p> "Hi"
+"#,
+    Stmt::Extends(bx!(string!("layout.hat"))),
+    Stmt::Block(
+        "body".into(),
+        vec![{
+            let mut p = tag!("p");
+            p.set_body(vec![string!("Hi")]);
+            Stmt::Tag(p)
+        }]
+    )
+);
+
 ////
 // assign
 
 parse_test!(
     assign,
     "a := 123",
-    Stmt::Assign("a".into(), bx!(num!(123)), false)
+    Stmt::Assign("a".into(), bx!(num!(123)), false, Span::default())
 );
 
 ////
@@ -765,7 +850,7 @@ parse_test!(
 parse_test!(
     reassign,
     "a = 123",
-    Stmt::Assign("a".into(), bx!(num!(123)), true)
+    Stmt::Assign("a".into(), bx!(num!(123)), true, Span::default())
 );
 
 ////
@@ -1269,7 +1354,7 @@ parse_test!(
         form.set_body(
             vec![h3, lbl1, lbl2, br, submit, reset]
                 .into_iter()
-                .map(|x| Stmt::Tag(x))
+                .map(Stmt::Tag)
                 .collect(),
         );
 
@@ -1407,3 +1492,33 @@ xparse_test!(
         Stmt::Tag(ul)
     }
 );
+
+#[test]
+fn test_tag_custom_name() {
+    let custom = |src: &str| match compile(src).unwrap().remove(0) {
+        Stmt::Tag(tag) => tag.custom_name().map(|s| s.to_string()),
+        other => panic!("expected tag, got {:?}", other),
+    };
+    assert_eq!(Some("<card>".to_string()), custom("<card> hi"));
+    assert_eq!(Some("<div>".to_string()), custom("<.box> hi"));
+    let tag = Tag::new(Stmt::Word("name".into(), Span::default()));
+    assert_eq!(None, tag.custom_name());
+}
+
+#[test]
+fn test_spans() {
+    let mut spaced = compile("x  +  y").unwrap();
+    let mut compact = compile("x + y").unwrap();
+    assert_ne!(compact, spaced);
+    match &spaced[0] {
+        Stmt::Call(_, args, span) => {
+            assert_eq!(Span::new(3, 1), *span);
+            assert_eq!(Span::new(6, 1), args[1].span());
+        }
+        other => panic!("expected call, got {:?}", other),
+    }
+
+    spaced.iter_mut().for_each(Stmt::clear_spans);
+    compact.iter_mut().for_each(Stmt::clear_spans);
+    assert_eq!(compact, spaced);
+}
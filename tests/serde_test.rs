@@ -0,0 +1,128 @@
+#![cfg(feature = "serde")]
+
+use {
+    hatter::{to_value, Env, Value},
+    serde::{
+        de::{value::Error, IntoDeserializer},
+        ser::{SerializeStruct, SerializeStructVariant, Serializer},
+        Deserialize, Serialize,
+    },
+    std::collections::BTreeMap,
+};
+
+struct User {
+    name: String,
+    age: u8,
+    admin: bool,
+    email: Option<String>,
+    tags: Vec<&'static str>,
+    role: Role,
+    pos: (i32, i32),
+}
+
+enum Role {
+    Editor { section: &'static str },
+}
+
+enum Status {
+    Active,
+}
+
+// Written out instead of derived, since the `serde` feature doesn't
+// pull in serde's derive macros.
+impl Serialize for User {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        let mut user = s.serialize_struct("User", 7)?;
+        user.serialize_field("name", &self.name)?;
+        user.serialize_field("age", &self.age)?;
+        user.serialize_field("admin", &self.admin)?;
+        user.serialize_field("email", &self.email)?;
+        user.serialize_field("tags", &self.tags)?;
+        user.serialize_field("role", &self.role)?;
+        user.serialize_field("pos", &self.pos)?;
+        user.end()
+    }
+}
+
+impl Serialize for Role {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Role::Editor { section } => {
+                let mut role = s.serialize_struct_variant("Role", 0, "Editor", 1)?;
+                role.serialize_field("section", section)?;
+                role.end()
+            }
+        }
+    }
+}
+
+impl Serialize for Status {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Status::Active => s.serialize_unit_variant("Status", 0, "Active"),
+        }
+    }
+}
+
+#[test]
+fn test_to_value() {
+    assert_eq!(Value::from(1), to_value(&1u8).unwrap());
+    assert_eq!(Value::from("hi"), to_value("hi").unwrap());
+    assert_eq!(Value::None, to_value(&()).unwrap());
+    assert_eq!(Value::from("Active"), to_value(&Status::Active).unwrap());
+    assert_eq!(
+        Value::from(vec![1.5, 2.5]),
+        to_value(&vec![1.5f32, 2.5]).unwrap()
+    );
+
+    let mut map = BTreeMap::new();
+    map.insert(1, "one");
+    let mut expected = BTreeMap::new();
+    expected.insert("1", "one");
+    assert_eq!(Value::from(expected), to_value(&map).unwrap());
+}
+
+#[test]
+fn test_render_struct() {
+    let user = User {
+        name: "Alice".into(),
+        age: 30,
+        admin: true,
+        email: None,
+        tags: vec!["a", "b"],
+        role: Role::Editor { section: "news" },
+        pos: (1, 2),
+    };
+    let mut env = Env::new();
+    env.set_serialize("user", &user).unwrap();
+    assert_eq!(
+        "<p>Alice 30 true </p>\n<p>a b news 2</p>\n",
+        env.render(
+            r#"
+<p> "{user.name} {user.age} {user.admin} {user.email}"
+<p> "{user.tags[0]} {user.tags[1]} {user.role.Editor.section} {user.pos[1]}"
+"#
+        )
+        .unwrap()
+    );
+}
+
+#[test]
+fn test_deserialize() {
+    let val = Value::deserialize(vec![1, 2, 3].into_deserializer());
+    let val: Result<Value, Error> = val;
+    assert_eq!(Value::from(vec![1, 2, 3]), val.unwrap());
+
+    let mut map = BTreeMap::new();
+    map.insert("name".to_string(), "Bob".to_string());
+    let val: Result<Value, Error> = Value::deserialize(map.clone().into_deserializer());
+    assert_eq!(Value::from(map), val.unwrap());
+}
+
+#[test]
+fn test_serialize() {
+    // serialize to a Value and back
+    let val = Value::from(vec![Value::from(1), Value::from("two"), Value::None]);
+    assert_eq!(val, to_value(&val).unwrap());
+    assert!(to_value(&Value::from(|_: hatter::Args| Value::None.ok())).is_err());
+}
@@ -0,0 +1,287 @@
+use {
+    hatter::{compile, Args, Env, MemoryLoader, Stmt, Template, Templates, Value},
+    std::{
+        fmt, fs, io,
+        path::PathBuf,
+        sync::Arc,
+        thread,
+        time::{Duration, SystemTime},
+    },
+};
+
+/// Create an empty temp directory for a test.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("hatter-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("partials")).unwrap();
+    dir
+}
+
+/// Write a file and set its mtime, since some filesystems only
+/// track it to the second.
+fn write(path: PathBuf, source: &str, secs: u64) {
+    fs::write(&path, source).unwrap();
+    fs::File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+        .unwrap();
+}
+
+fn is_send_sync<T: Send + Sync>() {}
+
+#[test]
+fn test_send_sync() {
+    is_send_sync::<Stmt>();
+    is_send_sync::<Value>();
+    is_send_sync::<Template>();
+}
+
+#[test]
+fn test_render_across_threads() {
+    let stmts = Arc::new(compile("<ul> for i in [1, 2, 3] do <li> i * n").unwrap());
+    let threads = (1..=4)
+        .map(|n| {
+            let stmts = stmts.clone();
+            thread::spawn(move || {
+                let mut env = Env::new();
+                env.set("n", n);
+                env.render_stmts(&stmts).unwrap()
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for (n, t) in threads.into_iter().enumerate() {
+        let n = n + 1;
+        assert_eq!(
+            format!(
+                "<ul><li>{}</li>\n<li>{}</li>\n<li>{}</li>\n</ul>\n",
+                n,
+                n * 2,
+                n * 3
+            ),
+            t.join().unwrap()
+        );
+    }
+}
+
+#[test]
+fn test_shared_list_across_threads() {
+    // one thread loops over a list while another pushes to it
+    let items = Value::from(vec![0]);
+    let Value::List(list) = items.clone() else {
+        unreachable!()
+    };
+    let pusher = thread::spawn(move || {
+        for i in 1..=200 {
+            list.borrow_mut().push(i.into());
+        }
+    });
+    let stmts = compile("for x in items\n  <i> x").unwrap();
+    for _ in 0..200 {
+        let mut env = Env::new();
+        env.set("items", items.clone());
+        assert!(env.render_stmts(&stmts).unwrap().starts_with("<i>0</i>"));
+    }
+    pusher.join().unwrap();
+    assert!(matches!(items, Value::List(list) if list.borrow().len() == 201));
+}
+
+#[test]
+fn test_template() {
+    let template = Template::from("<b> 1 + 2");
+    assert_eq!(1, template.stmts().unwrap().len());
+    assert!(Template::open("tests/templates/nope.hat").is_err());
+    assert!(Template::open("tests/templates/layout.hat").is_ok());
+    let path = std::path::Path::new("tests/templates/layout.hat");
+    let file = fs::File::open(path).unwrap();
+    assert_eq!(Template::from(path).source(), Template::from(file).source());
+}
+
+#[test]
+fn test_template_optimize() {
+    // not optimized unless asked, so builtins can be replaced
+    let mut template = Template::from("<b> 1 + 2");
+    let mut env = Env::new();
+    env.set("+", |_: Args| Ok(Value::from("plus")));
+    assert_eq!("<b>plus</b>\n", template.render(&mut env).unwrap());
+
+    template.set_optimize(true);
+    assert!(matches!(&template.stmts().unwrap()[0], Stmt::Tag(tag) if tag.html.is_some()));
+    assert_eq!("<b>3</b>\n", template.render(&mut Env::new()).unwrap());
+
+    let dir = temp_dir("optimize");
+    write(dir.join("index.hat"), "<p> 2 * 2", 1);
+    let mut templates = Templates::new(&dir).unwrap();
+    let optimized = |templates: &Templates| {
+        let index = templates.get("index").unwrap();
+        matches!(&index.stmts().unwrap()[0], Stmt::Tag(tag) if tag.html.is_some())
+    };
+    assert!(!optimized(&templates));
+    templates.set_optimize(true);
+    assert!(optimized(&templates));
+    assert_eq!(
+        "<p>4</p>\n",
+        templates.render("index", &mut Env::new()).unwrap()
+    );
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_templates() {
+    let dir = temp_dir("templates");
+    write(dir.join("index.hat"), "<h1> include('partials/nav')", 1);
+    write(dir.join("partials/nav.hat"), "<nav> Home", 1);
+    write(dir.join("notes.txt"), "not a template", 1);
+
+    let mut templates = Templates::new(&dir).unwrap();
+    templates.set_reload(true);
+    let templates = Arc::new(templates);
+    assert_eq!(vec!["index.hat", "partials/nav.hat"], templates.names());
+    assert!(templates.get("notes.txt").is_err());
+    assert!(templates.get("../index").is_err());
+
+    let index = templates.get("index").unwrap();
+    assert!(Arc::ptr_eq(&index, &templates.get("index.hat").unwrap()));
+
+    let mut env = Env::new();
+    env.set_loader(templates.clone());
+    assert_eq!(
+        "<h1><nav>Home</nav>\n</h1>\n",
+        index.render(&mut env).unwrap()
+    );
+
+    // changed files are reloaded
+    write(dir.join("partials/nav.hat"), "<nav> Away", 2);
+    assert_eq!(
+        "<h1><nav>Away</nav>\n</h1>\n",
+        templates.render("index", &mut env).unwrap()
+    );
+
+    // new files are found
+    write(dir.join("new.hat"), "<p> New", 2);
+    assert!(templates.get("new").is_ok());
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_templates_no_reload() {
+    let dir = temp_dir("no-reload");
+    write(dir.join("index.hat"), "<p> One", 1);
+
+    let mut templates = Templates::new(&dir).unwrap();
+    templates.set_reload(false);
+    write(dir.join("index.hat"), "<p> Two", 2);
+    write(dir.join("new.hat"), "<p> New", 2);
+    assert_eq!("<p> One", templates.get("index").unwrap().source());
+    assert!(templates.get("new").is_err());
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_template_render() {
+    let template = Template::from("<head> <title> title\n<body> <p> 'Hi'");
+    let mut env = Env::new();
+    for title in &["One", "Two"] {
+        env.set("title", *title);
+        assert_eq!(
+            format!(
+                "<!DOCTYPE html>\n<html><head><title>{}</title>\n</head>\n<body><p>Hi</p>\n</body>\n\n</html>\n",
+                title
+            ),
+            template.render(&mut env).unwrap()
+        );
+    }
+
+    // output from a failed render doesn't leak into the next one
+    let stmts = compile("<p> 'Before'\n<p> nope()").unwrap();
+    assert!(env.render_stmts(&stmts).is_err());
+    let stmts = compile("<p> 'After'").unwrap();
+    assert_eq!("<p>After</p>\n", env.render_stmts(&stmts).unwrap());
+}
+
+/// Remembers how big each write was.
+#[derive(Default)]
+struct Chunks(Vec<u8>, Vec<usize>);
+
+impl io::Write for Chunks {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        self.1.push(buf.len());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_streaming() {
+    let stmts = compile(
+        r#"
+<head> <title> "Big"
+<body>
+    <ul#list>
+        for i in 0..5000
+            <li.item> "Item {i}"
+"#,
+    )
+    .unwrap();
+    let expected = Env::new().render_stmts(&stmts).unwrap();
+    assert!(expected.len() > 100_000);
+
+    let mut chunks = Chunks::default();
+    Env::new().render_to_io(&stmts, &mut chunks).unwrap();
+    assert_eq!(expected, String::from_utf8(chunks.0).unwrap());
+    assert!(chunks.1.len() > 10);
+    assert!(chunks.1.iter().all(|size| *size < 9 * 1024));
+
+    let mut env = Env::new();
+    let mut out = String::new();
+    env.render_to_fmt(&stmts, &mut out).unwrap();
+    assert_eq!(expected, out);
+    assert_eq!("", env.out());
+
+    // borrowed writers work too, like a `Formatter`
+    assert_eq!(expected, Page(&stmts).to_string());
+
+    // a layout's blocks stream too
+    let loader = || {
+        let mut loader = MemoryLoader::new();
+        loader.insert(
+            "layout.hat",
+            "<head> <title> 'Big'\n<body>\n  <main> block content",
+        );
+        loader
+    };
+    let stmts = compile(
+        "extends 'layout.hat'\nblock content\n  for i in 0..5000\n    <li.item> 'Item {i}'",
+    )
+    .unwrap();
+    let mut env = Env::new();
+    env.set_loader(loader());
+    let expected = env.render_stmts(&stmts).unwrap();
+    assert!(expected.len() > 100_000);
+
+    let mut chunks = Chunks::default();
+    let mut env = Env::new();
+    env.set_loader(loader());
+    env.render_to_io(&stmts, &mut chunks).unwrap();
+    assert_eq!(expected, String::from_utf8(chunks.0).unwrap());
+    assert!(chunks.1.len() > 10);
+    assert!(chunks.1.iter().all(|size| *size < 9 * 1024));
+}
+
+/// Renders a page when it's displayed, straight into the `Formatter`.
+struct Page<'s>(&'s [Stmt]);
+
+impl fmt::Display for Page<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Env::new().render_to_fmt(self.0, f).map_err(|_| fmt::Error)
+    }
+}
@@ -0,0 +1,164 @@
+use {
+    hatter::{compile, Code, Env, Result, Value},
+    std::{fs, path::Path},
+};
+
+/// Render with the tree-walker and the VM, which should agree on
+/// everything: output and errors, down to their spans.
+fn both(source: &str) -> Result<String> {
+    let tree = compile(source).and_then(|stmts| Env::new().render_stmts(&stmts));
+    let vm = compile(source).and_then(|stmts| Env::new().render_code(&Code::new(stmts)));
+    assert_eq!(tree, vm, "{}", source);
+    vm
+}
+
+/// Evaluate with the tree-walker and the VM.
+fn eval_both(source: &str) -> Value {
+    let tree = Env::new().eval_src(source).unwrap();
+    let vm = Env::new()
+        .eval_code(&Code::new(compile(source).unwrap()))
+        .unwrap();
+    assert_eq!(tree, vm, "{}", source);
+    vm
+}
+
+fn hat_files(dir: &Path, files: &mut Vec<(String, String)>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            hat_files(&path, files);
+        } else if path.extension().is_some_and(|e| e == "hat") {
+            let source = fs::read_to_string(&path).unwrap();
+            files.push((path.display().to_string(), source));
+        }
+    }
+}
+
+#[test]
+fn test_vm_examples() {
+    let mut files = vec![];
+    hat_files(Path::new("./examples"), &mut files);
+    hat_files(Path::new("./tests/examples"), &mut files);
+    assert!(!files.is_empty());
+    for (_, source) in &files {
+        let _ = both(source);
+    }
+}
+
+#[test]
+fn test_vm_vars() {
+    assert_eq!(
+        "3",
+        both("x := 1\nif true\n  y := 2\n  x = x + y\nx\n").unwrap()
+    );
+    assert!(both("x := 1\nx := 2").is_err());
+    assert!(both("x = 2").is_err());
+    assert!(both("x := 1\nx = 'two'").is_err());
+    assert!(both("if true\n  y := 1\ny + 1").is_err());
+    assert!(both("x := x").is_err());
+    assert_eq!("<p>hi there</p>\n", both("<p> hi there").unwrap());
+    assert_eq!(
+        num(6.0),
+        eval_both("sum := 0\nfor x in [1, 2, 3]\n  sum += x\nsum")
+    );
+}
+
+#[test]
+fn test_vm_loops() {
+    let src =
+        "for i, x in [1, 2, 3, 4]\n  if x == 2\n    continue\n  if x == 4\n    break\n  <b> i";
+    assert_eq!("<b>0</b>\n<b>2</b>\n", both(src).unwrap());
+    let src = "i := 0\nwhile i < 3\n  i += 1\n  <i> i";
+    assert_eq!("<i>1</i>\n<i>2</i>\n<i>3</i>\n", both(src).unwrap());
+    let src = "for k, v in { a: 1, b: 2 }\n  <li> \"{k}{v}\"";
+    assert!(both(src).unwrap().contains("<li>b2</li>"));
+
+    // `continue` doesn't clear the loop's scope
+    assert!(both("for x in [1, 2]\n  y := x\n  continue").is_err());
+    assert!(both("break").is_err());
+    assert!(both("for x in 3\n  x").is_err());
+}
+
+#[test]
+fn test_vm_functions() {
+    let src = "def add(a, b)\n  return a + b\n  99\nadd(1, 2)";
+    assert_eq!(num(3.0), eval_both(src));
+    let src = "def greet(name, title)\n  title + ' ' + name\ngreet(title: 'Dr.', name: 'Who')";
+    assert_eq!(string("Dr. Who"), eval_both(src));
+    let src = "def total(n)\n  if n > 0\n    return n + total(n - 1)\n  0\ntotal(5)";
+    assert_eq!(num(15.0), eval_both(src));
+
+    // dynamically scoped
+    let src = "def show()\n  <b> secret\nfor secret in ['a', 'b']\n  show()";
+    assert_eq!("<b>a</b>\n<b>b</b>\n", both(src).unwrap());
+    let src = "def bump()\n  n += 1\nfor x in [1]\n  n := 1\n  bump()\n  <i> n";
+    assert_eq!("<i>2</i>\n", both(src).unwrap());
+
+    let src = "make := fn(x) fn() x * 2\ndouble := make(21)\ndouble()";
+    assert_eq!(num(42.0), eval_both(src));
+    assert!(both("def f(a)\n  a\nf(1, 2)").is_err());
+    assert!(both("def f()\n  nope\nf()").is_err());
+    assert!(both("nope()").is_err());
+    assert_eq!(boo(false), eval_both("false && nope()"));
+    assert_eq!(boo(true), eval_both("true || nope()"));
+}
+
+#[test]
+fn test_vm_tags() {
+    let src = "def <card(title)>\n  <div.card>\n    <h1> title\n    children\n<card title='Hi'>\n  <p> body";
+    assert_eq!(
+        "<div class='card'><h1>Hi</h1>\n<p>body</p>\n</div>\n",
+        both(src).unwrap()
+    );
+    assert!(both("def <x()>\n  <b>\n<x nope=1>").is_err());
+    let src = "name := 'box'\n<div#main.a.{name} data-x=3 hidden=false>\n  x := 1\n<p> x";
+    assert_eq!(
+        "<div id='main' class='a box' data-x=3></div>\n<p>1</p>\n",
+        both(src).unwrap()
+    );
+    let src = "<head>\n  <title> hi\n<p> body";
+    assert!(both(src).unwrap().starts_with("<!DOCTYPE html>"));
+}
+
+#[test]
+fn test_vm_errors() {
+    for src in [
+        "x := 1\n<p> y + x",
+        "def f(a)\n  a + nope\n<p> f(1)",
+        "def <t()>\n  <b> nope + 1\n<t>",
+        "for x in [1]\n  x := 2",
+        "def f()\n  break\nf()",
+        "return 1",
+    ] {
+        let err = both(src).unwrap_err();
+        assert!(err.pos > 0 || err.len > 0, "{} {:?}", src, err);
+    }
+}
+
+#[test]
+fn test_vm_try() {
+    // an error several calls deep unwinds back to try(), and the
+    // caller's locals, loop, and output carry on afterwards
+    let src = "def c(n)\n  n + nope\ndef b(n)\n  c(n + 1)\ndef a(n)\n  x := n * 2\n  b(x)\n\
+               for i in [1, 2, 3]\n  y := i\n  r := try(a, i)\n  <p> y\n  <i> r.err";
+    let out = both(src).unwrap();
+    assert_eq!(3, out.matches("<i>").count(), "{}", out);
+    assert!(out.starts_with("<p>1</p>\n<i>"), "{}", out);
+    assert!(out.contains("nope"), "{}", out);
+    assert!(out.contains("<p>3</p>"), "{}", out);
+    let src = "def f(n)\n  if n == 0\n    nope()\n  f(n - 1)\ntotal := 0\n\
+               for i in [1, 2]\n  try(f, 3)\n  total += i\ntotal";
+    assert_eq!(num(3.0), eval_both(src));
+}
+
+fn num(n: f64) -> Value {
+    Value::from(n)
+}
+
+fn string(s: &str) -> Value {
+    Value::from(s)
+}
+
+fn boo(b: bool) -> Value {
+    Value::Bool(b)
+}